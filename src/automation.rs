@@ -0,0 +1,683 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fade::FadeCurve,
+    history::Swap,
+    id::Id,
+    mixer::{TrackLevels, MAX_VOLUME_DB, MIN_VOLUME_DB},
+    processor::{process_inserts, Insert, Parameter},
+    track::Track,
+};
+
+const VOLUME_PARAMETER: Parameter = Parameter {
+    name: "Volume",
+    unit: "dB",
+    range: MIN_VOLUME_DB..=MAX_VOLUME_DB,
+    logarithmic: false,
+    default: 0.0,
+};
+
+const PAN_PARAMETER: Parameter = Parameter {
+    name: "Pan",
+    unit: "",
+    range: -1.0..=1.0,
+    logarithmic: false,
+    default: 0.0,
+};
+
+/// The control of a track an automation lane changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutomationTarget {
+    Volume,
+    Pan,
+    /// The parameter at `index` of the insert `insert`
+    Parameter {
+        insert: Id,
+        index: usize,
+    },
+}
+
+/// How a lane and its control affect each other during playback
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutomationMode {
+    /// The lane is ignored and the control keeps its value
+    Off,
+    /// The control follows the lane
+    #[default]
+    Read,
+    /// Like read, but the lane is written while the control is being changed
+    Touch,
+    /// Like touch, but the lane keeps being written after the control is let go, until playback stops
+    Latch,
+    /// The lane is written with the control for as long as it plays
+    Write,
+}
+
+impl AutomationMode {
+    pub const ALL: [AutomationMode; 5] = [
+        AutomationMode::Off,
+        AutomationMode::Read,
+        AutomationMode::Touch,
+        AutomationMode::Latch,
+        AutomationMode::Write,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AutomationMode::Off => "Off",
+            AutomationMode::Read => "Read",
+            AutomationMode::Touch => "Touch",
+            AutomationMode::Latch => "Latch",
+            AutomationMode::Write => "Write",
+        }
+    }
+}
+
+/// A point of an automation lane.
+///
+/// `time` is on the timeline in microseconds and `value` goes from 0 to 1 across the range of the control.
+/// `curve` is the shape of the segment to the next point
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    pub time: u64,
+    pub value: f32,
+    pub curve: FadeCurve,
+}
+
+/// The value of a control over time, as a line through breakpoints
+#[derive(Debug, Clone)]
+pub struct AutomationLane {
+    pub target: AutomationTarget,
+    pub mode: AutomationMode,
+    /// Sorted by time
+    points: Vec<Breakpoint>,
+    /// If the control is being changed. Cleared when the pointer is let go
+    touched: bool,
+    /// If a latch lane was touched since playback started
+    latched: bool,
+    /// If the lane is being written. The control is heard instead of the lane while it is
+    writing: bool,
+    /// The times written since writing started or playback jumped back
+    written: Option<Range<u64>>,
+}
+
+impl AutomationLane {
+    pub fn new(target: AutomationTarget) -> AutomationLane {
+        AutomationLane::with_points(target, AutomationMode::default(), Vec::new())
+    }
+
+    pub fn with_points(
+        target: AutomationTarget,
+        mode: AutomationMode,
+        mut points: Vec<Breakpoint>,
+    ) -> AutomationLane {
+        points.sort_by_key(|point| point.time);
+
+        AutomationLane {
+            target,
+            mode,
+            points,
+            touched: false,
+            latched: false,
+            writing: false,
+            written: None,
+        }
+    }
+
+    pub fn points(&self) -> &[Breakpoint] {
+        &self.points
+    }
+
+    /// If the lane is heard instead of the value of its control
+    pub fn playing(&self) -> bool {
+        self.mode != AutomationMode::Off && !self.writing && !self.points.is_empty()
+    }
+
+    /// If the lane is being written during playback
+    pub fn writing(&self) -> bool {
+        self.writing
+    }
+
+    /// The value at `time` in microseconds, from 0 to 1. `None` without any points.
+    ///
+    /// The lane holds the value of the first point before it and of the last point after it
+    pub fn value_at(&self, time: f64) -> Option<f32> {
+        let next = self
+            .points
+            .partition_point(|point| (point.time as f64) <= time);
+
+        let Some(previous) = next.checked_sub(1).map(|index| self.points[index]) else {
+            return self.points.first().map(|point| point.value);
+        };
+        let Some(next) = self.points.get(next) else {
+            return Some(previous.value);
+        };
+
+        let position = (time - previous.time as f64) / (next.time - previous.time) as f64;
+        Some(previous.value + (next.value - previous.value) * previous.curve.gain(position as f32))
+    }
+
+    /// Add `point`, after any points at the same time. Returns its index
+    pub fn add_point(&mut self, point: Breakpoint) -> usize {
+        let index = self
+            .points
+            .partition_point(|other| other.time <= point.time);
+        self.points.insert(index, point);
+        index
+    }
+
+    pub fn remove_point(&mut self, index: usize) {
+        if index < self.points.len() {
+            self.points.remove(index);
+        }
+    }
+
+    /// Move the point at `index`. It stays between the points around it
+    pub fn move_point(&mut self, index: usize, time: u64, value: f32) {
+        let start = index
+            .checked_sub(1)
+            .and_then(|before| self.points.get(before))
+            .map_or(0, |before| before.time);
+        let end = self
+            .points
+            .get(index + 1)
+            .map_or(u64::MAX, |after| after.time);
+
+        if let Some(point) = self.points.get_mut(index) {
+            point.time = time.clamp(start, end.max(start));
+            point.value = value.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Set the shape of the segment after the point at `index`
+    pub fn set_curve(&mut self, index: usize, curve: FadeCurve) {
+        if let Some(point) = self.points.get_mut(index) {
+            point.curve = curve;
+        }
+    }
+
+    /// Write `value` at `time` during playback, replacing the points since the last write
+    fn write(&mut self, time: u64, value: f32) {
+        let pass = match &self.written {
+            Some(pass) if pass.end <= time => pass.clone(),
+            // Writing just started, or playback jumped back to the start of the loop
+            _ => time..time,
+        };
+
+        self.points
+            .retain(|point| point.time <= pass.end || point.time > time);
+
+        match self.points.iter_mut().find(|point| point.time == time) {
+            Some(point) => point.value = value,
+            None => {
+                let index = self.add_point(Breakpoint {
+                    time,
+                    value,
+                    curve: FadeCurve::Linear,
+                });
+
+                // Points in the middle of a flat stretch written in this pass aren't needed
+                if index >= 2 {
+                    let (first, middle) = (self.points[index - 2], self.points[index - 1]);
+                    if first.time >= pass.start && first.value == value && middle.value == value {
+                        self.points.remove(index - 1);
+                    }
+                }
+            }
+        }
+
+        self.written = Some(pass.start..time);
+    }
+
+    /// Start or stop writing for the mode of the lane, and write `control` at `time` if it is writing.
+    ///
+    /// `released` is if the pointer is let go, `control` is the value of the control from 0 to 1, `None` if it was removed
+    fn update(&mut self, playing: bool, released: bool, time: u64, control: Option<f32>) {
+        self.latched =
+            playing && (self.latched || self.touched && self.mode == AutomationMode::Latch);
+        if released {
+            self.touched = false;
+        }
+
+        self.writing = playing
+            && control.is_some()
+            && match self.mode {
+                AutomationMode::Off | AutomationMode::Read => false,
+                AutomationMode::Touch => self.touched,
+                AutomationMode::Latch => self.latched,
+                AutomationMode::Write => true,
+            };
+
+        match control {
+            Some(value) if self.writing => self.write(time, value),
+            _ => self.written = None,
+        }
+    }
+}
+
+/// The time on the timeline of the frame `frame` at `sample_rate`, in microseconds
+pub fn frame_time(frame: usize, sample_rate: u32) -> f64 {
+    frame as f64 * 1_000_000.0 / sample_rate as f64
+}
+
+impl Track {
+    /// The controls of the track that can be automated, with their names
+    pub fn automation_targets(&self) -> Vec<(AutomationTarget, String)> {
+        let mut targets = vec![
+            (AutomationTarget::Volume, "Volume".to_string()),
+            (AutomationTarget::Pan, "Pan".to_string()),
+        ];
+
+        for (number, insert) in self.inserts.iter().enumerate() {
+            let processor = insert.processor();
+            let parameters = processor.parameters();
+
+            for (index, parameter) in parameters.iter().enumerate() {
+                // Equalizer bands have parameters with the same name
+                let same_name = |other: &Parameter| other.name == parameter.name;
+                let name = if parameters.iter().filter(|other| same_name(other)).count() > 1 {
+                    let occurrence = parameters[..index]
+                        .iter()
+                        .filter(|other| same_name(other))
+                        .count();
+                    format!("{} {}", parameter.name, occurrence + 1)
+                } else {
+                    parameter.name.to_string()
+                };
+
+                targets.push((
+                    AutomationTarget::Parameter {
+                        insert: insert.id,
+                        index,
+                    },
+                    format!("{}. {}: {}", number + 1, processor.kind().name(), name),
+                ));
+            }
+        }
+
+        targets
+    }
+
+    /// The range of the control `target` changes. `None` for a parameter of an insert that was removed
+    pub fn automation_parameter(&self, target: AutomationTarget) -> Option<Parameter> {
+        match target {
+            AutomationTarget::Volume => Some(VOLUME_PARAMETER),
+            AutomationTarget::Pan => Some(PAN_PARAMETER),
+            AutomationTarget::Parameter { insert, index } => {
+                let insert = self.inserts.iter().find(|other| other.id == insert)?;
+                let parameter = insert.processor().parameters().get(index).cloned();
+                parameter
+            }
+        }
+    }
+
+    /// The value of the control `target` changes, from 0 to 1
+    pub fn automation_control(&self, target: AutomationTarget) -> Option<f32> {
+        let parameter = self.automation_parameter(target)?;
+        let value = match target {
+            AutomationTarget::Volume => self.levels.volume,
+            AutomationTarget::Pan => self.levels.pan,
+            AutomationTarget::Parameter { insert, index } => {
+                let insert = self.inserts.iter().find(|other| other.id == insert)?;
+                let value = insert.processor().parameter(index);
+                value
+            }
+        };
+
+        Some(parameter.normalize(value))
+    }
+
+    /// Mark the lanes of `target` as touched, when its control is changed in the UI.
+    ///
+    /// They stay touched until the pointer is let go
+    pub fn touch_automation(&mut self, target: AutomationTarget) {
+        for lane in &mut self.automation {
+            if lane.target == target {
+                lane.touched = true;
+            }
+        }
+    }
+
+    /// Write the lanes that are writing at the playhead. Called every frame, whichever view is shown.
+    ///
+    /// Everything written between starting and stopping is undone in one step
+    pub fn update_automation(&mut self, ctx: &egui::Context) {
+        let (playing, time) = {
+            let state = self.app_state.read().unwrap();
+            (state.playing, state.transport.time())
+        };
+
+        let touched: Vec<_> = self
+            .inserts
+            .iter()
+            .flat_map(|insert| {
+                let id = insert.id;
+                insert
+                    .take_touched()
+                    .into_iter()
+                    .map(move |index| AutomationTarget::Parameter { insert: id, index })
+            })
+            .collect();
+        for target in touched {
+            self.touch_automation(target);
+        }
+
+        let released = ctx.input(|input| !input.pointer.any_down());
+        let was_writing = self.automation.iter().any(|lane| lane.writing);
+        let before = (!was_writing).then(|| self.automation.clone());
+        let controls: Vec<_> = self
+            .automation
+            .iter()
+            .map(|lane| self.automation_control(lane.target))
+            .collect();
+
+        for (lane, control) in self.automation.iter_mut().zip(controls) {
+            lane.update(playing, released, time, control);
+        }
+
+        let writing = self.automation.iter().any(|lane| lane.writing);
+        if writing && !was_writing {
+            self.automation_before = before;
+        }
+
+        if !writing {
+            if let Some(before) = self.automation_before.take() {
+                let written = std::mem::replace(&mut self.automation, before);
+                let (command, ()) = Swap::record(
+                    "Write Automation",
+                    self,
+                    |track| &mut track.automation,
+                    |track| track.automation = written,
+                );
+
+                self.app_state.write().unwrap().history.push(command);
+            }
+        }
+    }
+}
+
+/// The volume and pan lanes of a track being played, evaluated for every frame by the mixer
+pub struct LevelAutomation<'a> {
+    levels: TrackLevels,
+    volume: Option<&'a AutomationLane>,
+    pan: Option<&'a AutomationLane>,
+}
+
+impl<'a> LevelAutomation<'a> {
+    /// `None` if neither the volume nor the pan of `track` is automated
+    pub fn new(track: &'a Track) -> Option<LevelAutomation<'a>> {
+        let lane = |target| {
+            track
+                .automation
+                .iter()
+                .find(|lane| lane.target == target && lane.playing())
+        };
+
+        let volume = lane(AutomationTarget::Volume);
+        let pan = lane(AutomationTarget::Pan);
+
+        (volume.is_some() || pan.is_some()).then_some(LevelAutomation {
+            levels: track.levels,
+            volume,
+            pan,
+        })
+    }
+
+    /// The levels of the track at `time` in microseconds
+    pub fn levels_at(&self, time: f64) -> TrackLevels {
+        let mut levels = self.levels;
+
+        if let Some(value) = self.volume.and_then(|lane| lane.value_at(time)) {
+            levels.volume = VOLUME_PARAMETER.denormalize(value);
+        }
+        if let Some(value) = self.pan.and_then(|lane| lane.value_at(time)) {
+            levels.pan = PAN_PARAMETER.denormalize(value);
+        }
+
+        levels
+    }
+}
+
+/// Run `buffer` through `inserts` like `process_inserts`, with the automated parameters following their lanes.
+///
+/// `position` is the frame of the timeline the buffer starts at. Every frame is processed with the parameters
+/// at its own time, see `Insert::process_automated`
+pub fn process_automated_inserts<'a>(
+    inserts: &[Insert],
+    lanes: &[AutomationLane],
+    buffer: &mut [f32],
+    position: usize,
+    sample_rate: u32,
+    channels: u16,
+    sidechain: impl Fn(Id) -> Option<&'a [f32]>,
+) {
+    if channels == 0 || sample_rate == 0 {
+        process_inserts(inserts, buffer, sample_rate, channels, sidechain);
+        return;
+    }
+
+    for insert in inserts {
+        // The lanes being played for the parameters of the insert, with the index of their parameter
+        let automated = || {
+            lanes
+                .iter()
+                .filter(|lane| lane.playing())
+                .filter_map(|lane| match lane.target {
+                    AutomationTarget::Parameter { insert: id, index } if id == insert.id => {
+                        Some((lane, index))
+                    }
+                    _ => None,
+                })
+        };
+
+        insert.process_automated(
+            buffer,
+            insert.sidechain.and_then(&sidechain),
+            sample_rate,
+            channels,
+            |frame| {
+                let time = frame_time(position + frame, sample_rate);
+                automated().filter_map(move |(lane, index)| Some((index, lane.value_at(time)?)))
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mixer::db_to_gain,
+        processor::{Format, ProcessorKind},
+    };
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn point(time: u64, value: f32) -> Breakpoint {
+        Breakpoint {
+            time,
+            value,
+            curve: FadeCurve::Linear,
+        }
+    }
+
+    fn lane(mode: AutomationMode) -> AutomationLane {
+        AutomationLane::with_points(AutomationTarget::Volume, mode, vec![point(0, 0.5)])
+    }
+
+    fn times(lane: &AutomationLane) -> Vec<u64> {
+        lane.points().iter().map(|point| point.time).collect()
+    }
+
+    #[test]
+    fn value_at() {
+        let mut points = vec![point(4000, 0.5), point(1000, 0.0), point(2000, 1.0)];
+        points[2].curve = FadeCurve::SCurve;
+        let lane =
+            AutomationLane::with_points(AutomationTarget::Volume, AutomationMode::Read, points);
+
+        // The lane holds its first and last value outside of its points
+        assert_eq!(lane.value_at(0.0), Some(0.0));
+        assert_eq!(lane.value_at(5000.0), Some(0.5));
+
+        assert_eq!(lane.value_at(1000.0), Some(0.0));
+        assert_eq!(lane.value_at(1500.0), Some(0.5));
+        assert_eq!(lane.value_at(2000.0), Some(1.0));
+        // The segment after a point has the curve of the point
+        assert_eq!(
+            lane.value_at(2500.0),
+            Some(1.0 - 0.5 * FadeCurve::SCurve.gain(0.25))
+        );
+
+        let empty = AutomationLane::new(AutomationTarget::Pan);
+        assert_eq!(empty.value_at(0.0), None);
+    }
+
+    #[test]
+    fn write_replaces_the_points_it_passes() {
+        let points = vec![
+            point(0, 0.2),
+            point(150, 0.9),
+            point(250, 0.1),
+            point(1000, 0.3),
+        ];
+        let mut lane =
+            AutomationLane::with_points(AutomationTarget::Volume, AutomationMode::Write, points);
+
+        for time in [100, 200, 300] {
+            lane.write(time, 0.5);
+        }
+        // Only the ends of the flat stretch that was written are kept
+        assert_eq!(
+            lane.points(),
+            [
+                point(0, 0.2),
+                point(100, 0.5),
+                point(300, 0.5),
+                point(1000, 0.3)
+            ]
+        );
+
+        // Jumping back to the start of a loop starts a new pass, which keeps what's after it
+        lane.write(150, 0.7);
+        lane.write(250, 0.8);
+        assert_eq!(times(&lane), [0, 100, 150, 250, 300, 1000]);
+
+        lane.write(250, 0.6);
+        assert_eq!(lane.points()[3], point(250, 0.6));
+    }
+
+    #[test]
+    fn modes_write_only_while_playing() {
+        for mode in AutomationMode::ALL {
+            let mut lane = lane(mode);
+
+            lane.update(false, false, 100, Some(0.8));
+            assert!(!lane.writing());
+
+            lane.update(true, false, 200, Some(0.8));
+            assert_eq!(lane.writing(), mode == AutomationMode::Write, "{mode:?}");
+            assert_eq!(
+                lane.playing(),
+                mode != AutomationMode::Off && !lane.writing()
+            );
+
+            // The control was removed
+            lane.update(true, false, 300, None);
+            assert!(!lane.writing());
+        }
+    }
+
+    #[test]
+    fn touch_writes_while_held() {
+        let mut lane = lane(AutomationMode::Touch);
+
+        lane.touched = true;
+        lane.update(true, false, 100, Some(0.8));
+        assert!(lane.writing());
+        lane.update(true, false, 200, Some(0.9));
+        assert!(lane.writing());
+
+        lane.update(true, true, 300, Some(0.9));
+        assert!(!lane.writing());
+        assert!(lane.playing());
+        assert_eq!(times(&lane), [0, 100, 200]);
+
+        // A click that is let go in the same frame doesn't write
+        lane.touched = true;
+        lane.update(true, true, 400, Some(0.1));
+        assert!(!lane.writing());
+    }
+
+    #[test]
+    fn latch_writes_until_stopped() {
+        let mut lane = lane(AutomationMode::Latch);
+
+        lane.touched = true;
+        lane.update(true, true, 100, Some(0.8));
+        assert!(lane.writing());
+        lane.update(true, true, 200, Some(0.8));
+        assert!(lane.writing());
+
+        lane.update(false, true, 300, Some(0.8));
+        assert!(!lane.writing());
+
+        // The latch doesn't carry over to the next time playback starts
+        lane.update(true, true, 400, Some(0.8));
+        assert!(!lane.writing());
+    }
+
+    #[test]
+    fn parameters_follow_lanes_on_every_frame() {
+        const FRAMES: usize = 480;
+        const POSITION: usize = 100;
+
+        let insert = Insert::new(ProcessorKind::Gain.create());
+        insert.prepare(Format {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            max_block_size: FRAMES,
+        });
+        let parameter = insert.processor().parameters()[0].clone();
+
+        // A ramp, then a jump between two frames
+        let target = AutomationTarget::Parameter {
+            insert: insert.id,
+            index: 0,
+        };
+        let points = vec![
+            point(0, 0.0),
+            point(5000, 1.0),
+            point(7510, 1.0),
+            point(7510, 0.25),
+        ];
+        let lanes = [AutomationLane::with_points(
+            target,
+            AutomationMode::Read,
+            points,
+        )];
+
+        let mut buffer = vec![1.0; FRAMES];
+        process_automated_inserts(
+            &[insert],
+            &lanes,
+            &mut buffer,
+            POSITION,
+            SAMPLE_RATE,
+            1,
+            |_| None,
+        );
+
+        for (frame, sample) in buffer.iter().enumerate() {
+            let value = lanes[0].value_at(frame_time(POSITION + frame, SAMPLE_RATE));
+            let expected = db_to_gain(parameter.denormalize(value.unwrap()));
+
+            assert!(
+                (sample - expected).abs() < 1e-6,
+                "{frame}: {sample} {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    channel::Layout,
+    playback::{PlaybackEngine, DEFAULT_BUFFER_SIZE},
+    settings::OutputSettings,
+    state::State,
+    track::Track,
+    wave_file::{self, BitDepth},
+};
+
+/// Which kind of output the playback engine is pulled by
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum BackendKind {
+    /// An audio device
+    #[default]
+    Device,
+    /// Nothing is played. Buffers are pulled on a simulated clock
+    Null,
+    /// Like `Null`, but everything that would have been played is written to a wave file
+    File { path: PathBuf },
+}
+
+impl BackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Device => "Device",
+            BackendKind::Null => "Null",
+            BackendKind::File { .. } => "File",
+        }
+    }
+}
+
+/// Something that pulls audio from a `PlaybackEngine` and plays it.
+///
+/// Backends start paused
+pub trait OutputBackend {
+    fn play(&mut self);
+
+    fn pause(&mut self);
+
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> u16;
+
+    /// Turn dither of integer output on or off while running. Backends that only output floats ignore it
+    fn set_dither(&mut self, _dither: bool) {}
+}
+
+/// Plays through an audio device with cpal
+pub struct CpalBackend {
+    stream: cpal::Stream,
+    sample_rate: u32,
+    channels: u16,
+    /// Read by the stream every buffer
+    dither: Arc<AtomicBool>,
+}
+
+impl CpalBackend {
+    /// Open a stream on `device` with the configuration that best matches `settings`
+    pub fn new(
+        device: &cpal::Device,
+        settings: &OutputSettings,
+        tracks: Vec<Arc<RwLock<Track>>>,
+        state: Arc<RwLock<State>>,
+    ) -> Result<CpalBackend, cpal::BuildStreamError> {
+        let supported_config = settings
+            .choose_config(device)
+            .ok_or(cpal::BuildStreamError::StreamConfigNotSupported)?;
+
+        let mut config = supported_config.config();
+        config.buffer_size = settings.buffer_size(&supported_config);
+
+        info!("Device: {}", device.name().unwrap_or_default());
+        info!("Channel Count: {}", supported_config.channels());
+        info!("Sample Rate: {}", supported_config.sample_rate().0);
+        info!("Buffer Size: {:?}", config.buffer_size);
+        info!("Sample Format: {}", supported_config.sample_format());
+
+        let buffer_size = match (config.buffer_size, supported_config.buffer_size()) {
+            (cpal::BufferSize::Fixed(size), _) => size as usize,
+            (_, cpal::SupportedBufferSize::Range { max, .. }) => *max as usize,
+            _ => DEFAULT_BUFFER_SIZE,
+        };
+
+        let engine = PlaybackEngine::new(
+            tracks,
+            state,
+            config.sample_rate.0,
+            config.channels,
+            buffer_size,
+        );
+        let dither = Arc::new(AtomicBool::new(settings.dither));
+        let flag = dither.clone();
+
+        // Mixing always happens in f32, the result is converted to the format of the device
+        let stream = match supported_config.sample_format() {
+            SampleFormat::I8 => build_output_stream::<i8>(device, &config, engine, flag),
+            SampleFormat::I16 => build_output_stream::<i16>(device, &config, engine, flag),
+            SampleFormat::I32 => build_output_stream::<i32>(device, &config, engine, flag),
+            SampleFormat::I64 => build_output_stream::<i64>(device, &config, engine, flag),
+            SampleFormat::U8 => build_output_stream::<u8>(device, &config, engine, flag),
+            SampleFormat::U16 => build_output_stream::<u16>(device, &config, engine, flag),
+            SampleFormat::U32 => build_output_stream::<u32>(device, &config, engine, flag),
+            SampleFormat::U64 => build_output_stream::<u64>(device, &config, engine, flag),
+            SampleFormat::F32 => build_output_stream::<f32>(device, &config, engine, flag),
+            SampleFormat::F64 => build_output_stream::<f64>(device, &config, engine, flag),
+            _ => Err(cpal::BuildStreamError::StreamConfigNotSupported),
+        }?;
+
+        let mut backend = CpalBackend {
+            stream,
+            sample_rate: config.sample_rate.0,
+            channels: config.channels,
+            dither,
+        };
+        backend.pause();
+
+        Ok(backend)
+    }
+}
+
+impl OutputBackend for CpalBackend {
+    fn play(&mut self) {
+        if let Err(e) = self.stream.play() {
+            error!("Unable to start output stream: {e}");
+        }
+    }
+
+    fn pause(&mut self) {
+        if let Err(e) = self.stream.pause() {
+            error!("Unable to pause output stream: {e}");
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn set_dither(&mut self, dither: bool) {
+        self.dither.store(dither, Ordering::Relaxed);
+    }
+}
+
+/// Build an output stream for devices with sample type `T`.
+///
+/// The engine fills a buffer of interleaved f32 samples which is then clipped and converted to `T`.
+/// While `dither` is set, triangular (TPDF) dither of +-1 LSB is added for integer formats of 16 bits or less
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut engine: PlaybackEngine,
+    dither: Arc<AtomicBool>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    // Formats with more bits are already below the precision of f32
+    let bits = T::FORMAT.sample_size() * 8;
+    let dither_lsb = if !T::FORMAT.is_float() && bits <= 16 {
+        1.0 / (1u32 << (bits - 1)) as f32
+    } else {
+        0.0
+    };
+
+    let mut buffer = Vec::new();
+
+    device.build_output_stream(
+        config,
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            // Only allocates when the device asks for a larger buffer than before
+            buffer.resize(output.len(), 0.0);
+            engine.process(&mut buffer);
+
+            let lsb = if dither.load(Ordering::Relaxed) {
+                dither_lsb
+            } else {
+                0.0
+            };
+            for (output, value) in output.iter_mut().zip(&buffer) {
+                *output = convert_sample(*value, lsb);
+            }
+        },
+        |err| error!("Output stream error: {err}"),
+        None,
+    )
+}
+
+/// Clip a normalized sample to -1..1 and convert it to `T`
+///
+/// `lsb` is the size of the dither noise. No dither is applied if it is zero
+fn convert_sample<T: FromSample<f32>>(value: f32, lsb: f32) -> T {
+    let noise = if lsb > 0.0 {
+        (rand::random::<f32>() - rand::random::<f32>()) * lsb
+    } else {
+        0.0
+    };
+
+    T::from_sample_((value + noise).clamp(-1.0, 1.0))
+}
+
+/// Pulls buffers from the engine without playing them.
+///
+/// While playing, a buffer is pulled every time one would have finished playing on a device.
+/// `render` pulls buffers immediately, which makes the output deterministic
+pub struct NullBackend {
+    engine: Arc<Mutex<PlaybackEngine>>,
+    buffer_size: usize,
+
+    /// Every sample pulled so far, if it's being kept
+    recording: Option<Arc<Mutex<Vec<f32>>>>,
+
+    clock: Option<(JoinHandle<()>, Arc<AtomicBool>)>,
+}
+
+impl NullBackend {
+    /// Pull buffers of `buffer_size` frames from `engine`
+    pub fn new(engine: PlaybackEngine, buffer_size: usize) -> NullBackend {
+        NullBackend {
+            engine: Arc::new(Mutex::new(engine)),
+            buffer_size: buffer_size.max(1),
+            recording: None,
+            clock: None,
+        }
+    }
+
+    /// Keep every pulled sample
+    fn recorded(mut self) -> NullBackend {
+        self.recording = Some(Arc::new(Mutex::new(Vec::new())));
+        self
+    }
+
+    /// Pull `frames` frames from the engine in buffers of the backend's buffer size.
+    ///
+    /// Resampling is finished first so nothing is missing from the output.
+    /// The last buffer is shorter if `frames` isn't a multiple of the buffer size
+    #[cfg(test)]
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        self.engine.lock().unwrap().finish_resampling();
+
+        let channels = self.channels() as usize;
+        let mut output = vec![0.0; frames * channels];
+
+        for buffer in output.chunks_mut(self.buffer_size * channels) {
+            pull(&self.engine, buffer, &self.recording);
+        }
+
+        output
+    }
+
+    /// The duration of one buffer
+    fn period(&self) -> Duration {
+        Duration::from_secs_f64(self.buffer_size as f64 / self.sample_rate() as f64)
+    }
+}
+
+impl OutputBackend for NullBackend {
+    fn play(&mut self) {
+        if self.clock.is_some() {
+            return;
+        }
+
+        let running = Arc::new(AtomicBool::new(true));
+        let period = self.period();
+        let engine = self.engine.clone();
+        let recording = self.recording.clone();
+        let mut buffer = vec![0.0; self.buffer_size * self.channels() as usize];
+
+        let thread = {
+            let running = running.clone();
+
+            std::thread::spawn(move || {
+                let mut next = Instant::now();
+
+                while running.load(Ordering::Acquire) {
+                    pull(&engine, &mut buffer, &recording);
+
+                    next += period;
+                    std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                }
+            })
+        };
+
+        self.clock = Some((thread, running));
+    }
+
+    fn pause(&mut self) {
+        if let Some((thread, running)) = self.clock.take() {
+            running.store(false, Ordering::Release);
+            let _ = thread.join();
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.engine.lock().unwrap().sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.engine.lock().unwrap().channels()
+    }
+}
+
+impl Drop for NullBackend {
+    fn drop(&mut self) {
+        self.pause();
+    }
+}
+
+/// Fill `buffer` from the engine and keep a copy in `recording`
+fn pull(
+    engine: &Mutex<PlaybackEngine>,
+    buffer: &mut [f32],
+    recording: &Option<Arc<Mutex<Vec<f32>>>>,
+) {
+    engine.lock().unwrap().process(buffer);
+
+    if let Some(recording) = recording {
+        recording.lock().unwrap().extend_from_slice(buffer);
+    }
+}
+
+/// Pulls buffers like `NullBackend` and writes everything that would have been played to a wave file.
+///
+/// The file is written as 32 bit float whenever playback is paused and when the backend is dropped
+pub struct FileBackend {
+    null: NullBackend,
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(engine: PlaybackEngine, buffer_size: usize, path: impl AsRef<Path>) -> FileBackend {
+        FileBackend {
+            null: NullBackend::new(engine, buffer_size).recorded(),
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Pull `frames` frames from the engine immediately. See `NullBackend::render`
+    #[cfg(test)]
+    pub fn render(&mut self, frames: usize) -> Vec<f32> {
+        self.null.render(frames)
+    }
+
+    /// Write everything pulled so far to the file
+    pub fn write(&self) -> io::Result<()> {
+        let Some(recording) = &self.null.recording else {
+            return Ok(());
+        };
+
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        wave_file::write(
+            &mut file,
+            self.sample_rate(),
+            self.channels(),
+            Layout::from_channel_count(self.channels()),
+            BitDepth::ThirtyTwoFloat,
+            &recording.lock().unwrap(),
+            false,
+        )?;
+
+        info!("Wrote output to {}", self.path.display());
+
+        Ok(())
+    }
+}
+
+impl OutputBackend for FileBackend {
+    fn play(&mut self) {
+        self.null.play();
+    }
+
+    fn pause(&mut self) {
+        self.null.pause();
+
+        if let Err(e) = self.write() {
+            error!("Unable to write output to {}: {e}", self.path.display());
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.null.sample_rate()
+    }
+
+    fn channels(&self) -> u16 {
+        self.null.channels()
+    }
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        self.pause();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clip::Clip,
+        processor::{Insert, ProcessorKind},
+        render,
+        sample::Sample,
+        util::sine,
+    };
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A stereo tone at the start of the timeline and a mono tone that starts part way through
+    fn session() -> (Vec<Arc<RwLock<Track>>>, Arc<RwLock<State>>) {
+        let state = Arc::new(RwLock::new(State::new(egui::Context::default(), None)));
+
+        let stereo = Arc::new(Sample::from_data(
+            sine(440.0, 0.5, SAMPLE_RATE, 9000, 2),
+            SAMPLE_RATE,
+            2,
+        ));
+        let mono = Arc::new(Sample::from_data(
+            sine(1000.0, 0.25, SAMPLE_RATE, 7000, 1),
+            SAMPLE_RATE,
+            1,
+        ));
+
+        let tracks = vec![
+            Track::new("Stereo", vec![Clip::new(stereo, 0)], state.clone()),
+            Track::new("Mono", vec![Clip::new(mono, 62_500)], state.clone()),
+        ];
+
+        (
+            tracks
+                .into_iter()
+                .map(|track| Arc::new(RwLock::new(track)))
+                .collect(),
+            state,
+        )
+    }
+
+    /// Render the whole session through a null backend, with some silence after it
+    fn play(sample_rate: u32, buffer_size: usize, frames: usize) -> Vec<f32> {
+        let (tracks, state) = session();
+        let engine = PlaybackEngine::new(tracks, state, sample_rate, 2, buffer_size);
+
+        NullBackend::new(engine, buffer_size).render(frames)
+    }
+
+    /// The offline render of the same session
+    fn bounce(sample_rate: u32) -> Vec<f32> {
+        let (tracks, state) = session();
+        let buses = state.read().unwrap().buses.clone();
+        let settings = render::RenderSettings {
+            sample_rate,
+            channels: 2,
+            ..Default::default()
+        };
+
+        let session = render::RenderSession::new(&tracks, &buses.read().unwrap());
+        render::render(&session, &settings)
+    }
+
+    #[test]
+    fn null_backend_matches_render() {
+        for sample_rate in [44100, 48000, 96000] {
+            let expected = bounce(sample_rate);
+            assert!(expected.iter().any(|sample| *sample != 0.0));
+
+            let frames = expected.len() / 2 + 500;
+            for buffer_size in [1, 64, 1024, 333] {
+                let output = play(sample_rate, buffer_size, frames);
+
+                assert!(
+                    output[..expected.len()] == expected[..],
+                    "{sample_rate} Hz in buffers of {buffer_size} frames doesn't match the render",
+                );
+                assert!(output[expected.len()..].iter().all(|sample| *sample == 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn render_leaves_playback_alone() {
+        // The session with a limiter on the first track, which remembers what it processed
+        let limited = || {
+            let (tracks, state) = session();
+            let limiter = Insert::new(ProcessorKind::Limiter.create());
+            limiter.processor().set_parameter(0, 12.0);
+            tracks[0].write().unwrap().inserts.push(limiter);
+
+            let buses = state.read().unwrap().buses.clone();
+            (tracks, state, buses)
+        };
+        let settings = render::RenderSettings {
+            sample_rate: 44100,
+            channels: 2,
+            ..Default::default()
+        };
+
+        let (tracks, state, buses) = limited();
+        let engine = PlaybackEngine::new(tracks.clone(), state, SAMPLE_RATE, 2, 256);
+        let mut backend = NullBackend::new(engine, 256);
+
+        let mut played = backend.render(4096);
+        let session = render::RenderSession::new(&tracks, &buses.read().unwrap());
+        let rendered = render::render(&session, &settings);
+        played.extend(backend.render(6144));
+
+        let (tracks, state, buses) = limited();
+        let session = render::RenderSession::new(&tracks, &buses.read().unwrap());
+        assert!(render::render(&session, &settings) == rendered);
+
+        let engine = PlaybackEngine::new(tracks, state, SAMPLE_RATE, 2, 256);
+        assert!(NullBackend::new(engine, 256).render(10240) == played);
+    }
+
+    #[test]
+    fn file_backend_writes_what_was_played() {
+        let path = std::env::temp_dir().join(format!(
+            "audio_editor_file_backend_{}.wav",
+            std::process::id()
+        ));
+
+        let (tracks, state) = session();
+        let engine = PlaybackEngine::new(tracks, state, SAMPLE_RATE, 2, 256);
+        let mut backend = FileBackend::new(engine, 256, &path);
+
+        let mut played = backend.render(5000);
+        played.extend(backend.render(5000));
+        backend.write().unwrap();
+
+        let file = wave_file::read(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(file.header.sampling_rate, SAMPLE_RATE);
+        assert_eq!(file.header.channel_count, 2);
+        assert_eq!(file.bit_depth, BitDepth::ThirtyTwoFloat);
+        assert_eq!(file.data, played);
+    }
+}
@@ -0,0 +1,616 @@
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::{
+    channel::{ChannelMapping, Layout, Speakers},
+    id::{get_id_mgr, Id},
+    meter::Meter,
+    mixer::{db_to_gain, format_volume, TrackLevels, MAX_VOLUME_DB, MIN_VOLUME_DB},
+    processor::{insert_chain_ui, Insert},
+};
+
+/// Where the signal of a track or bus goes after its levels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Master,
+    Bus(Id),
+}
+
+/// A copy of the signal of a track or bus going to an aux bus
+///
+/// `level` is in decibels and silent at `MIN_VOLUME_DB`
+/// `pre_fader` sends take the signal before the volume and pan are applied. Muting mutes every send
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuxSend {
+    pub bus: Id,
+    pub level: f32,
+    pub pre_fader: bool,
+}
+
+impl AuxSend {
+    pub fn new(bus: Id) -> AuxSend {
+        AuxSend {
+            bus,
+            level: 0.0,
+            pre_fader: false,
+        }
+    }
+
+    /// The linear gain of the send
+    pub fn gain(&self) -> f32 {
+        if self.level <= MIN_VOLUME_DB {
+            0.0
+        } else {
+            db_to_gain(self.level)
+        }
+    }
+}
+
+/// An aux bus that tracks and other buses are mixed into
+///
+/// Changes to `output` and `sends` have to go through `Buses`, so they can't make a cycle
+pub struct Bus {
+    pub id: Id,
+    pub name: String,
+    /// Volume, pan and mute. Buses can't be soloed
+    pub levels: TrackLevels,
+    pub inserts: Vec<Insert>,
+    /// The speakers each channel of the bus is sent to. `None` passes every channel through
+    pub mapping: Option<ChannelMapping>,
+    output: Output,
+    sends: Vec<AuxSend>,
+    /// The levels of the bus, measured by the playback engine
+    pub meter: Arc<Meter>,
+}
+
+impl Bus {
+    pub fn new(name: impl ToString) -> Bus {
+        Bus {
+            id: get_id_mgr().gen_id(),
+            name: name.to_string(),
+            levels: TrackLevels::default(),
+            inserts: Vec::new(),
+            mapping: None,
+            output: Output::Master,
+            sends: Vec::new(),
+            meter: Arc::default(),
+        }
+    }
+
+    pub fn output(&self) -> Output {
+        self.output
+    }
+
+    pub fn sends(&self) -> &[AuxSend] {
+        &self.sends
+    }
+
+    /// The level and pre-fader setting of the sends can be changed freely, their buses can't
+    pub fn send_mut(&mut self, index: usize) -> Option<(&mut f32, &mut bool)> {
+        self.sends
+            .get_mut(index)
+            .map(|send| (&mut send.level, &mut send.pre_fader))
+    }
+
+    /// The buses this bus feeds directly
+    fn targets(&self) -> impl Iterator<Item = Id> + '_ {
+        let output = match self.output {
+            Output::Master => None,
+            Output::Bus(bus) => Some(bus),
+        };
+
+        output
+            .into_iter()
+            .chain(self.sends.iter().map(|send| send.bus))
+    }
+}
+
+/// The bus everything ends up in before the output
+///
+/// `volume` is in decibels and silent at `MIN_VOLUME_DB`
+#[derive(Default)]
+pub struct MasterBus {
+    pub volume: f32,
+    pub inserts: Vec<Insert>,
+}
+
+impl MasterBus {
+    /// The linear gain of the master
+    pub fn gain(&self) -> f32 {
+        if self.volume <= MIN_VOLUME_DB {
+            0.0
+        } else {
+            db_to_gain(self.volume)
+        }
+    }
+}
+
+/// The aux buses of a session and the master bus.
+///
+/// Buses feed each other through their outputs and sends, which is never allowed to make a cycle
+#[derive(Default)]
+pub struct Buses {
+    pub master: MasterBus,
+    buses: Vec<Bus>,
+    /// Indices of `buses` where every bus comes before the buses it feeds
+    order: Vec<usize>,
+}
+
+impl Buses {
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    pub fn index(&self, id: Id) -> Option<usize> {
+        self.buses.iter().position(|bus| bus.id == id)
+    }
+
+    pub fn bus(&self, id: Id) -> Option<&Bus> {
+        self.buses.iter().find(|bus| bus.id == id)
+    }
+
+    pub fn bus_mut(&mut self, id: Id) -> Option<&mut Bus> {
+        self.buses.iter_mut().find(|bus| bus.id == id)
+    }
+
+    /// The order buses are mixed in, as indices of `buses`
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// A copy of the buses to render offline. The inserts get processors of their own
+    pub fn render_copy(&self) -> Buses {
+        let inserts = |inserts: &[Insert]| inserts.iter().map(Insert::duplicate).collect();
+
+        Buses {
+            master: MasterBus {
+                volume: self.master.volume,
+                inserts: inserts(&self.master.inserts),
+            },
+            buses: self
+                .buses
+                .iter()
+                .map(|bus| Bus {
+                    id: bus.id,
+                    name: bus.name.clone(),
+                    levels: bus.levels,
+                    inserts: inserts(&bus.inserts),
+                    mapping: bus.mapping,
+                    output: bus.output,
+                    sends: bus.sends.clone(),
+                    meter: Arc::default(),
+                })
+                .collect(),
+            order: self.order.clone(),
+        }
+    }
+
+    /// The ids and names of the buses, to pick from in the UI
+    pub fn names(&self) -> Vec<(Id, String)> {
+        self.buses
+            .iter()
+            .map(|bus| (bus.id, bus.name.clone()))
+            .collect()
+    }
+
+    /// Add a bus with its output and sends cleared
+    pub fn add(&mut self, mut bus: Bus) -> Id {
+        let id = bus.id;
+        bus.output = Output::Master;
+        bus.sends.clear();
+
+        self.buses.push(bus);
+        self.update_order();
+
+        id
+    }
+
+    /// Remove a bus. Buses that went to it go to the master instead and their sends to it are removed
+    pub fn remove(&mut self, id: Id) {
+        self.buses.retain(|bus| bus.id != id);
+
+        for bus in &mut self.buses {
+            if bus.output == Output::Bus(id) {
+                bus.output = Output::Master;
+            }
+            bus.sends.retain(|send| send.bus != id);
+        }
+
+        self.update_order();
+    }
+
+    /// If `bus` feeds `target`, directly or through other buses
+    pub fn feeds(&self, bus: Id, target: Id) -> bool {
+        let mut visited = Vec::new();
+        let mut pending = vec![bus];
+
+        while let Some(bus) = pending.pop() {
+            if visited.contains(&bus) {
+                continue;
+            }
+            visited.push(bus);
+
+            for next in self.bus(bus).into_iter().flat_map(Bus::targets) {
+                if next == target {
+                    return true;
+                }
+                pending.push(next);
+            }
+        }
+
+        false
+    }
+
+    /// If feeding `to` from `from` would make a bus feed itself
+    pub fn would_cycle(&self, from: Id, to: Id) -> bool {
+        from == to || self.feeds(to, from)
+    }
+
+    /// Send the output of `bus` to `output`.
+    ///
+    /// Returns `false` and leaves the output alone if it would make a cycle
+    pub fn set_output(&mut self, bus: Id, output: Output) -> bool {
+        if let Output::Bus(target) = output {
+            if self.would_cycle(bus, target) {
+                return false;
+            }
+        }
+
+        let Some(bus) = self.bus_mut(bus) else {
+            return false;
+        };
+        bus.output = output;
+        self.update_order();
+
+        true
+    }
+
+    /// Add a send from `bus`.
+    ///
+    /// Returns `false` and doesn't add it if it would make a cycle
+    pub fn add_send(&mut self, bus: Id, send: AuxSend) -> bool {
+        if self.would_cycle(bus, send.bus) {
+            return false;
+        }
+
+        let Some(bus) = self.bus_mut(bus) else {
+            return false;
+        };
+        bus.sends.push(send);
+        self.update_order();
+
+        true
+    }
+
+    pub fn remove_send(&mut self, bus: Id, index: usize) {
+        let Some(bus) = self.bus_mut(bus) else {
+            return;
+        };
+
+        if index < bus.sends.len() {
+            bus.sends.remove(index);
+            self.update_order();
+        }
+    }
+
+    /// Sort the buses so every bus comes before the buses it feeds
+    fn update_order(&mut self) {
+        let mut inputs = vec![0; self.buses.len()];
+        for bus in &self.buses {
+            for target in bus.targets().filter_map(|target| self.index(target)) {
+                inputs[target] += 1;
+            }
+        }
+
+        self.order.clear();
+        let mut ready: Vec<usize> = (0..self.buses.len())
+            .filter(|index| inputs[*index] == 0)
+            .collect();
+
+        while let Some(index) = ready.pop() {
+            self.order.push(index);
+
+            for target in self.buses[index]
+                .targets()
+                .filter_map(|target| self.index(target))
+            {
+                inputs[target] -= 1;
+                if inputs[target] == 0 {
+                    ready.push(target);
+                }
+            }
+        }
+
+        // Only possible if the buses were built with a cycle. What reaches a bus after it was mixed is lost
+        if self.order.len() < self.buses.len() {
+            warn!("Buses feed each other in a cycle");
+
+            for index in 0..self.buses.len() {
+                if !self.order.contains(&index) {
+                    self.order.push(index);
+                }
+            }
+        }
+    }
+}
+
+impl Buses {
+    /// Draw the editor of the master and every bus.
+    ///
+    /// `channels` is the channel count of the output and `tracks` are the tracks inserts can be keyed from.
+    /// Returns the bus that was removed, so tracks going to it can be sent elsewhere
+    pub fn ui(&mut self, ui: &mut egui::Ui, channels: u16, tracks: &[(Id, String)]) -> Option<Id> {
+        ui.heading("Master");
+        ui.add(
+            egui::Slider::new(&mut self.master.volume, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                .text("Volume")
+                .suffix(" dB")
+                .custom_formatter(|volume, _| format_volume(volume)),
+        );
+        ui.collapsing("Inserts", |ui| {
+            insert_chain_ui(ui, ui.id(), &mut self.master.inserts, tracks);
+        });
+
+        let mut removed = None;
+
+        for id in self.buses.iter().map(|bus| bus.id).collect::<Vec<_>>() {
+            ui.separator();
+            ui.push_id(id, |ui| {
+                if self.bus_ui(ui, id, channels, tracks) {
+                    removed = Some(id);
+                }
+            });
+        }
+
+        ui.separator();
+        if ui.button("Add Bus").clicked() {
+            self.add(Bus::new(format!("Bus {}", self.buses.len() + 1)));
+        }
+
+        if let Some(id) = removed {
+            self.remove(id);
+        }
+
+        removed
+    }
+
+    /// Draw the editor of the bus with `id`. Returns `true` if it should be removed
+    fn bus_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        id: Id,
+        channels: u16,
+        tracks: &[(Id, String)],
+    ) -> bool {
+        let names = self.names();
+        let bus_name = |output: Output| match output {
+            Output::Master => "Master",
+            Output::Bus(bus) => names
+                .iter()
+                .find(|(id, _)| *id == bus)
+                .map_or("Missing", |(_, name)| name.as_str()),
+        };
+        // Where the bus can go without feeding itself
+        let targets: Vec<_> = names
+            .iter()
+            .map(|(bus, _)| *bus)
+            .filter(|bus| !self.would_cycle(id, *bus))
+            .collect();
+
+        let Some(bus) = self.bus_mut(id) else {
+            return false;
+        };
+        let mut remove = false;
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut bus.name);
+            ui.toggle_value(&mut bus.levels.mute, "M")
+                .on_hover_text("Mute");
+            if ui.button("Remove").clicked() {
+                remove = true;
+            }
+        });
+
+        ui.add(
+            egui::Slider::new(&mut bus.levels.volume, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                .text("Volume")
+                .suffix(" dB")
+                .custom_formatter(|volume, _| format_volume(volume)),
+        );
+        ui.add(egui::Slider::new(&mut bus.levels.pan, -1.0..=1.0).text("Pan"));
+
+        let mut output = bus.output;
+        egui::ComboBox::from_label("Output")
+            .selected_text(bus_name(output))
+            .show_ui(ui, |ui| {
+                let buses = targets.iter().map(|bus| Output::Bus(*bus));
+
+                for target in std::iter::once(Output::Master).chain(buses) {
+                    ui.selectable_value(&mut output, target, bus_name(target));
+                }
+            });
+
+        ui.label("Sends");
+
+        // The send that was removed
+        let mut removed = None;
+
+        for (index, send) in bus.sends.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::Slider::new(&mut send.level, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                        .text(bus_name(Output::Bus(send.bus)))
+                        .suffix(" dB")
+                        .custom_formatter(|level, _| format_volume(level)),
+                );
+                ui.checkbox(&mut send.pre_fader, "Pre")
+                    .on_hover_text("Send before the volume and pan");
+
+                if ui.button("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+
+        let mut added = None;
+        egui::ComboBox::from_id_source("add-send")
+            .selected_text("Add Send")
+            .show_ui(ui, |ui| {
+                for target in &targets {
+                    if ui
+                        .selectable_label(false, bus_name(Output::Bus(*target)))
+                        .clicked()
+                    {
+                        added = Some(*target);
+                    }
+                }
+            });
+
+        ui.collapsing("Inserts", |ui| {
+            insert_chain_ui(ui, ui.id(), &mut bus.inserts, tracks);
+        });
+
+        ui.collapsing("Channel Mapping", |ui| {
+            mapping_ui(ui, &mut bus.mapping, channels);
+        });
+
+        if let Some(index) = removed {
+            self.remove_send(id, index);
+        }
+        if let Some(target) = added {
+            self.add_send(id, AuxSend::new(target));
+        }
+        if output != self.bus(id).map_or(output, Bus::output) {
+            self.set_output(id, output);
+        }
+
+        remove
+    }
+}
+
+/// Draw the speakers each channel of a bus goes to. `None` passes every channel through
+fn mapping_ui(ui: &mut egui::Ui, mapping: &mut Option<ChannelMapping>, channels: u16) {
+    let channels = channels.min(Speakers::MAX_COUNT as u16);
+    let layout = Layout::from_channel_count(channels);
+
+    let mut custom = mapping.is_some();
+    if ui
+        .checkbox(&mut custom, "Custom")
+        .on_hover_text("Send each channel to the speakers checked below")
+        .changed()
+    {
+        *mapping = custom.then(|| ChannelMapping::identity(channels));
+    }
+
+    let Some(mapping) = mapping else {
+        return;
+    };
+
+    egui::Grid::new("mapping-grid").show(ui, |ui| {
+        ui.label("");
+        for output in 0..channels {
+            ui.label(Speakers::from(output).short_name());
+        }
+        ui.end_row();
+
+        for input in 0..channels {
+            match Layout::channel_speaker(layout, input as usize) {
+                Some(speaker) => ui.label(format!("{} ({})", input + 1, speaker.short_name())),
+                None => ui.label(format!("{}", input + 1)),
+            };
+
+            for output in 0..channels {
+                let speaker = Speakers::from(output);
+                let speakers = mapping[input].unwrap_or(Speakers::empty());
+
+                let mut checked = speakers.contains(speaker);
+                if ui.checkbox(&mut checked, "").changed() {
+                    let speakers = speakers ^ speaker;
+                    mapping[input] = (!speakers.is_empty()).then_some(speakers);
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three buses, all going to the master
+    fn buses() -> (Buses, [Id; 3]) {
+        let mut buses = Buses::default();
+        let ids = ["A", "B", "C"].map(|name| buses.add(Bus::new(name)));
+
+        (buses, ids)
+    }
+
+    /// Where in the mixing order the bus with `id` is
+    fn position(buses: &Buses, id: Id) -> usize {
+        let index = buses.index(id).unwrap();
+        buses.order().iter().position(|i| *i == index).unwrap()
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let (mut buses, [a, b, c]) = buses();
+
+        assert!(!buses.set_output(a, Output::Bus(a)));
+        assert!(!buses.add_send(a, AuxSend::new(a)));
+
+        assert!(buses.set_output(a, Output::Bus(b)));
+        assert!(!buses.set_output(b, Output::Bus(a)));
+        assert!(!buses.add_send(b, AuxSend::new(a)));
+        assert_eq!(buses.bus(b).unwrap().output(), Output::Master);
+        assert!(buses.bus(b).unwrap().sends().is_empty());
+
+        // Through a send and another bus
+        assert!(buses.add_send(b, AuxSend::new(c)));
+        assert!(!buses.set_output(c, Output::Bus(a)));
+        assert!(!buses.add_send(c, AuxSend::new(a)));
+        assert!(buses.feeds(a, c));
+        assert!(!buses.feeds(c, a));
+    }
+
+    #[test]
+    fn feeders_are_mixed_first() {
+        let (mut buses, [a, b, c]) = buses();
+
+        // Against the order the buses were added in
+        assert!(buses.set_output(c, Output::Bus(b)));
+        assert!(buses.add_send(b, AuxSend::new(a)));
+        assert!(buses.add_send(c, AuxSend::new(a)));
+
+        assert_eq!(buses.order().len(), 3);
+        assert!(position(&buses, c) < position(&buses, b));
+        assert!(position(&buses, b) < position(&buses, a));
+    }
+
+    #[test]
+    fn removing_reroutes_to_master() {
+        let (mut buses, [a, b, c]) = buses();
+
+        assert!(buses.set_output(a, Output::Bus(b)));
+        assert!(buses.add_send(c, AuxSend::new(b)));
+        assert!(buses.add_send(c, AuxSend::new(a)));
+
+        buses.remove(b);
+
+        assert!(buses.bus(b).is_none());
+        assert_eq!(buses.bus(a).unwrap().output(), Output::Master);
+        let sends: Vec<_> = buses
+            .bus(c)
+            .unwrap()
+            .sends()
+            .iter()
+            .map(|send| send.bus)
+            .collect();
+        assert_eq!(sends, [a]);
+
+        assert_eq!(buses.order().len(), 2);
+        assert!(position(&buses, c) < position(&buses, a));
+    }
+}
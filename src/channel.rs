@@ -22,9 +22,9 @@ bitflags::bitflags! {
 
 impl Speakers {
     /// The number of speakers there are
-    pub const MAX_COUNT: usize = 11;
+    pub const MAX_COUNT: usize = 12;
 
-    /// Returns the bitfield as an index from 1-MAX_COUNT
+    /// Returns the bitfield as an index from 0 to MAX_COUNT - 1
     pub fn as_u16(&self) -> u16 {
         let zeros = self.bits().trailing_zeros() as u16;
         if zeros >= Speakers::MAX_COUNT as u16 {
@@ -34,9 +34,23 @@ impl Speakers {
         }
     }
 
-    /// Returns a bitfield with all the speakers set until the `channel` parameter
-    pub const fn all_to(channel: u16) -> Speakers {
-        Speakers::from_bits_truncate(u16::MAX >> (16 - channel))
+    /// The short label of a single speaker, e.g. `Ls` for the side left speaker
+    pub fn short_name(&self) -> &'static str {
+        match *self {
+            Speakers::FrontLeft => "L",
+            Speakers::FrontRight => "R",
+            Speakers::Center => "C",
+            Speakers::Subwoofer => "LFE",
+            Speakers::SideLeft => "Ls",
+            Speakers::SideRight => "Rs",
+            Speakers::RearLeft => "Lrs",
+            Speakers::RearRight => "Rrs",
+            Speakers::HeightLeft1 => "Ltf",
+            Speakers::HeightRight1 => "Rtf",
+            Speakers::HeightLeft2 => "Ltr",
+            Speakers::HeightRight2 => "Rtr",
+            _ => "?",
+        }
     }
 }
 
@@ -60,6 +74,230 @@ impl From<u16> for Speakers {
     }
 }
 
+/// A -3dB gain
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// The speaker bits of the channel mask of WAVE_FORMAT_EXTENSIBLE files
+mod mask {
+    pub const FRONT_LEFT: u32 = 0x1;
+    pub const FRONT_RIGHT: u32 = 0x2;
+    pub const FRONT_CENTER: u32 = 0x4;
+    pub const LOW_FREQUENCY: u32 = 0x8;
+    pub const BACK_LEFT: u32 = 0x10;
+    pub const BACK_RIGHT: u32 = 0x20;
+    pub const SIDE_LEFT: u32 = 0x200;
+    pub const SIDE_RIGHT: u32 = 0x400;
+    pub const TOP_FRONT_LEFT: u32 = 0x1000;
+    pub const TOP_FRONT_RIGHT: u32 = 0x4000;
+    pub const TOP_BACK_LEFT: u32 = 0x8000;
+    pub const TOP_BACK_RIGHT: u32 = 0x20000;
+}
+
+/// A standard arrangement of speakers.
+///
+/// Signals in a layout have a channel for every speaker, in the order of `Layout::speakers`.
+/// This is the channel order of wave files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Mono,
+    Stereo,
+    /// Left, right and center
+    Lcr,
+    Quad,
+    Surround51,
+    Surround71,
+    Surround714,
+    Surround512,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 8] = [
+        Layout::Mono,
+        Layout::Stereo,
+        Layout::Lcr,
+        Layout::Quad,
+        Layout::Surround51,
+        Layout::Surround71,
+        Layout::Surround714,
+        Layout::Surround512,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Mono => "Mono",
+            Layout::Stereo => "Stereo",
+            Layout::Lcr => "LCR",
+            Layout::Quad => "Quad",
+            Layout::Surround51 => "5.1",
+            Layout::Surround71 => "7.1",
+            Layout::Surround714 => "7.1.4",
+            Layout::Surround512 => "5.1.2",
+        }
+    }
+
+    /// The speaker of every channel, in channel order
+    pub fn speakers(&self) -> &'static [Speakers] {
+        use Speakers as S;
+
+        match self {
+            Layout::Mono => &[S::Center],
+            Layout::Stereo => &[S::FrontLeft, S::FrontRight],
+            Layout::Lcr => &[S::FrontLeft, S::FrontRight, S::Center],
+            Layout::Quad => &[S::FrontLeft, S::FrontRight, S::RearLeft, S::RearRight],
+            Layout::Surround51 => &[
+                S::FrontLeft,
+                S::FrontRight,
+                S::Center,
+                S::Subwoofer,
+                S::SideLeft,
+                S::SideRight,
+            ],
+            Layout::Surround71 => &[
+                S::FrontLeft,
+                S::FrontRight,
+                S::Center,
+                S::Subwoofer,
+                S::RearLeft,
+                S::RearRight,
+                S::SideLeft,
+                S::SideRight,
+            ],
+            Layout::Surround714 => &[
+                S::FrontLeft,
+                S::FrontRight,
+                S::Center,
+                S::Subwoofer,
+                S::RearLeft,
+                S::RearRight,
+                S::SideLeft,
+                S::SideRight,
+                S::HeightLeft1,
+                S::HeightRight1,
+                S::HeightLeft2,
+                S::HeightRight2,
+            ],
+            Layout::Surround512 => &[
+                S::FrontLeft,
+                S::FrontRight,
+                S::Center,
+                S::Subwoofer,
+                S::SideLeft,
+                S::SideRight,
+                S::HeightLeft1,
+                S::HeightRight1,
+            ],
+        }
+    }
+
+    pub fn channel_count(&self) -> u16 {
+        self.speakers().len() as u16
+    }
+
+    /// The layout usually meant by a channel count. 8 channels are 7.1
+    pub fn from_channel_count(channels: u16) -> Option<Layout> {
+        match channels {
+            1 => Some(Layout::Mono),
+            2 => Some(Layout::Stereo),
+            3 => Some(Layout::Lcr),
+            4 => Some(Layout::Quad),
+            6 => Some(Layout::Surround51),
+            8 => Some(Layout::Surround71),
+            12 => Some(Layout::Surround714),
+            _ => None,
+        }
+    }
+
+    /// The layout described by the channel mask of a WAVE_FORMAT_EXTENSIBLE file.
+    ///
+    /// Back speakers are used as the surrounds of layouts without rear speakers, e.g. 5.1 (back)
+    pub fn from_channel_mask(mask: u32) -> Option<Layout> {
+        use mask::*;
+
+        const STEREO: u32 = FRONT_LEFT | FRONT_RIGHT;
+        const LCR: u32 = STEREO | FRONT_CENTER;
+        const QUAD: u32 = STEREO | BACK_LEFT | BACK_RIGHT;
+        const SURROUND_51: u32 = LCR | LOW_FREQUENCY | SIDE_LEFT | SIDE_RIGHT;
+        const SURROUND_51_BACK: u32 = LCR | LOW_FREQUENCY | BACK_LEFT | BACK_RIGHT;
+        const SURROUND_71: u32 = SURROUND_51 | BACK_LEFT | BACK_RIGHT;
+        const TOP_FRONT: u32 = TOP_FRONT_LEFT | TOP_FRONT_RIGHT;
+        const SURROUND_714: u32 = SURROUND_71 | TOP_FRONT | TOP_BACK_LEFT | TOP_BACK_RIGHT;
+        const SURROUND_512: u32 = SURROUND_51 | TOP_FRONT;
+        const SURROUND_512_BACK: u32 = SURROUND_51_BACK | TOP_FRONT;
+
+        match mask {
+            FRONT_CENTER => Some(Layout::Mono),
+            STEREO => Some(Layout::Stereo),
+            LCR => Some(Layout::Lcr),
+            QUAD => Some(Layout::Quad),
+            SURROUND_51 | SURROUND_51_BACK => Some(Layout::Surround51),
+            SURROUND_71 => Some(Layout::Surround71),
+            SURROUND_714 => Some(Layout::Surround714),
+            SURROUND_512 | SURROUND_512_BACK => Some(Layout::Surround512),
+            _ => None,
+        }
+    }
+
+    /// The channel mask of a WAVE_FORMAT_EXTENSIBLE file with this layout
+    pub fn channel_mask(&self) -> u32 {
+        use mask::*;
+
+        self.speakers()
+            .iter()
+            .map(|speaker| match *speaker {
+                Speakers::FrontLeft => FRONT_LEFT,
+                Speakers::FrontRight => FRONT_RIGHT,
+                Speakers::Center => FRONT_CENTER,
+                Speakers::Subwoofer => LOW_FREQUENCY,
+                Speakers::SideLeft => SIDE_LEFT,
+                Speakers::SideRight => SIDE_RIGHT,
+                Speakers::RearLeft => BACK_LEFT,
+                Speakers::RearRight => BACK_RIGHT,
+                Speakers::HeightLeft1 => TOP_FRONT_LEFT,
+                Speakers::HeightRight1 => TOP_FRONT_RIGHT,
+                Speakers::HeightLeft2 => TOP_BACK_LEFT,
+                Speakers::HeightRight2 => TOP_BACK_RIGHT,
+                _ => 0,
+            })
+            .fold(0, |mask, speaker| mask | speaker)
+    }
+
+    /// The speaker of `channel` in a signal with `layout`.
+    ///
+    /// Signals without a known layout have their channels in the order of `Speakers`
+    pub fn channel_speaker(layout: Option<Layout>, channel: usize) -> Option<Speakers> {
+        match layout {
+            Some(layout) => layout.speakers().get(channel).copied(),
+            None if channel < Speakers::MAX_COUNT => Some(Speakers::from(channel as u16)),
+            None => None,
+        }
+    }
+}
+
+/// The speakers that play `speaker` when it isn't available, with their gains.
+///
+/// Following ITU-R BS.775, the center is split into left and right at -3dB, surrounds are folded
+/// into the front at -3dB and the LFE is left out. Heights fold into the speakers below them
+fn fold_targets(speaker: Speakers, available: Speakers) -> &'static [(Speakers, f32)] {
+    use Speakers as S;
+
+    match speaker {
+        S::Center => &[(S::FrontLeft, MINUS_3DB), (S::FrontRight, MINUS_3DB)],
+        S::FrontLeft => &[(S::Center, MINUS_3DB)],
+        S::FrontRight => &[(S::Center, MINUS_3DB)],
+        S::SideLeft if available.contains(S::RearLeft) => &[(S::RearLeft, 1.0)],
+        S::SideRight if available.contains(S::RearRight) => &[(S::RearRight, 1.0)],
+        S::RearLeft if available.contains(S::SideLeft) => &[(S::SideLeft, 1.0)],
+        S::RearRight if available.contains(S::SideRight) => &[(S::SideRight, 1.0)],
+        S::SideLeft | S::RearLeft => &[(S::FrontLeft, MINUS_3DB)],
+        S::SideRight | S::RearRight => &[(S::FrontRight, MINUS_3DB)],
+        S::HeightLeft1 => &[(S::FrontLeft, MINUS_3DB)],
+        S::HeightRight1 => &[(S::FrontRight, MINUS_3DB)],
+        S::HeightLeft2 => &[(S::SideLeft, MINUS_3DB)],
+        S::HeightRight2 => &[(S::SideRight, MINUS_3DB)],
+        _ => &[],
+    }
+}
+
 /// Maps input channels to speakers.
 /// You can map up to `Speakers::MAX_COUNT` input channels
 ///
@@ -71,7 +309,7 @@ impl ChannelMapping {
     pub fn identity(channels: u16) -> ChannelMapping {
         let sps: Vec<_> = (0..Speakers::MAX_COUNT as u16)
             .map(|f| {
-                if f > channels {
+                if f >= channels {
                     None
                 } else {
                     Some(Speakers::from(f))
@@ -82,33 +320,6 @@ impl ChannelMapping {
         ChannelMapping(sps.try_into().unwrap())
     }
 
-    /// Construct the default smart mapping of speakers depending on the input and output channel count
-    ///
-    /// If there is 1 input channel, this channel will be mapped to all speakers in the output channel range
-    /// If there is the same amount of input and output channels, or more input channels, 1:1 mapping is used
-    /// Otherwise the front left and right channels are mapped to every pair of speakers (except center and sub) if they don't exist.
-    pub fn default(input_channels: u16, output_channels: u16) -> ChannelMapping {
-        if input_channels == 1 {
-            // Map all outputs to first channel
-            let mut v = vec![None; Speakers::MAX_COUNT];
-            v[0] = Some(Speakers::all());
-            ChannelMapping(v.try_into().unwrap())
-        } else if input_channels >= output_channels {
-            // Map channels 1:1
-            ChannelMapping::identity(output_channels)
-        } else {
-            let mut map = ChannelMapping::empty();
-
-            let upto = Speakers::all_to(output_channels) & !Speakers::Subwoofer & !Speakers::Center;
-            map[0u16] = Some(upto);
-            for c in 2..output_channels {
-                map[c] = Some(Speakers::from(c));
-            }
-
-            map
-        }
-    }
-
     /// No inputs are mapped to any speakers
     pub fn empty() -> ChannelMapping {
         ChannelMapping([None; Speakers::MAX_COUNT])
@@ -157,104 +368,437 @@ impl IndexMut<usize> for ChannelMapping {
     }
 }
 
-impl ChannelMapping {
-    /// Easily construct a channel mapping from an array
+/// The gain from every input channel to every output channel.
+///
+/// Unlike `ChannelMapping`, an input can be sent to an output at any level, which is needed for downmixes.
+/// Rows are input channels and columns are output channels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GainMatrix([[f32; Speakers::MAX_COUNT]; Speakers::MAX_COUNT]);
+
+impl GainMatrix {
+    /// No inputs are sent to any speakers
+    pub fn empty() -> GainMatrix {
+        GainMatrix([[0.0; Speakers::MAX_COUNT]; Speakers::MAX_COUNT])
+    }
+
+    /// The default routing from a signal with `input_channels` to one with `output_channels`.
     ///
-    /// # Arguments
-    /// * `mapping` - An array that contains a tuple with the first element being the input channel and the second being the output channel(s)
-    /// * `input_channel_count` - The number of input channels
-    /// * `keep_default` - if `true`, channels not specified in array will be defaults (see `ChannelMapping::default`), otherwise use empty mappings
+    /// The layouts are guessed from the channel counts. See `GainMatrix::between`
+    pub fn default(input_channels: u16, output_channels: u16) -> GainMatrix {
+        GainMatrix::between(
+            Layout::from_channel_count(input_channels),
+            input_channels,
+            Layout::from_channel_count(output_channels),
+            output_channels,
+        )
+    }
+
+    /// Route every input speaker to the same output speaker.
     ///
-    /// # Examples
-    pub fn from_array_mapping<const N: usize>(
-        mapping: [(Speakers, Speakers); N],
-        input_channel_count: u16,
-        keep_default: bool,
-    ) -> Self {
-        let mut default = if keep_default {
-            ChannelMapping::default(input_channel_count, input_channel_count)
-        } else {
-            ChannelMapping::empty()
+    /// Speakers the output doesn't have are folded into the ones it does, with the coefficients of ITU-R BS.775.
+    /// Channels without a layout are assumed to be in the order of `Speakers`
+    pub fn between(
+        input: Option<Layout>,
+        input_channels: u16,
+        output: Option<Layout>,
+        output_channels: u16,
+    ) -> GainMatrix {
+        let outputs: [Option<Speakers>; Speakers::MAX_COUNT] = std::array::from_fn(|channel| {
+            (channel < output_channels as usize)
+                .then(|| Layout::channel_speaker(output, channel))
+                .flatten()
+        });
+        let available = outputs
+            .iter()
+            .flatten()
+            .fold(Speakers::empty(), |available, speaker| available | *speaker);
+
+        /// Add `gain` to the outputs playing `speaker`, folding it into other speakers if there are none.
+        /// Folding can take a few steps, e.g. a surround goes to the front left which goes to the center of a mono output
+        fn add(
+            row: &mut [f32],
+            outputs: &[Option<Speakers>],
+            available: Speakers,
+            speaker: Speakers,
+            gain: f32,
+            depth: usize,
+        ) {
+            if available.contains(speaker) {
+                for (gain_out, _) in row
+                    .iter_mut()
+                    .zip(outputs)
+                    .filter(|(_, output)| **output == Some(speaker))
+                {
+                    *gain_out += gain;
+                }
+            } else if depth < 3 {
+                for (target, fold_gain) in fold_targets(speaker, available) {
+                    add(
+                        row,
+                        outputs,
+                        available,
+                        *target,
+                        gain * fold_gain,
+                        depth + 1,
+                    );
+                }
+            }
+        }
+
+        let mut matrix = GainMatrix::empty();
+
+        for (input_index, row) in matrix
+            .0
+            .iter_mut()
+            .enumerate()
+            .take(input_channels as usize)
+        {
+            if let Some(speaker) = Layout::channel_speaker(input, input_index) {
+                add(row, &outputs, available, speaker, 1.0, 0);
+            }
+        }
+
+        matrix
+    }
+
+    /// The gain from `input` to the output channel `output`. Zero if either is out of range
+    pub fn gain(&self, input: usize, output: usize) -> f32 {
+        self.0
+            .get(input)
+            .and_then(|row| row.get(output))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Mutable access to the gain from `input` to the output channel `output`
+    pub fn gain_mut(&mut self, input: usize, output: usize) -> Option<&mut f32> {
+        self.0.get_mut(input)?.get_mut(output)
+    }
+
+    /// Scale the gains of each input channel by its gain in `gains`. Inputs without a gain are left as they are
+    pub fn with_input_gains(mut self, gains: &[f32]) -> GainMatrix {
+        for (row, gain) in self.0.iter_mut().zip(gains) {
+            row.iter_mut().for_each(|value| *value *= gain);
+        }
+
+        self
+    }
+
+    /// The gains of the first `inputs` input channels, one row per input
+    pub fn rows(&self, inputs: usize) -> Vec<Vec<f32>> {
+        self.0.iter().take(inputs).map(|row| row.to_vec()).collect()
+    }
+
+    /// Build a matrix from rows of gains. Inputs and outputs past `Speakers::MAX_COUNT` are ignored
+    pub fn from_rows(rows: &[Vec<f32>]) -> GainMatrix {
+        let mut matrix = GainMatrix::empty();
+        for (row, gains) in matrix.0.iter_mut().zip(rows) {
+            for (gain, value) in row.iter_mut().zip(gains) {
+                *gain = *value;
+            }
+        }
+
+        matrix
+    }
+}
+
+impl From<ChannelMapping> for GainMatrix {
+    /// Every speaker an input is mapped to gets the input at unity gain.
+    /// Speakers are used as output channels in the order of `Speakers`
+    fn from(mapping: ChannelMapping) -> Self {
+        let mut matrix = GainMatrix::empty();
+
+        for (input, row) in matrix.0.iter_mut().enumerate() {
+            let Some(speakers) = mapping[input] else {
+                continue;
+            };
+
+            for speaker in speakers.iter() {
+                if let Some(gain) = row.get_mut(speaker.bits().trailing_zeros() as usize) {
+                    *gain = 1.0;
+                }
+            }
+        }
+
+        matrix
+    }
+}
+
+/// Down and upmixes between standard layouts, with the coefficients from ITU-R BS.775.
+///
+/// 5.1 is in the order L, R, C, LFE, Ls, Rs. The LFE channel is left out of downmixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingPreset {
+    MonoToStereo,
+    MonoTo51,
+    StereoToMono,
+    StereoTo51,
+    Surround51ToMono,
+    Surround51ToStereo,
+}
+
+impl RoutingPreset {
+    pub const ALL: [RoutingPreset; 6] = [
+        RoutingPreset::MonoToStereo,
+        RoutingPreset::MonoTo51,
+        RoutingPreset::StereoToMono,
+        RoutingPreset::StereoTo51,
+        RoutingPreset::Surround51ToMono,
+        RoutingPreset::Surround51ToStereo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoutingPreset::MonoToStereo => "Mono to Stereo",
+            RoutingPreset::MonoTo51 => "Mono to 5.1",
+            RoutingPreset::StereoToMono => "Stereo to Mono",
+            RoutingPreset::StereoTo51 => "Stereo to 5.1",
+            RoutingPreset::Surround51ToMono => "5.1 to Mono",
+            RoutingPreset::Surround51ToStereo => "5.1 to Stereo",
+        }
+    }
+
+    pub fn matrix(&self) -> GainMatrix {
+        const L: usize = 0;
+        const R: usize = 1;
+        const C: usize = 2;
+        const LS: usize = 4;
+        const RS: usize = 5;
+
+        // (input, output, gain)
+        let gains: &[(usize, usize, f32)] = match self {
+            // The phantom center of a stereo pair is 3dB louder than either speaker
+            RoutingPreset::MonoToStereo => &[(0, L, MINUS_3DB), (0, R, MINUS_3DB)],
+            RoutingPreset::MonoTo51 => &[(0, C, 1.0)],
+            RoutingPreset::StereoToMono => &[(L, 0, MINUS_3DB), (R, 0, MINUS_3DB)],
+            RoutingPreset::StereoTo51 => &[(L, L, 1.0), (R, R, 1.0)],
+            RoutingPreset::Surround51ToMono => &[
+                (L, 0, MINUS_3DB),
+                (R, 0, MINUS_3DB),
+                (C, 0, 1.0),
+                (LS, 0, 0.5),
+                (RS, 0, 0.5),
+            ],
+            RoutingPreset::Surround51ToStereo => &[
+                (L, L, 1.0),
+                (R, R, 1.0),
+                (C, L, MINUS_3DB),
+                (C, R, MINUS_3DB),
+                (LS, L, MINUS_3DB),
+                (RS, R, MINUS_3DB),
+            ],
         };
 
-        for (i, o) in mapping.iter() {
-            default[i] = Some(*o);
+        let mut matrix = GainMatrix::empty();
+        for &(input, output, gain) in gains {
+            matrix.0[input][output] = gain;
         }
 
-        default
+        matrix
     }
 }
 
+/// Route a packed (interleaved) input signal to an output signal.
+///
+/// This is the same routing as `channel_router_split_input`, so a sample sounds the same
+/// whether it is played directly or through a resampler
+///
+/// # Arguments
+///
+/// * `input_channels` - the number of channels the input signal contains
+/// * `output_channels` - the number of channels the output signal contains
+/// * `input` - the input signal with the channels of each frame after another
+/// * `output` - the output to add into. this will act as a packed audio signal
+/// * `input_offset` - the frame of the input signal to start reading from
+/// * `routing` - the gains to apply from each input to each output. `None` uses the default for the channel counts
+/// * `envelope` - the gain of each frame of the output, for fades
+///
 pub fn channel_router(
     input_channels: u16,
     output_channels: u16,
     input: &[f32],
     output: &mut [f32],
     input_offset: usize,
+    routing: &Option<GainMatrix>,
+    envelope: impl Fn(usize) -> f32,
 ) {
-    match (
-        Speakers::from(input_channels - 1),
-        Speakers::from(output_channels - 1),
-    ) {
-        (Speakers::FrontLeft, _) => output
-            .chunks_exact_mut(output_channels as _)
-            .zip(input[input_offset * input_channels as usize..].iter())
-            .for_each(|(o, i)| o.fill(*i)),
-        (i, o) if i >= o => output.copy_from_slice(
-            &input[input_offset * input_channels as usize..output_channels as usize],
-        ),
-        (_, _) => output
-            .chunks_exact_mut(output_channels as _)
-            .zip(input[input_offset * input_channels as usize..].chunks(input_channels as _))
-            .for_each(|(o, i)| {
-                o[..2].copy_from_slice(&i[..2]);
-                o[2..4.min(input_channels as usize)]
-                    .copy_from_slice(&i[2..4.min(input_channels as usize)]);
-                o[4..]
-                    .chunks_exact_mut(2)
-                    .for_each(|o| o.copy_from_slice(&i[..2]));
-            }),
-    }
-}
+    let channels = input_channels as usize;
+    let input = input.get(input_offset * channels..).unwrap_or(&[]);
 
+    route(
+        input_channels,
+        output_channels,
+        output,
+        routing,
+        envelope,
+        |channel| input.iter().skip(channel).step_by(channels),
+    );
+}
 
-/// Route an input signal to an output signal using a channel mapping
-/// 
+/// Route an input signal to an output signal using a gain matrix
+///
 /// # Arguments
-/// 
+///
 /// * `input_channels` - the number of channels the input signal contains
 /// * `output_channels` - the number of channels the output signal contains
-/// * `input` - the input signal. the indicies of the first array are channels 
-/// * `output` - the output to write into. this will act as a packed audio signal
+/// * `input` - the input signal. the indicies of the first array are channels
+/// * `output` - the output to add into. this will act as a packed audio signal
 /// * `input_offset` - the offset of the input signal to read from
-/// * `channel_mapping` - the mapping to consult when writing the signals
-/// 
+/// * `routing` - the gains to apply from each input to each output. `None` uses the default for the channel counts
+/// * `envelope` - the gain of each frame of the output, for fades
+///
 pub fn channel_router_split_input(
     input_channels: u16,
     output_channels: u16,
     input: &[impl AsRef<[f32]>],
     output: &mut [f32],
     input_offset: usize,
-    channel_mapping: &Option<ChannelMapping>,
+    routing: &Option<GainMatrix>,
+    envelope: impl Fn(usize) -> f32,
+) {
+    route(
+        input_channels,
+        output_channels,
+        output,
+        routing,
+        envelope,
+        |channel| {
+            // The input may not be available yet if it is still being resampled
+            input
+                .get(channel)
+                .and_then(|input| input.as_ref().get(input_offset..))
+                .unwrap_or(&[])
+                .iter()
+        },
+    );
+}
+
+/// Add every input channel into the output channels it's routed to.
+///
+/// `input` gives the samples of an input channel, starting at the first frame of the output.
+/// `envelope` gives the gain of each frame of the output
+fn route<'a, I: Iterator<Item = &'a f32>>(
+    input_channels: u16,
+    output_channels: u16,
+    output: &mut [f32],
+    routing: &Option<GainMatrix>,
+    envelope: impl Fn(usize) -> f32,
+    input: impl Fn(usize) -> I,
 ) {
-    let default_mapping = ChannelMapping::default(input_channels, output_channels);
-    let available = Speakers::all_to(output_channels);
-    let channel_mapping = channel_mapping.as_ref().unwrap_or(&default_mapping);
+    if input_channels == 0 || output_channels == 0 {
+        return;
+    }
 
-    for (input_index, channel) in input.iter().enumerate() {
-        let input = &channel.as_ref()[input_offset..];
+    let default_routing = GainMatrix::default(input_channels, output_channels);
+    let routing = routing.as_ref().unwrap_or(&default_routing);
+    let available = (output_channels as usize).min(Speakers::MAX_COUNT);
 
-        if let Some(speakers) = channel_mapping[input_index] {
-            speakers.intersection(available).iter().for_each(|s| {
-                let output_index = s.as_u16();
+    for input_index in 0..(input_channels as usize).min(Speakers::MAX_COUNT) {
+        for output_index in 0..available {
+            let gain = routing.gain(input_index, output_index);
+            if gain == 0.0 {
+                continue;
+            }
 
-                output
-                    .iter_mut()
-                    .skip(output_index as usize)
-                    .step_by(output_channels as usize)
-                    .enumerate()
-                    .for_each(|(i, o)| *o += input[i]);
-            });
+            output
+                .iter_mut()
+                .skip(output_index)
+                .step_by(output_channels as usize)
+                .zip(input(input_index))
+                .enumerate()
+                .for_each(|(frame, (o, i))| *o += i * gain * envelope(frame));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: usize = 64;
+
+    /// A gain matrix where every input reaches every output at a different level
+    fn dense_matrix() -> GainMatrix {
+        let mut matrix = GainMatrix::empty();
+        for input in 0..Speakers::MAX_COUNT {
+            for output in 0..Speakers::MAX_COUNT {
+                *matrix.gain_mut(input, output).unwrap() =
+                    (input * 7 + output * 3 + 1) as f32 / 97.0;
+            }
+        }
+
+        matrix
+    }
+
+    #[test]
+    fn routers_match() {
+        let envelope = |frame: usize| 1.0 - frame as f32 / FRAMES as f32;
+
+        for input_channels in 1..=Speakers::MAX_COUNT as u16 {
+            for output_channels in 1..=Speakers::MAX_COUNT as u16 {
+                let channels = input_channels as usize;
+                let packed: Vec<f32> = (0..FRAMES * channels)
+                    .map(|index| ((index * 31 % 101) as f32 - 50.0) / 50.0)
+                    .collect();
+                let split: Vec<Vec<f32>> = (0..channels)
+                    .map(|channel| {
+                        packed
+                            .iter()
+                            .skip(channel)
+                            .step_by(channels)
+                            .copied()
+                            .collect()
+                    })
+                    .collect();
+
+                for routing in [None, Some(dense_matrix())] {
+                    // Offsets near the end read past the input, which should be treated as silence
+                    for offset in [0, 5, FRAMES - 3, FRAMES + 10] {
+                        let len = FRAMES * output_channels as usize;
+                        let mut from_packed = vec![0.0; len];
+                        let mut from_split = vec![0.0; len];
+
+                        channel_router(
+                            input_channels,
+                            output_channels,
+                            &packed,
+                            &mut from_packed,
+                            offset,
+                            &routing,
+                            envelope,
+                        );
+                        channel_router_split_input(
+                            input_channels,
+                            output_channels,
+                            &split,
+                            &mut from_split,
+                            offset,
+                            &routing,
+                            envelope,
+                        );
+
+                        assert!(
+                            from_packed == from_split,
+                            "{input_channels} to {output_channels} channels from frame {offset}",
+                        );
+                        if offset < FRAMES {
+                            assert!(from_packed.iter().any(|sample| *sample != 0.0));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identity_maps_each_channel() {
+        for channels in 0..=Speakers::MAX_COUNT as u16 {
+            let mapping = ChannelMapping::identity(channels);
+
+            for channel in 0..Speakers::MAX_COUNT as u16 {
+                let expected = (channel < channels).then(|| Speakers::from(channel));
+                assert_eq!(mapping[channel], expected);
+            }
         }
     }
 }
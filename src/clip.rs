@@ -0,0 +1,230 @@
+use std::{ops::Range, sync::Arc, time::Duration};
+
+use crate::{
+    channel::Speakers,
+    fade::Fade,
+    id::{get_id_mgr, Id},
+    mixer::gain_to_db,
+    sample::{Sample, WaveViewClipState},
+};
+
+/// A region of a sample placed on a track's timeline.
+///
+/// Clips don't own any audio, they only reference a range of the sample's data.
+/// This makes every edit non-destructive.
+pub struct Clip {
+    pub id: Id,
+    pub sample: Arc<Sample>,
+
+    /// The position of the clip on the timeline in microseconds
+    pub start: u64,
+    /// The first frame of the sample that is played
+    pub offset: u64,
+    /// The number of frames of the sample that are played
+    pub length: u64,
+    /// The linear gain of each channel of the sample, applied before the track's routing
+    pub gain: [f32; Speakers::MAX_COUNT],
+    pub fade_in: Fade,
+    pub fade_out: Fade,
+
+    /// `None` if the sample isn't on the GPU
+    pub view: Option<Arc<WaveViewClipState>>,
+}
+
+impl Clip {
+    /// Create a clip that plays all of `sample` starting at `start` (in microseconds)
+    pub fn new(sample: Arc<Sample>, start: u64) -> Clip {
+        let length = sample.frames() as u64;
+        Clip::with_range(sample, start, 0, length)
+    }
+
+    /// Create a clip that plays `length` frames of `sample` from `offset`, starting at `start` (in microseconds)
+    ///
+    /// The range is clamped to the length of the sample
+    pub fn with_range(sample: Arc<Sample>, start: u64, offset: u64, length: u64) -> Clip {
+        let frames = sample.frames() as u64;
+        let offset = offset.min(frames);
+
+        Clip {
+            id: get_id_mgr().gen_id(),
+            view: WaveViewClipState::new(&sample).map(Arc::new),
+            start,
+            offset,
+            length: length.min(frames - offset),
+            gain: [1.0; Speakers::MAX_COUNT],
+            fade_in: Fade::default(),
+            fade_out: Fade::default(),
+            sample,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.sample.name
+    }
+
+    /// The duration of the clip
+    pub fn len_time(&self) -> Duration {
+        Duration::from_secs_f64(self.length as f64 / self.sample.header.sampling_rate as f64)
+    }
+
+    /// The position of the end of the clip on the timeline in microseconds
+    pub fn end(&self) -> u64 {
+        self.start + self.len_time().as_micros() as u64
+    }
+
+    /// The range of frames the clip occupies on the timeline when played at `sample_rate`
+    pub fn frame_range(&self, sample_rate: f64) -> Range<usize> {
+        let start = (self.start as f64 * sample_rate / 1_000_000.0).round() as usize;
+        let len = (self.length as f64 * sample_rate / self.sample.header.sampling_rate as f64)
+            .round() as usize;
+
+        start..start + len
+    }
+
+    /// Convert a duration in microseconds to a number of frames of the sample
+    pub fn micros_to_frames(&self, micros: u64) -> u64 {
+        (micros as f64 * self.sample.sample_rate).round() as u64
+    }
+
+    /// Convert a number of frames of the sample to a duration in microseconds
+    pub fn frames_to_micros(&self, frames: u64) -> u64 {
+        (frames as f64 / self.sample.sample_rate).round() as u64
+    }
+
+    /// Get the part of the clip that falls inside `range` (in microseconds) on the timeline as a new clip
+    ///
+    /// `None` is returned if the clip doesn't overlap the range
+    pub fn slice(&self, range: &Range<u64>) -> Option<Clip> {
+        let start = range.start.max(self.start);
+        let end = range.end.min(self.end());
+
+        if start >= end {
+            return None;
+        }
+
+        let first = self.micros_to_frames(start - self.start).min(self.length);
+        let last = self.micros_to_frames(end - self.start).min(self.length);
+
+        if first >= last {
+            return None;
+        }
+
+        let mut clip = Clip::with_range(
+            self.sample.clone(),
+            start,
+            self.offset + first,
+            last - first,
+        );
+        clip.gain = self.gain;
+        // Only the ends of the clip that are kept keep their fades
+        if start == self.start {
+            clip.fade_in = self.fade_in;
+        }
+        if end == self.end() {
+            clip.fade_out = self.fade_out;
+        }
+
+        Some(clip)
+    }
+
+    /// Move the start of the clip to `time` (in microseconds) while keeping the end in place.
+    ///
+    /// The clip can't be extended past the beginning of its sample or the timeline, and is kept at least one frame long
+    pub fn trim_start(&mut self, time: u64) {
+        let end_frame = self.offset + self.length;
+        // The clip can't be extended past the start of the timeline
+        let min_offset = self
+            .offset
+            .saturating_sub(self.micros_to_frames(self.start));
+
+        let delta = (time as f64 - self.start as f64) * self.sample.sample_rate;
+        let offset = ((self.offset as f64 + delta).round() as i64)
+            .clamp(min_offset as i64, end_frame as i64 - 1) as u64;
+
+        if offset < self.offset {
+            self.start = self
+                .start
+                .saturating_sub(self.frames_to_micros(self.offset - offset));
+        } else {
+            self.start += self.frames_to_micros(offset - self.offset);
+        }
+
+        self.offset = offset;
+        self.length = end_frame - offset;
+    }
+
+    /// Move the end of the clip to `time` (in microseconds) while keeping the start in place.
+    ///
+    /// The clip can't be extended past the end of its sample, and is kept at least one frame long
+    pub fn trim_end(&mut self, time: u64) {
+        let available = self.sample.frames() as u64 - self.offset;
+
+        self.length = self
+            .micros_to_frames(time.saturating_sub(self.start))
+            .clamp(1, available.max(1));
+    }
+
+    /// The interleaved data of the sample that is played
+    pub fn data(&self) -> &[f32] {
+        let channels = self.sample.header.channel_count as usize;
+        let start = self.offset as usize * channels;
+
+        &self.sample.data[start..start + self.length as usize * channels]
+    }
+
+    /// If any channel has a gain other than unity
+    pub fn has_gain(&self) -> bool {
+        self.gain.iter().any(|gain| *gain != 1.0)
+    }
+
+    /// A short description of the gain of the clip, or `None` if it is at unity
+    pub fn gain_label(&self) -> Option<String> {
+        if !self.has_gain() {
+            return None;
+        }
+
+        let gains = &self.gain[..self.sample.header.channel_count as usize];
+        if gains.iter().all(|gain| *gain == gains[0]) {
+            Some(format!("{:+.1} dB", gain_to_db(gains[0])))
+        } else {
+            Some("gain per channel".to_string())
+        }
+    }
+
+    /// The first frame played from the sample when it has been resampled to `sample_rate`
+    pub fn source_offset(&self, sample_rate: f64) -> usize {
+        (self.offset as f64 * sample_rate / self.sample.header.sampling_rate as f64).round()
+            as usize
+    }
+}
+
+impl Clip {
+    /// A copy of the clip that shares its id and view, used to restore it later.
+    ///
+    /// Only one of the copies should be on a track at a time
+    pub fn snapshot(&self) -> Clip {
+        Clip {
+            id: self.id,
+            sample: self.sample.clone(),
+            start: self.start,
+            offset: self.offset,
+            length: self.length,
+            gain: self.gain,
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            view: self.view.clone(),
+        }
+    }
+}
+
+impl Clone for Clip {
+    /// Clones get their own id and view so they can be displayed independently
+    fn clone(&self) -> Self {
+        let mut clip = Clip::with_range(self.sample.clone(), self.start, self.offset, self.length);
+        clip.gain = self.gain;
+        clip.fade_in = self.fade_in;
+        clip.fade_out = self.fade_out;
+
+        clip
+    }
+}
@@ -0,0 +1,595 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    automation::AutomationTarget,
+    bus::{AuxSend, Buses, MasterBus, Output},
+    history::{Snapshot, Swap},
+    id::Id,
+    meter::{Meter, MeterDisplay},
+    mixer::{format_pan, format_volume, TrackLevels, MAX_VOLUME_DB, MIN_VOLUME_DB},
+    processor::Insert,
+    state::State,
+    track::Track,
+};
+
+/// The width of a channel strip
+const STRIP_WIDTH: f32 = 130.0;
+/// The length of the faders and the meters next to them
+const FADER_LENGTH: f32 = 200.0;
+/// The diameter of the pan and send knobs
+const KNOB_SIZE: f32 = 24.0;
+/// How far a knob has to be dragged to turn it through its whole range
+const KNOB_DRAG_LENGTH: f32 = 150.0;
+/// The angle of the knob pointer from straight up at either end of the range
+const KNOB_ANGLE: f32 = 0.75 * std::f32::consts::PI;
+
+/// What the central panel shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum View {
+    /// The waveform lanes of the tracks
+    #[default]
+    Timeline,
+    /// A channel strip for every track and bus
+    Mixer,
+}
+
+/// The mixer console. The strips edit the same tracks and buses the playback engine mixes
+#[derive(Default)]
+pub struct Console {
+    /// The meters of the strips, by track or bus
+    meters: HashMap<Id, MeterDisplay>,
+}
+
+impl Console {
+    /// Draw a strip for every track, every bus and the master.
+    ///
+    /// Changes to tracks are recorded in the history. `show_buses` is set when an insert slot of a bus is clicked,
+    /// since bus inserts are edited in the buses window
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        tracks: &[Arc<RwLock<Track>>],
+        state: &Arc<RwLock<State>>,
+        master_meter: &mut MeterDisplay,
+        show_buses: &mut bool,
+    ) {
+        let (buses, meter, channels) = {
+            let state = state.read().unwrap();
+            (
+                state.buses.clone(),
+                state.master_meter.clone(),
+                state.output_channels,
+            )
+        };
+
+        let names: Vec<_> = tracks
+            .iter()
+            .map(|track| {
+                let track = track.read().unwrap();
+                (track.id, track.name.clone())
+            })
+            .collect();
+        let bus_names = buses.read().unwrap().names();
+
+        // Forget the meters of tracks and buses that were removed
+        self.meters
+            .retain(|id, _| names.iter().chain(&bus_names).any(|(other, _)| other == id));
+
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal_top(|ui| {
+                let targets: Vec<_> = bus_names.iter().map(|(bus, _)| *bus).collect();
+                let strip = StripUi {
+                    buses: &bus_names,
+                    targets: &targets,
+                    channels,
+                };
+
+                for track in tracks {
+                    let mut track = track.write().unwrap();
+                    let display = self.meters.entry(track.id).or_default();
+
+                    ui.push_id(track.id, |ui| {
+                        strip_frame(ui, |ui| track_strip(ui, strip, &mut track, display));
+                    });
+                    track.windows_ui(ui.ctx(), &names);
+                }
+
+                ui.separator();
+
+                let mut buses = buses.write().unwrap();
+                for (id, _) in &bus_names {
+                    // Buses can't go to the buses that feed them
+                    let targets: Vec<_> = targets
+                        .iter()
+                        .copied()
+                        .filter(|target| !buses.would_cycle(*id, *target))
+                        .collect();
+                    let strip = StripUi {
+                        targets: &targets,
+                        ..strip
+                    };
+                    let display = self.meters.entry(*id).or_default();
+
+                    ui.push_id(id, |ui| {
+                        strip_frame(ui, |ui| {
+                            *show_buses |= bus_strip(ui, strip, &mut buses, *id, display);
+                        });
+                    });
+                }
+
+                ui.separator();
+
+                strip_frame(ui, |ui| {
+                    *show_buses |=
+                        master_strip(ui, &mut buses.master, &meter, master_meter, channels);
+                });
+            });
+        });
+    }
+}
+
+/// The settings of a track or bus shown on its strip.
+///
+/// The strip edits a copy, which is compared with the original afterwards to find what changed
+#[derive(Debug, Clone, PartialEq)]
+struct StripSettings {
+    levels: TrackLevels,
+    output: Output,
+    sends: Vec<AuxSend>,
+    /// The name of each insert and if it is bypassed
+    inserts: Vec<(&'static str, bool)>,
+    /// If the track is armed for recording. `None` for buses, which can't be soloed or armed
+    armed: Option<bool>,
+}
+
+/// The names of `inserts` and if they are bypassed, for the insert slots
+fn insert_slots(inserts: &[Insert]) -> Vec<(&'static str, bool)> {
+    inserts
+        .iter()
+        .map(|insert| (insert.processor().kind().name(), insert.bypass))
+        .collect()
+}
+
+/// What happened on a strip besides the changes to its settings
+#[derive(Debug, Default)]
+struct StripResponse {
+    /// The control being dragged, so its changes are undone in one step
+    gesture: Option<egui::Id>,
+    /// If a drag ended
+    released: bool,
+    /// If an insert slot was clicked
+    open_inserts: bool,
+    removed_send: Option<usize>,
+    /// The bus a send was added to
+    added_send: Option<Id>,
+}
+
+impl StripResponse {
+    /// Remember if `control` is being dragged or was let go
+    fn track(&mut self, control: &egui::Response) {
+        if control.changed() && control.dragged() {
+            self.gesture = Some(control.id);
+        }
+        self.released |= control.drag_released();
+    }
+}
+
+/// Draws the controls shared by the strips of tracks and buses
+#[derive(Clone, Copy)]
+struct StripUi<'a> {
+    /// The ids and names of every bus
+    buses: &'a [(Id, String)],
+    /// The buses the strip can go to
+    targets: &'a [Id],
+    channels: u16,
+}
+
+impl StripUi<'_> {
+    fn output_name(&self, output: Output) -> &str {
+        match output {
+            Output::Master => "Master",
+            Output::Bus(bus) => self
+                .buses
+                .iter()
+                .find(|(id, _)| *id == bus)
+                .map_or("Missing", |(_, name)| name.as_str()),
+        }
+    }
+
+    /// Draw the insert slots, sends, output, pan, buttons, fader and meter of a strip and edit `settings`
+    fn show(
+        &self,
+        ui: &mut egui::Ui,
+        settings: &mut StripSettings,
+        meter: &Meter,
+        display: &mut MeterDisplay,
+    ) -> StripResponse {
+        let mut response = StripResponse {
+            open_inserts: insert_slots_ui(ui, &mut settings.inserts),
+            ..StripResponse::default()
+        };
+
+        ui.separator();
+
+        for (index, send) in settings.sends.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let level = knob(ui, &mut send.level, MIN_VOLUME_DB..=MAX_VOLUME_DB, 0.0)
+                    .on_hover_text(format!("{} dB", format_volume(send.level as f64)));
+                response.track(&level);
+
+                ui.vertical(|ui| {
+                    ui.label(self.output_name(Output::Bus(send.bus)));
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut send.pre_fader, "Pre")
+                            .on_hover_text("Send before the volume and pan");
+                        if ui
+                            .small_button("x")
+                            .on_hover_text("Remove the send")
+                            .clicked()
+                        {
+                            response.removed_send = Some(index);
+                        }
+                    });
+                });
+            });
+        }
+
+        egui::ComboBox::from_id_source("add-send")
+            .selected_text("Add Send")
+            .show_ui(ui, |ui| {
+                for target in self.targets {
+                    if ui
+                        .selectable_label(false, self.output_name(Output::Bus(*target)))
+                        .clicked()
+                    {
+                        response.added_send = Some(*target);
+                    }
+                }
+            });
+
+        ui.separator();
+
+        egui::ComboBox::from_id_source("output")
+            .selected_text(self.output_name(settings.output))
+            .show_ui(ui, |ui| {
+                let buses = self.targets.iter().map(|bus| Output::Bus(*bus));
+
+                for target in std::iter::once(Output::Master).chain(buses) {
+                    ui.selectable_value(&mut settings.output, target, self.output_name(target));
+                }
+            });
+
+        ui.horizontal(|ui| {
+            let pan = knob(ui, &mut settings.levels.pan, -1.0..=1.0, 0.0);
+            response.track(&pan);
+            ui.label(format_pan(settings.levels.pan as f64));
+        });
+
+        ui.horizontal(|ui| {
+            ui.toggle_value(&mut settings.levels.mute, "M")
+                .on_hover_text("Mute");
+            if let Some(armed) = &mut settings.armed {
+                ui.toggle_value(&mut settings.levels.solo, "S")
+                    .on_hover_text("Solo");
+                ui.toggle_value(armed, "R")
+                    .on_hover_text("Arm for recording");
+            }
+        });
+
+        let fader = fader_ui(
+            ui,
+            &mut settings.levels.volume,
+            meter,
+            display,
+            self.channels,
+        );
+        response.track(&fader);
+
+        response
+    }
+}
+
+/// Draw a frame around a strip
+fn strip_frame(ui: &mut egui::Ui, add_contents: impl FnOnce(&mut egui::Ui)) {
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.set_width(STRIP_WIDTH);
+        ui.vertical(add_contents);
+    });
+}
+
+/// Draw the strip of `track`.
+///
+/// Changes are recorded in the history. Dragging a control is undone in one step
+fn track_strip(ui: &mut egui::Ui, strip: StripUi, track: &mut Track, display: &mut MeterDisplay) {
+    ui.strong(&track.name);
+
+    let before = StripSettings {
+        levels: track.levels,
+        output: track.output,
+        sends: track.sends.clone(),
+        inserts: insert_slots(&track.inserts),
+        armed: Some(track.armed),
+    };
+    let mut settings = before.clone();
+    let meter = track.meter.clone();
+
+    let response = strip.show(ui, &mut settings, &meter, display);
+    let gesture = response.gesture;
+
+    if settings.levels.volume != before.levels.volume {
+        track.touch_automation(AutomationTarget::Volume);
+    }
+    if settings.levels.pan != before.levels.pan {
+        track.touch_automation(AutomationTarget::Pan);
+    }
+
+    if settings.levels != before.levels {
+        let name = if settings.levels.volume != before.levels.volume {
+            "Volume"
+        } else if settings.levels.pan != before.levels.pan {
+            "Pan"
+        } else if settings.levels.mute != before.levels.mute {
+            "Mute"
+        } else {
+            "Solo"
+        };
+
+        record(
+            track,
+            name,
+            |track| &mut track.levels,
+            settings.levels,
+            gesture,
+        );
+    }
+
+    if settings.output != before.output {
+        record(
+            track,
+            "Track Output",
+            |track| &mut track.output,
+            settings.output,
+            None,
+        );
+    }
+
+    let mut sends = settings.sends;
+    let sends_change = if let Some(index) = response.removed_send {
+        sends.remove(index);
+        Some("Remove Send")
+    } else if let Some(bus) = response.added_send {
+        sends.push(AuxSend::new(bus));
+        Some("Add Send")
+    } else if sends
+        .iter()
+        .zip(&before.sends)
+        .any(|(send, before)| send.pre_fader != before.pre_fader)
+    {
+        Some("Pre Fader Send")
+    } else {
+        (sends != before.sends).then_some("Send Level")
+    };
+    if let Some(name) = sends_change {
+        record(track, name, |track| &mut track.sends, sends, gesture);
+    }
+
+    if settings.inserts != before.inserts {
+        let mut inserts = track.inserts.clone();
+        for (insert, (_, bypass)) in inserts.iter_mut().zip(&settings.inserts) {
+            insert.bypass = *bypass;
+        }
+
+        record(
+            track,
+            "Bypass Insert",
+            |track| &mut track.inserts,
+            inserts,
+            None,
+        );
+    }
+
+    track.armed = settings.armed.unwrap_or(track.armed);
+
+    if response.open_inserts {
+        track.open_inserts();
+    }
+    if response.released {
+        track.app_state.write().unwrap().history.end_gesture();
+    }
+}
+
+/// Set `field` of `track` to `value` and record the change in the history
+fn record<T: Snapshot + Send + Sync + 'static>(
+    track: &mut Track,
+    name: &str,
+    field: fn(&mut Track) -> &mut T,
+    value: T,
+    gesture: Option<egui::Id>,
+) {
+    let (mut command, ()) = Swap::record(name, track, field, |track| *field(track) = value);
+
+    if let Some(gesture) = gesture {
+        command = command.with_gesture(gesture);
+    }
+
+    track.app_state.write().unwrap().history.push(command);
+}
+
+/// Draw the strip of the bus with `id`.
+///
+/// Outputs and sends are changed through `buses`, so they can't make a cycle. Returns `true` if an insert slot was clicked
+fn bus_strip(
+    ui: &mut egui::Ui,
+    strip: StripUi,
+    buses: &mut Buses,
+    id: Id,
+    display: &mut MeterDisplay,
+) -> bool {
+    let Some(bus) = buses.bus(id) else {
+        return false;
+    };
+
+    ui.strong(&bus.name);
+
+    let mut settings = StripSettings {
+        levels: bus.levels,
+        output: bus.output(),
+        sends: bus.sends().to_vec(),
+        inserts: insert_slots(&bus.inserts),
+        armed: None,
+    };
+    let meter = bus.meter.clone();
+
+    let response = strip.show(ui, &mut settings, &meter, display);
+
+    let Some(bus) = buses.bus_mut(id) else {
+        return false;
+    };
+
+    bus.levels = settings.levels;
+    for (insert, (_, bypass)) in bus.inserts.iter_mut().zip(&settings.inserts) {
+        insert.bypass = *bypass;
+    }
+    for (index, send) in settings.sends.iter().enumerate() {
+        if let Some((level, pre_fader)) = bus.send_mut(index) {
+            *level = send.level;
+            *pre_fader = send.pre_fader;
+        }
+    }
+
+    if settings.output != bus.output() {
+        buses.set_output(id, settings.output);
+    }
+    if let Some(index) = response.removed_send {
+        buses.remove_send(id, index);
+    }
+    if let Some(target) = response.added_send {
+        buses.add_send(id, AuxSend::new(target));
+    }
+
+    response.open_inserts
+}
+
+/// Draw the strip of the master bus. Returns `true` if an insert slot was clicked
+fn master_strip(
+    ui: &mut egui::Ui,
+    master: &mut MasterBus,
+    meter: &Meter,
+    display: &mut MeterDisplay,
+    channels: u16,
+) -> bool {
+    ui.strong("Master");
+
+    let mut inserts = insert_slots(&master.inserts);
+    let open_inserts = insert_slots_ui(ui, &mut inserts);
+    for (insert, (_, bypass)) in master.inserts.iter_mut().zip(inserts) {
+        insert.bypass = bypass;
+    }
+
+    ui.separator();
+    fader_ui(ui, &mut master.volume, meter, display, channels);
+
+    open_inserts
+}
+
+/// Draw a slot for each insert with a checkbox to bypass it. Returns `true` if a slot was clicked
+fn insert_slots_ui(ui: &mut egui::Ui, inserts: &mut [(&'static str, bool)]) -> bool {
+    let mut clicked = false;
+
+    for (name, bypass) in inserts.iter_mut() {
+        ui.horizontal(|ui| {
+            let mut active = !*bypass;
+            if ui
+                .checkbox(&mut active, "")
+                .on_hover_text("Uncheck to bypass")
+                .changed()
+            {
+                *bypass = !active;
+            }
+
+            clicked |= ui.small_button(*name).clicked();
+        });
+    }
+
+    clicked |= ui
+        .small_button("Inserts...")
+        .on_hover_text("Open the insert editor")
+        .clicked();
+
+    clicked
+}
+
+/// Draw a vertical volume fader with the meter next to it. Double clicking the fader goes back to 0 dB
+fn fader_ui(
+    ui: &mut egui::Ui,
+    volume: &mut f32,
+    meter: &Meter,
+    display: &mut MeterDisplay,
+    channels: u16,
+) -> egui::Response {
+    let fader = ui
+        .horizontal(|ui| {
+            ui.spacing_mut().slider_width = FADER_LENGTH;
+
+            let mut fader = ui.add(
+                egui::Slider::new(volume, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                    .vertical()
+                    .show_value(false),
+            );
+            if fader.double_clicked() {
+                *volume = 0.0;
+                fader.mark_changed();
+            }
+
+            display.ui(ui, meter, channels, FADER_LENGTH, true);
+
+            fader
+        })
+        .inner;
+
+    ui.label(format!("{} dB", format_volume(*volume as f64)));
+
+    fader
+}
+
+/// A knob turned by dragging up or down. Double clicking it goes back to `default`
+fn knob(
+    ui: &mut egui::Ui,
+    value: &mut f32,
+    range: RangeInclusive<f32>,
+    default: f32,
+) -> egui::Response {
+    let (rect, mut response) = ui.allocate_exact_size(
+        egui::vec2(KNOB_SIZE, KNOB_SIZE),
+        egui::Sense::click_and_drag(),
+    );
+    let (min, max) = (*range.start(), *range.end());
+
+    if response.double_clicked() {
+        *value = default;
+        response.mark_changed();
+    } else if response.dragged() {
+        let delta = -response.drag_delta().y / KNOB_DRAG_LENGTH * (max - min);
+        if delta != 0.0 {
+            *value = (*value + delta).clamp(min, max);
+            response.mark_changed();
+        }
+    }
+
+    let visuals = ui.style().interact(&response);
+    let center = rect.center();
+    let radius = KNOB_SIZE / 2.0 - 1.0;
+    ui.painter()
+        .circle(center, radius, visuals.bg_fill, visuals.fg_stroke);
+
+    let angle = ((*value - min) / (max - min) * 2.0 - 1.0) * KNOB_ANGLE;
+    let pointer = egui::vec2(angle.sin(), -angle.cos()) * radius * 0.8;
+    ui.painter()
+        .line_segment([center, center + pointer], visuals.fg_stroke);
+
+    response
+}
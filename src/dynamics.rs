@@ -0,0 +1,802 @@
+use std::ops::Range;
+
+use crate::{
+    meter::{TruePeak, TRUE_PEAK_DELAY},
+    mixer::{db_to_gain, gain_to_db},
+    processor::{AudioProcessor, Parameter, ProcessorKind},
+};
+
+/// Levels below this are treated as this, so silence doesn't turn into negative infinity
+const SILENCE_DB: f32 = -120.0;
+/// How long the limiter looks ahead for peaks in seconds
+const LIMITER_LOOKAHEAD: f32 = 0.0015;
+/// The limiter looks ahead at least this far, so the peaks found by the true peak filter are still ahead
+const MIN_LOOKAHEAD_FRAMES: usize = 12;
+/// How fast the level detector of the gate falls in milliseconds
+const GATE_DETECTOR_RELEASE: f32 = 10.0;
+/// The gain reduction at the end of the bar in the insert editor
+const GAIN_REDUCTION_RANGE_DB: f32 = 24.0;
+
+/// How much of the previous value a one pole smoother with `time` (in milliseconds) keeps every frame
+fn smoothing(time: f32, sample_rate: u32) -> f32 {
+    (-1.0 / (time * 0.001 * sample_rate as f32)).exp()
+}
+
+/// The highest absolute sample of the frame at `frame` in `key`, in decibels
+fn frame_level(key: &[f32], frame: Range<usize>) -> f32 {
+    let peak = key[frame]
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+
+    gain_to_db(peak).max(SILENCE_DB)
+}
+
+/// Show how much a processor turns the signal down
+pub fn gain_reduction_ui(ui: &mut egui::Ui, reduction: f32) {
+    ui.add(
+        egui::ProgressBar::new(reduction / GAIN_REDUCTION_RANGE_DB)
+            .text(format!("Gain Reduction {:.1} dB", reduction)),
+    );
+
+    if reduction > 0.0 {
+        ui.ctx().request_repaint();
+    }
+}
+
+/// A feed-forward compressor with a soft knee
+pub struct Compressor {
+    /// In decibels
+    threshold: f32,
+    ratio: f32,
+    /// The width of the knee around the threshold in decibels
+    knee: f32,
+    /// In milliseconds
+    attack: f32,
+    /// In milliseconds
+    release: f32,
+    /// In decibels
+    makeup: f32,
+
+    sample_rate: u32,
+    channels: usize,
+    /// The smoothed gain reduction in decibels
+    envelope: f32,
+    /// The highest gain reduction in the last buffer
+    gain_reduction: f32,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        let mut compressor = Compressor {
+            threshold: 0.0,
+            ratio: 0.0,
+            knee: 0.0,
+            attack: 0.0,
+            release: 0.0,
+            makeup: 0.0,
+            sample_rate: 0,
+            channels: 0,
+            envelope: 0.0,
+            gain_reduction: 0.0,
+        };
+        for (index, parameter) in Compressor::PARAMETERS.iter().enumerate() {
+            compressor.set_parameter(index, parameter.default);
+        }
+
+        compressor
+    }
+}
+
+impl Compressor {
+    const PARAMETERS: [Parameter; 6] = [
+        Parameter {
+            name: "Threshold",
+            unit: "dB",
+            range: -60.0..=0.0,
+            logarithmic: false,
+            default: -18.0,
+        },
+        Parameter {
+            name: "Ratio",
+            unit: ": 1",
+            range: 1.0..=20.0,
+            logarithmic: true,
+            default: 4.0,
+        },
+        Parameter {
+            name: "Knee",
+            unit: "dB",
+            range: 0.0..=24.0,
+            logarithmic: false,
+            default: 6.0,
+        },
+        Parameter {
+            name: "Attack",
+            unit: "ms",
+            range: 0.1..=100.0,
+            logarithmic: true,
+            default: 10.0,
+        },
+        Parameter {
+            name: "Release",
+            unit: "ms",
+            range: 10.0..=2000.0,
+            logarithmic: true,
+            default: 100.0,
+        },
+        Parameter {
+            name: "Makeup",
+            unit: "dB",
+            range: 0.0..=24.0,
+            logarithmic: false,
+            default: 0.0,
+        },
+    ];
+
+    /// The gain reduction for a signal at `level` decibels
+    fn reduction(&self, level: f32) -> f32 {
+        let over = level - self.threshold;
+        let slope = 1.0 - 1.0 / self.ratio;
+
+        if 2.0 * over <= -self.knee {
+            0.0
+        } else if 2.0 * over.abs() < self.knee {
+            slope * (over + self.knee / 2.0).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+
+    fn process_keyed(&mut self, buffer: &mut [f32], key: Option<&[f32]>) {
+        if self.channels == 0 {
+            return;
+        }
+
+        let attack = smoothing(self.attack, self.sample_rate);
+        let release = smoothing(self.release, self.sample_rate);
+        self.gain_reduction = 0.0;
+
+        for start in (0..buffer.len() / self.channels).map(|frame| frame * self.channels) {
+            let frame = start..start + self.channels;
+            let level = frame_level(key.unwrap_or(buffer), frame.clone());
+
+            let target = self.reduction(level);
+            let coefficient = if target > self.envelope {
+                attack
+            } else {
+                release
+            };
+            self.envelope = target + coefficient * (self.envelope - target);
+            self.gain_reduction = self.gain_reduction.max(self.envelope);
+
+            let gain = db_to_gain(self.makeup - self.envelope);
+            buffer[frame].iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+impl AudioProcessor for Compressor {
+    fn kind(&self) -> ProcessorKind {
+        ProcessorKind::Compressor
+    }
+
+    fn prepare(&mut self, sample_rate: u32, channels: u16, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels as usize;
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        self.process_keyed(buffer, None);
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.gain_reduction = 0.0;
+    }
+
+    fn parameters(&self) -> &[Parameter] {
+        &Compressor::PARAMETERS
+    }
+
+    fn parameter(&self, index: usize) -> f32 {
+        match index {
+            0 => self.threshold,
+            1 => self.ratio,
+            2 => self.knee,
+            3 => self.attack,
+            4 => self.release,
+            5 => self.makeup,
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        let Some(parameter) = Compressor::PARAMETERS.get(index) else {
+            return;
+        };
+        let value = value.clamp(*parameter.range.start(), *parameter.range.end());
+
+        match index {
+            0 => self.threshold = value,
+            1 => self.ratio = value,
+            2 => self.knee = value,
+            3 => self.attack = value,
+            4 => self.release = value,
+            _ => self.makeup = value,
+        }
+    }
+
+    fn accepts_sidechain(&self) -> bool {
+        true
+    }
+
+    fn process_sidechain(&mut self, buffer: &mut [f32], sidechain: &[f32]) {
+        self.process_keyed(buffer, Some(sidechain));
+    }
+
+    fn gain_reduction(&self) -> Option<f32> {
+        Some(self.gain_reduction)
+    }
+}
+
+/// A brickwall limiter that keeps the true peak of the signal below the ceiling.
+///
+/// It looks ahead so the gain is already down when a peak arrives, which delays the signal.
+/// The limiter always listens to its own signal, otherwise the ceiling couldn't be kept
+pub struct Limiter {
+    /// In decibels
+    input_gain: f32,
+    /// In dBTP
+    ceiling: f32,
+    /// In milliseconds
+    release: f32,
+
+    sample_rate: u32,
+    channels: usize,
+    /// The number of frames the gain comes down ahead of a peak
+    lookahead: usize,
+    /// The delayed signal, `lookahead` interleaved frames plus the delay of the true peak filter
+    delay: Vec<f32>,
+    delay_position: usize,
+    true_peaks: Vec<TruePeak>,
+    /// The gain needed by the last frame, recovering at the release time
+    release_gain: f32,
+    /// The release gains of the last `lookahead + 1` frames
+    required: Vec<f32>,
+    /// The lowest required gain at each of the last `lookahead + 1` frames, averaged to smooth the gain
+    held: Vec<f32>,
+    held_sum: f64,
+    position: usize,
+    /// The highest gain reduction in the last buffer in decibels
+    gain_reduction: f32,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        let mut limiter = Limiter {
+            input_gain: 0.0,
+            ceiling: 0.0,
+            release: 0.0,
+            sample_rate: 0,
+            channels: 0,
+            lookahead: 0,
+            delay: Vec::new(),
+            delay_position: 0,
+            true_peaks: Vec::new(),
+            release_gain: 1.0,
+            required: Vec::new(),
+            held: Vec::new(),
+            held_sum: 0.0,
+            position: 0,
+            gain_reduction: 0.0,
+        };
+        for (index, parameter) in Limiter::PARAMETERS.iter().enumerate() {
+            limiter.set_parameter(index, parameter.default);
+        }
+
+        limiter
+    }
+}
+
+impl Limiter {
+    const PARAMETERS: [Parameter; 3] = [
+        Parameter {
+            name: "Input Gain",
+            unit: "dB",
+            range: 0.0..=24.0,
+            logarithmic: false,
+            default: 0.0,
+        },
+        Parameter {
+            name: "Ceiling",
+            unit: "dBTP",
+            range: -12.0..=0.0,
+            logarithmic: false,
+            default: -1.0,
+        },
+        Parameter {
+            name: "Release",
+            unit: "ms",
+            range: 1.0..=1000.0,
+            logarithmic: true,
+            default: 50.0,
+        },
+    ];
+}
+
+impl AudioProcessor for Limiter {
+    fn kind(&self) -> ProcessorKind {
+        ProcessorKind::Limiter
+    }
+
+    fn prepare(&mut self, sample_rate: u32, channels: u16, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels as usize;
+        self.lookahead =
+            ((LIMITER_LOOKAHEAD * sample_rate as f32) as usize).max(MIN_LOOKAHEAD_FRAMES);
+
+        self.delay = vec![0.0; self.latency() * self.channels];
+        self.true_peaks = (0..self.channels).map(|_| TruePeak::default()).collect();
+        self.required = vec![1.0; self.lookahead + 1];
+        self.held = vec![1.0; self.lookahead + 1];
+        self.reset();
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.channels == 0 {
+            return;
+        }
+
+        let input_gain = db_to_gain(self.input_gain);
+        let ceiling = db_to_gain(self.ceiling);
+        let release = smoothing(self.release, self.sample_rate);
+        let window = self.lookahead + 1;
+        self.gain_reduction = 0.0;
+
+        for frame in buffer.chunks_exact_mut(self.channels) {
+            let mut peak = 0.0f32;
+            for (sample, true_peak) in frame.iter_mut().zip(&mut self.true_peaks) {
+                *sample *= input_gain;
+                peak = peak.max(true_peak.process(*sample));
+            }
+
+            // The gain drops at once and recovers at the release time
+            let required = if peak > ceiling { ceiling / peak } else { 1.0 };
+            self.release_gain = required.min(1.0 - (1.0 - self.release_gain) * release);
+
+            // Hold the lowest gain for the whole lookahead, then average it over the lookahead so the gain
+            // is down by the time the delayed peak comes out
+            self.required[self.position] = self.release_gain;
+            let held = self
+                .required
+                .iter()
+                .fold(1.0f32, |gain, required| gain.min(*required));
+            self.held_sum += (held - self.held[self.position]) as f64;
+            self.held[self.position] = held;
+            self.position = (self.position + 1) % window;
+
+            let gain = (self.held_sum / window as f64) as f32;
+            self.gain_reduction = self.gain_reduction.max(-gain_to_db(gain));
+
+            let delayed = &mut self.delay
+                [self.delay_position * self.channels..(self.delay_position + 1) * self.channels];
+            for (sample, delayed) in frame.iter_mut().zip(delayed) {
+                let input = std::mem::replace(delayed, *sample);
+                *sample = (input * gain).clamp(-ceiling, ceiling);
+            }
+            self.delay_position = (self.delay_position + 1) % self.latency();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.fill(0.0);
+        self.delay_position = 0;
+        self.true_peaks.iter_mut().for_each(TruePeak::reset);
+        self.release_gain = 1.0;
+        self.required.fill(1.0);
+        self.held.fill(1.0);
+        self.held_sum = self.held.len() as f64;
+        self.position = 0;
+        self.gain_reduction = 0.0;
+    }
+
+    fn latency(&self) -> usize {
+        // Peaks are found late by the true peak filter, so the signal is delayed by that much more
+        self.lookahead + TRUE_PEAK_DELAY
+    }
+
+    fn parameters(&self) -> &[Parameter] {
+        &Limiter::PARAMETERS
+    }
+
+    fn parameter(&self, index: usize) -> f32 {
+        match index {
+            0 => self.input_gain,
+            1 => self.ceiling,
+            2 => self.release,
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        let Some(parameter) = Limiter::PARAMETERS.get(index) else {
+            return;
+        };
+        let value = value.clamp(*parameter.range.start(), *parameter.range.end());
+
+        match index {
+            0 => self.input_gain = value,
+            1 => self.ceiling = value,
+            _ => self.release = value,
+        }
+    }
+
+    fn gain_reduction(&self) -> Option<f32> {
+        Some(self.gain_reduction)
+    }
+}
+
+/// A noise gate, or an expander with a low ratio.
+///
+/// It opens when the level reaches the threshold and closes once the level has stayed below the threshold minus the
+/// hysteresis for the hold time. While closed the signal is expanded downwards by the ratio, but never further than the range
+pub struct Gate {
+    /// In decibels
+    threshold: f32,
+    /// In decibels
+    hysteresis: f32,
+    ratio: f32,
+    /// The lowest gain of the gate in decibels
+    range: f32,
+    /// In milliseconds
+    attack: f32,
+    /// In milliseconds
+    hold: f32,
+    /// In milliseconds
+    release: f32,
+
+    sample_rate: u32,
+    channels: usize,
+    /// The detected level as a linear gain
+    level: f32,
+    open: bool,
+    /// The frames left before the gate closes
+    hold_remaining: usize,
+    /// The smoothed gain in decibels
+    gain: f32,
+    /// The highest gain reduction in the last buffer in decibels
+    gain_reduction: f32,
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        let mut gate = Gate {
+            threshold: 0.0,
+            hysteresis: 0.0,
+            ratio: 0.0,
+            range: 0.0,
+            attack: 0.0,
+            hold: 0.0,
+            release: 0.0,
+            sample_rate: 0,
+            channels: 0,
+            level: 0.0,
+            open: false,
+            hold_remaining: 0,
+            gain: 0.0,
+            gain_reduction: 0.0,
+        };
+        for (index, parameter) in Gate::PARAMETERS.iter().enumerate() {
+            gate.set_parameter(index, parameter.default);
+        }
+        gate.gain = gate.range;
+
+        gate
+    }
+}
+
+impl Gate {
+    const PARAMETERS: [Parameter; 7] = [
+        Parameter {
+            name: "Threshold",
+            unit: "dB",
+            range: -80.0..=0.0,
+            logarithmic: false,
+            default: -40.0,
+        },
+        Parameter {
+            name: "Hysteresis",
+            unit: "dB",
+            range: 0.0..=20.0,
+            logarithmic: false,
+            default: 6.0,
+        },
+        Parameter {
+            name: "Ratio",
+            unit: ": 1",
+            range: 1.0..=100.0,
+            logarithmic: true,
+            default: 100.0,
+        },
+        Parameter {
+            name: "Range",
+            unit: "dB",
+            range: -80.0..=0.0,
+            logarithmic: false,
+            default: -80.0,
+        },
+        Parameter {
+            name: "Attack",
+            unit: "ms",
+            range: 0.1..=50.0,
+            logarithmic: true,
+            default: 1.0,
+        },
+        Parameter {
+            name: "Hold",
+            unit: "ms",
+            range: 0.0..=500.0,
+            logarithmic: false,
+            default: 50.0,
+        },
+        Parameter {
+            name: "Release",
+            unit: "ms",
+            range: 5.0..=2000.0,
+            logarithmic: true,
+            default: 100.0,
+        },
+    ];
+
+    fn process_keyed(&mut self, buffer: &mut [f32], key: Option<&[f32]>) {
+        if self.channels == 0 {
+            return;
+        }
+
+        let attack = smoothing(self.attack, self.sample_rate);
+        let release = smoothing(self.release, self.sample_rate);
+        let detector = smoothing(GATE_DETECTOR_RELEASE, self.sample_rate);
+        let hold = (self.hold * 0.001 * self.sample_rate as f32) as usize;
+        self.gain_reduction = 0.0;
+
+        for start in (0..buffer.len() / self.channels).map(|frame| frame * self.channels) {
+            let frame = start..start + self.channels;
+
+            // Follow the peaks and fall slowly, so the gate doesn't chatter within a waveform
+            let peak = db_to_gain(frame_level(key.unwrap_or(buffer), frame.clone()));
+            self.level = peak.max(self.level * detector);
+            let level = gain_to_db(self.level).max(SILENCE_DB);
+
+            if level >= self.threshold {
+                self.open = true;
+            }
+            if self.open {
+                if level >= self.threshold - self.hysteresis {
+                    self.hold_remaining = hold;
+                } else if self.hold_remaining > 0 {
+                    self.hold_remaining -= 1;
+                } else {
+                    self.open = false;
+                }
+            }
+
+            let target = if self.open {
+                0.0
+            } else {
+                ((level - self.threshold).min(0.0) * (self.ratio - 1.0)).max(self.range)
+            };
+            let coefficient = if target > self.gain { attack } else { release };
+            self.gain = target + coefficient * (self.gain - target);
+            self.gain_reduction = self.gain_reduction.max(-self.gain);
+
+            let gain = db_to_gain(self.gain);
+            buffer[frame].iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+impl AudioProcessor for Gate {
+    fn kind(&self) -> ProcessorKind {
+        ProcessorKind::Gate
+    }
+
+    fn prepare(&mut self, sample_rate: u32, channels: u16, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.channels = channels as usize;
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        self.process_keyed(buffer, None);
+    }
+
+    fn reset(&mut self) {
+        self.level = 0.0;
+        self.open = false;
+        self.hold_remaining = 0;
+        self.gain = self.range;
+        self.gain_reduction = 0.0;
+    }
+
+    fn parameters(&self) -> &[Parameter] {
+        &Gate::PARAMETERS
+    }
+
+    fn parameter(&self, index: usize) -> f32 {
+        match index {
+            0 => self.threshold,
+            1 => self.hysteresis,
+            2 => self.ratio,
+            3 => self.range,
+            4 => self.attack,
+            5 => self.hold,
+            6 => self.release,
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        let Some(parameter) = Gate::PARAMETERS.get(index) else {
+            return;
+        };
+        let value = value.clamp(*parameter.range.start(), *parameter.range.end());
+
+        match index {
+            0 => self.threshold = value,
+            1 => self.hysteresis = value,
+            2 => self.ratio = value,
+            3 => self.range = value,
+            4 => self.attack = value,
+            5 => self.hold = value,
+            _ => self.release = value,
+        }
+    }
+
+    fn accepts_sidechain(&self) -> bool {
+        true
+    }
+
+    fn process_sidechain(&mut self, buffer: &mut [f32], sidechain: &[f32]) {
+        self.process_keyed(buffer, Some(sidechain));
+    }
+
+    fn gain_reduction(&self) -> Option<f32> {
+        Some(self.gain_reduction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// `frames` frames of a mono signal that stays at `level` decibels
+    fn steady(level: f32, frames: usize) -> Vec<f32> {
+        vec![db_to_gain(level); frames]
+    }
+
+    fn gate(hysteresis: f32, hold: f32) -> Gate {
+        let mut gate = Gate::default();
+        gate.set_parameter(0, -40.0);
+        gate.set_parameter(1, hysteresis);
+        gate.set_parameter(5, hold);
+        gate.prepare(SAMPLE_RATE, 1, 512);
+        gate.reset();
+
+        gate
+    }
+
+    /// Whether the gate is open after each frame of `signal`
+    fn gate_states(gate: &mut Gate, signal: &[f32]) -> Vec<bool> {
+        signal
+            .iter()
+            .map(|sample| {
+                gate.process(&mut [*sample]);
+                gate.open
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limiter_keeps_true_peak_below_ceiling() {
+        let channels = 2;
+        let mut limiter = Limiter::default();
+        limiter.set_parameter(0, 12.0);
+        limiter.set_parameter(1, -1.0);
+        limiter.prepare(SAMPLE_RATE, channels as u16, 512);
+
+        // A quarter of the sample rate at 45 degrees has true peaks 3 dB over its sample peaks,
+        // followed by a burst of a lower tone
+        let mut signal: Vec<f32> = (0..SAMPLE_RATE as usize / 2)
+            .flat_map(|frame| {
+                let phase =
+                    std::f32::consts::FRAC_PI_2 * frame as f32 + std::f32::consts::FRAC_PI_4;
+                [phase.sin(); 2]
+            })
+            .collect();
+        signal.extend((0..SAMPLE_RATE as usize / 2).flat_map(|frame| {
+            let phase = std::f32::consts::TAU * 997.0 * frame as f32 / SAMPLE_RATE as f32;
+            [phase.sin() * 0.8, -phase.sin() * 0.8]
+        }));
+
+        for block in signal.chunks_mut(512 * channels) {
+            limiter.process(block);
+        }
+
+        let ceiling = db_to_gain(-1.0);
+        let mut meters = [TruePeak::default(), TruePeak::default()];
+        for frame in signal.chunks_exact(channels) {
+            for (meter, sample) in meters.iter_mut().zip(frame) {
+                let true_peak = meter.process(*sample);
+                assert!(
+                    gain_to_db(true_peak) <= -1.0 + 1e-3,
+                    "{} dBTP is over the ceiling",
+                    gain_to_db(true_peak)
+                );
+            }
+        }
+
+        // The signal is still brought up to the ceiling, not just turned down
+        let peak = signal
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > ceiling * db_to_gain(-3.5), "{peak}");
+    }
+
+    #[test]
+    fn gate_hysteresis() {
+        let mut gate = gate(6.0, 0.0);
+
+        // Below the threshold, but above the hysteresis, a closed gate stays closed
+        let closed = gate_states(&mut gate, &steady(-43.0, 4800));
+        assert!(closed.iter().all(|open| !open));
+
+        // It opens as soon as the threshold is reached
+        let opened = gate_states(&mut gate, &steady(-38.0, 480));
+        assert!(opened.iter().all(|open| *open));
+
+        // Then stays open while the level is within the hysteresis
+        let held = gate_states(&mut gate, &steady(-43.0, 48000));
+        assert!(held.iter().all(|open| *open));
+
+        // And closes once it falls below it
+        let fallen = gate_states(&mut gate, &steady(-60.0, 4800));
+        assert!(!fallen.last().unwrap());
+    }
+
+    #[test]
+    fn gate_hold() {
+        let close_frame = |hold: f32| {
+            let mut gate = gate(6.0, hold);
+            gate_states(&mut gate, &steady(-30.0, 4800));
+
+            gate_states(&mut gate, &steady(-60.0, 48000))
+                .iter()
+                .position(|open| !open)
+                .unwrap()
+        };
+
+        let without_hold = close_frame(0.0);
+        let with_hold = close_frame(50.0);
+
+        // The detector takes a moment to fall below the hysteresis, then the gate is held open for 50 ms
+        assert!(without_hold < SAMPLE_RATE as usize / 20, "{without_hold}");
+        assert_eq!(with_hold - without_hold, SAMPLE_RATE as usize / 20);
+    }
+
+    #[test]
+    fn gate_follows_key() {
+        let mut gate = gate(6.0, 0.0);
+        gate.set_parameter(4, 0.1);
+
+        let mut signal = steady(-60.0, 4800);
+        gate.process_sidechain(&mut signal, &steady(-20.0, 4800));
+        assert!(gate.open);
+        assert!((signal.last().unwrap() - db_to_gain(-60.0)).abs() < 1e-6);
+
+        let mut signal = steady(-20.0, 48000);
+        gate.process_sidechain(&mut signal, &steady(-80.0, 48000));
+        assert!(!gate.open);
+        assert!(gain_to_db(*signal.last().unwrap()) < -90.0);
+    }
+}
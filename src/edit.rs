@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use crate::{clip::Clip, track::Track};
+
+/// Clips copied from a range of tracks.
+///
+/// Every entry is a track (relative to the first copied track) and its clips.
+/// Clip positions are relative to the start of the copied range
+#[derive(Clone, Default)]
+pub struct Clipboard {
+    pub tracks: Vec<(usize, Vec<Clip>)>,
+}
+
+impl Clipboard {
+    pub fn is_empty(&self) -> bool {
+        self.tracks.iter().all(|(_, clips)| clips.is_empty())
+    }
+}
+
+/// Editing operations on the clip list of a track.
+///
+/// None of these touch the audio data, they only add, remove or resize clips
+impl Track {
+    /// Split every clip that plays at `time` (in microseconds) into two clips
+    ///
+    /// Returns `true` if any clip was split
+    pub fn split_at(&mut self, time: u64) -> bool {
+        let mut split = false;
+
+        for index in (0..self.clips.len()).rev() {
+            let clip = &self.clips[index];
+            if time <= clip.start || time >= clip.end() {
+                continue;
+            }
+
+            let (Some(left), Some(right)) = (
+                clip.slice(&(clip.start..time)),
+                clip.slice(&(time..clip.end())),
+            ) else {
+                continue;
+            };
+
+            self.clips.splice(index..=index, [left, right]);
+            split = true;
+        }
+
+        if split {
+            self.invalidate_view();
+        }
+
+        split
+    }
+
+    /// Copy the parts of the clips that fall inside `range` (in microseconds).
+    ///
+    /// The copied clips are positioned relative to the start of the range
+    pub fn copy_range(&self, range: &Range<u64>) -> Vec<Clip> {
+        self.clips
+            .iter()
+            .filter_map(|clip| clip.slice(range))
+            .map(|mut clip| {
+                clip.start -= range.start;
+                clip
+            })
+            .collect()
+    }
+
+    /// Remove everything inside `range` (in microseconds), splitting clips that cross its edges.
+    ///
+    /// With `ripple`, everything after the range is moved back to close the gap.
+    /// Returns `true` if any clip was removed, shortened or moved
+    pub fn delete_range(&mut self, range: &Range<u64>, ripple: bool) -> bool {
+        if range.start >= range.end {
+            return false;
+        }
+
+        let shift = range.end - range.start;
+        let mut clips = Vec::with_capacity(self.clips.len());
+        let mut changed = false;
+
+        for mut clip in std::mem::take(&mut self.clips) {
+            if clip.end() <= range.start {
+                clips.push(clip);
+            } else if clip.start >= range.end {
+                if ripple {
+                    clip.start -= shift;
+                    changed = true;
+                }
+                clips.push(clip);
+            } else {
+                changed = true;
+                clips.extend(clip.slice(&(clip.start..range.start)));
+
+                if let Some(mut after) = clip.slice(&(range.end..clip.end())) {
+                    if ripple {
+                        after.start -= shift;
+                    }
+                    clips.push(after);
+                }
+            }
+        }
+
+        self.clips = clips;
+        if changed {
+            self.invalidate_view();
+        }
+
+        changed
+    }
+
+    /// Cut the parts of the clips inside `range` (in microseconds), leaving a gap.
+    ///
+    /// Returns `None` if there was nothing to cut
+    pub fn cut_range(&mut self, range: &Range<u64>) -> Option<Vec<Clip>> {
+        let clips = self.copy_range(range);
+
+        self.delete_range(range, false).then_some(clips)
+    }
+
+    /// Paste `clips` (positioned relative to zero) at `time` (in microseconds).
+    ///
+    /// Whatever is already on the track where the clips land is overwritten.
+    /// Returns `false` if there was nothing to paste
+    pub fn paste(&mut self, clips: &[Clip], time: u64) -> bool {
+        let Some(end) = clips.iter().map(|clip| clip.end()).max() else {
+            return false;
+        };
+
+        self.delete_range(&(time..time + end), false);
+
+        self.clips.extend(clips.iter().map(|clip| {
+            let mut clip = clip.clone();
+            clip.start += time;
+            clip
+        }));
+        self.clips.sort_by_key(|clip| clip.start);
+
+        self.invalidate_view();
+
+        true
+    }
+
+    /// Move the start of the clip at `index` to `time` (in microseconds), keeping its end in place
+    pub fn trim_clip_start(&mut self, index: usize, time: u64) {
+        self.clips[index].trim_start(time);
+        self.clips.sort_by_key(|clip| clip.start);
+
+        self.invalidate_view();
+    }
+
+    /// Move the end of the clip at `index` to `time` (in microseconds), keeping its start in place
+    pub fn trim_clip_end(&mut self, index: usize, time: u64) {
+        self.clips[index].trim_end(time);
+
+        self.invalidate_view();
+    }
+}
@@ -0,0 +1,433 @@
+use std::f32::consts::PI;
+
+use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Type};
+use egui::{Color32, Pos2, Sense, Stroke};
+
+use crate::processor::{AudioProcessor, Parameter, ProcessorKind};
+
+/// The most bands an equalizer can have
+pub const MAX_BANDS: usize = 8;
+/// Parameters of each band: type, frequency, gain and Q
+const BAND_PARAMETERS: usize = 4;
+/// Coefficients are computed for this rate until the equalizer is prepared
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+/// Band frequencies are kept below this fraction of the sample rate
+const MAX_FREQUENCY_RATIO: f32 = 0.49;
+
+const RESPONSE_HEIGHT: f32 = 120.0;
+/// The response curve goes from -RESPONSE_RANGE_DB to +RESPONSE_RANGE_DB
+const RESPONSE_RANGE_DB: f32 = 24.0;
+const RESPONSE_MIN_FREQUENCY: f32 = 20.0;
+const RESPONSE_MAX_FREQUENCY: f32 = 20000.0;
+const BAND_MARKER_RADIUS: f32 = 4.0;
+
+/// Passes the signal through unchanged
+const IDENTITY: Coefficients<f32> = Coefficients {
+    a1: 0.0,
+    a2: 0.0,
+    b0: 1.0,
+    b1: 0.0,
+    b2: 0.0,
+};
+
+/// The filter of a band
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandType {
+    LowShelf,
+    HighShelf,
+    Peaking,
+    LowPass,
+    HighPass,
+    Notch,
+    BandPass,
+}
+
+impl BandType {
+    pub const ALL: [BandType; 7] = [
+        BandType::LowShelf,
+        BandType::HighShelf,
+        BandType::Peaking,
+        BandType::LowPass,
+        BandType::HighPass,
+        BandType::Notch,
+        BandType::BandPass,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BandType::LowShelf => "Low Shelf",
+            BandType::HighShelf => "High Shelf",
+            BandType::Peaking => "Peaking",
+            BandType::LowPass => "Low Pass",
+            BandType::HighPass => "High Pass",
+            BandType::Notch => "Notch",
+            BandType::BandPass => "Band Pass",
+        }
+    }
+
+    /// If the gain of the band does anything
+    pub fn has_gain(&self) -> bool {
+        matches!(
+            self,
+            BandType::LowShelf | BandType::HighShelf | BandType::Peaking
+        )
+    }
+
+    /// The type stored in a parameter, which is its index in `ALL`
+    fn from_parameter(value: f32) -> BandType {
+        BandType::ALL[(value.round() as usize).min(BandType::ALL.len() - 1)]
+    }
+
+    fn parameter(&self) -> f32 {
+        BandType::ALL.iter().position(|kind| kind == self).unwrap() as f32
+    }
+
+    fn filter(&self, gain: f32) -> Type<f32> {
+        match self {
+            BandType::LowShelf => Type::LowShelf(gain),
+            BandType::HighShelf => Type::HighShelf(gain),
+            BandType::Peaking => Type::PeakingEQ(gain),
+            BandType::LowPass => Type::LowPass,
+            BandType::HighPass => Type::HighPass,
+            BandType::Notch => Type::Notch,
+            BandType::BandPass => Type::BandPass,
+        }
+    }
+}
+
+/// The settings of one band
+///
+/// `frequency` is in Hz and `gain` in decibels
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Band {
+    kind: BandType,
+    frequency: f32,
+    gain: f32,
+    q: f32,
+}
+
+impl Band {
+    const fn new(kind: BandType, frequency: f32) -> Band {
+        Band {
+            kind,
+            frequency,
+            gain: 0.0,
+            q: biquad::Q_BUTTERWORTH_F32,
+        }
+    }
+
+    fn coefficients(&self, sample_rate: u32) -> Coefficients<f32> {
+        let frequency = self.frequency.min(sample_rate as f32 * MAX_FREQUENCY_RATIO);
+
+        Coefficients::<f32>::from_params(
+            self.kind.filter(self.gain),
+            sample_rate.hz(),
+            frequency.hz(),
+            self.q,
+        )
+        .unwrap_or(IDENTITY)
+    }
+}
+
+/// The bands of a new equalizer. The first `DEFAULT_BAND_COUNT` are active
+const DEFAULT_BANDS: [Band; MAX_BANDS] = [
+    Band::new(BandType::LowShelf, 100.0),
+    Band::new(BandType::Peaking, 500.0),
+    Band::new(BandType::Peaking, 2000.0),
+    Band::new(BandType::HighShelf, 8000.0),
+    Band::new(BandType::Peaking, 250.0),
+    Band::new(BandType::Peaking, 1000.0),
+    Band::new(BandType::Peaking, 4000.0),
+    Band::new(BandType::Peaking, 12000.0),
+];
+const DEFAULT_BAND_COUNT: usize = 4;
+
+/// The gain in decibels of a filter with `coefficients` at `frequency`
+fn response(coefficients: &Coefficients<f32>, frequency: f32, sample_rate: u32) -> f32 {
+    let w = 2.0 * PI * frequency / sample_rate as f32;
+    let (cos, sin) = (w.cos(), w.sin());
+    let (cos2, sin2) = ((2.0 * w).cos(), (2.0 * w).sin());
+
+    let numerator_re = coefficients.b0 + coefficients.b1 * cos + coefficients.b2 * cos2;
+    let numerator_im = -(coefficients.b1 * sin + coefficients.b2 * sin2);
+    let denominator_re = 1.0 + coefficients.a1 * cos + coefficients.a2 * cos2;
+    let denominator_im = -(coefficients.a1 * sin + coefficients.a2 * sin2);
+
+    let power = (numerator_re * numerator_re + numerator_im * numerator_im)
+        / (denominator_re * denominator_re + denominator_im * denominator_im);
+
+    10.0 * power.max(f32::MIN_POSITIVE).log10()
+}
+
+/// A parametric equalizer with up to `MAX_BANDS` bands.
+///
+/// The first parameter is the number of active bands, followed by the type, frequency, gain and Q of every band
+pub struct Equalizer {
+    band_count: usize,
+    bands: [Band; MAX_BANDS],
+    coefficients: [Coefficients<f32>; MAX_BANDS],
+    parameters: Vec<Parameter>,
+    sample_rate: u32,
+    /// The filters of every band for each channel
+    filters: Vec<[DirectForm2Transposed<f32>; MAX_BANDS]>,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        let mut parameters = vec![Parameter {
+            name: "Bands",
+            unit: "",
+            range: 1.0..=MAX_BANDS as f32,
+            logarithmic: false,
+            default: DEFAULT_BAND_COUNT as f32,
+        }];
+
+        for band in &DEFAULT_BANDS {
+            parameters.extend([
+                Parameter {
+                    name: "Type",
+                    unit: "",
+                    range: 0.0..=(BandType::ALL.len() - 1) as f32,
+                    logarithmic: false,
+                    default: band.kind.parameter(),
+                },
+                Parameter {
+                    name: "Frequency",
+                    unit: "Hz",
+                    range: RESPONSE_MIN_FREQUENCY..=RESPONSE_MAX_FREQUENCY,
+                    logarithmic: true,
+                    default: band.frequency,
+                },
+                Parameter {
+                    name: "Gain",
+                    unit: "dB",
+                    range: -RESPONSE_RANGE_DB..=RESPONSE_RANGE_DB,
+                    logarithmic: false,
+                    default: band.gain,
+                },
+                Parameter {
+                    name: "Q",
+                    unit: "",
+                    range: 0.1..=18.0,
+                    logarithmic: true,
+                    default: band.q,
+                },
+            ]);
+        }
+
+        let mut equalizer = Equalizer {
+            band_count: DEFAULT_BAND_COUNT,
+            bands: DEFAULT_BANDS,
+            coefficients: [IDENTITY; MAX_BANDS],
+            parameters,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            filters: Vec::new(),
+        };
+        equalizer.update_coefficients();
+
+        equalizer
+    }
+}
+
+impl Equalizer {
+    fn update_coefficients(&mut self) {
+        for band in 0..MAX_BANDS {
+            self.update_band(band);
+        }
+    }
+
+    fn update_band(&mut self, band: usize) {
+        let coefficients = self.bands[band].coefficients(self.sample_rate);
+        self.coefficients[band] = coefficients;
+
+        for filters in &mut self.filters {
+            filters[band].update_coefficients(coefficients);
+        }
+    }
+
+    /// The gain of the active bands together at `frequency`, in decibels
+    pub fn response(&self, frequency: f32) -> f32 {
+        self.coefficients[..self.band_count]
+            .iter()
+            .map(|coefficients| response(coefficients, frequency, self.sample_rate))
+            .sum()
+    }
+
+    /// The index of the first parameter of `band`
+    fn band_parameter(band: usize) -> usize {
+        1 + band * BAND_PARAMETERS
+    }
+
+    /// Draw the response of the active bands with a marker at the frequency of each band
+    fn response_ui(&self, ui: &mut egui::Ui) {
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(ui.available_width(), RESPONSE_HEIGHT),
+            Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+
+        painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+
+        let (min, max) = (
+            RESPONSE_MIN_FREQUENCY.log10(),
+            RESPONSE_MAX_FREQUENCY.log10(),
+        );
+        let x_at =
+            |frequency: f32| rect.left() + rect.width() * (frequency.log10() - min) / (max - min);
+        let frequency_at =
+            |x: f32| 10f32.powf(min + (x - rect.left()) / rect.width() * (max - min));
+        let y_at = |db: f32| {
+            let db = db.clamp(-RESPONSE_RANGE_DB, RESPONSE_RANGE_DB);
+            rect.center().y - db / RESPONSE_RANGE_DB * rect.height() / 2.0
+        };
+
+        let grid = Stroke::new(1.0, visuals.faint_bg_color);
+        for frequency in [100.0, 1000.0, 10000.0] {
+            let x = x_at(frequency);
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                grid,
+            );
+        }
+        for db in [-12.0, 0.0, 12.0] {
+            let y = y_at(db);
+            painter.line_segment(
+                [Pos2::new(rect.left(), y), Pos2::new(rect.right(), y)],
+                grid,
+            );
+        }
+
+        let points = (0..=rect.width() as usize)
+            .map(|x| {
+                let x = rect.left() + x as f32;
+                Pos2::new(x, y_at(self.response(frequency_at(x))))
+            })
+            .collect::<Vec<_>>();
+        painter.add(egui::Shape::line(
+            points,
+            Stroke::new(1.5, visuals.selection.bg_fill),
+        ));
+
+        for band in &self.bands[..self.band_count] {
+            let center = Pos2::new(x_at(band.frequency), y_at(self.response(band.frequency)));
+            painter.circle_filled(center, BAND_MARKER_RADIUS, Color32::WHITE);
+        }
+    }
+}
+
+impl AudioProcessor for Equalizer {
+    fn kind(&self) -> ProcessorKind {
+        ProcessorKind::Equalizer
+    }
+
+    fn prepare(&mut self, sample_rate: u32, channels: u16, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.filters =
+            vec![[DirectForm2Transposed::<f32>::new(IDENTITY); MAX_BANDS]; channels as usize];
+        self.update_coefficients();
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.filters.is_empty() {
+            return;
+        }
+
+        for frame in buffer.chunks_exact_mut(self.filters.len()) {
+            for (sample, filters) in frame.iter_mut().zip(&mut self.filters) {
+                for filter in &mut filters[..self.band_count] {
+                    *sample = filter.run(*sample);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset_state();
+        }
+    }
+
+    fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    fn parameter(&self, index: usize) -> f32 {
+        if index == 0 {
+            return self.band_count as f32;
+        }
+
+        let Some(band) = self.bands.get((index - 1) / BAND_PARAMETERS) else {
+            return 0.0;
+        };
+
+        match (index - 1) % BAND_PARAMETERS {
+            0 => band.kind.parameter(),
+            1 => band.frequency,
+            2 => band.gain,
+            _ => band.q,
+        }
+    }
+
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        let Some(parameter) = self.parameters.get(index) else {
+            return;
+        };
+        let value = value.clamp(*parameter.range.start(), *parameter.range.end());
+
+        if index == 0 {
+            let band_count = value.round() as usize;
+
+            // Bands that come back shouldn't ring with what they had before
+            for filters in &mut self.filters {
+                for filter in &mut filters[self.band_count.min(band_count)..band_count] {
+                    filter.reset_state();
+                }
+            }
+
+            self.band_count = band_count;
+            return;
+        }
+
+        let band_index = (index - 1) / BAND_PARAMETERS;
+        let band = &mut self.bands[band_index];
+        match (index - 1) % BAND_PARAMETERS {
+            0 => band.kind = BandType::from_parameter(value),
+            1 => band.frequency = value,
+            2 => band.gain = value,
+            _ => band.q = value,
+        }
+
+        self.update_band(band_index);
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        self.response_ui(ui);
+        self.parameter_slider(ui, 0);
+
+        for band in 0..self.band_count {
+            let first = Equalizer::band_parameter(band);
+            let kind = self.bands[band].kind;
+
+            ui.horizontal(|ui| {
+                ui.label(format!("Band {}", band + 1));
+
+                egui::ComboBox::from_id_source(band)
+                    .selected_text(kind.name())
+                    .show_ui(ui, |ui| {
+                        for option in BandType::ALL {
+                            if ui.selectable_label(option == kind, option.name()).clicked() {
+                                self.set_parameter(first, option.parameter());
+                            }
+                        }
+                    });
+            });
+
+            ui.indent(band, |ui| {
+                self.parameter_slider(ui, first + 1);
+                ui.add_enabled_ui(kind.has_gain(), |ui| self.parameter_slider(ui, first + 2));
+                self.parameter_slider(ui, first + 3);
+            });
+        }
+    }
+}
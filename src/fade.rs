@@ -0,0 +1,173 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mixer::{db_to_gain, MIN_VOLUME_DB},
+    track::Track,
+};
+
+/// The shape of a fade
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FadeCurve {
+    Linear,
+    /// Sine taper. Two clips crossfading with it keep the same total power
+    #[default]
+    EqualPower,
+    /// Starts and ends slowly
+    SCurve,
+    /// Linear in decibels, from `MIN_VOLUME_DB` to unity
+    Logarithmic,
+}
+
+impl FadeCurve {
+    pub const ALL: [FadeCurve; 4] = [
+        FadeCurve::Linear,
+        FadeCurve::EqualPower,
+        FadeCurve::SCurve,
+        FadeCurve::Logarithmic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FadeCurve::Linear => "Linear",
+            FadeCurve::EqualPower => "Equal Power",
+            FadeCurve::SCurve => "S-Curve",
+            FadeCurve::Logarithmic => "Logarithmic",
+        }
+    }
+
+    /// The gain of a fade in at `position` (0 is the start of the fade, 1 the end).
+    ///
+    /// Fade outs use the same curve backwards
+    pub fn gain(&self, position: f32) -> f32 {
+        let position = position.clamp(0.0, 1.0);
+
+        match self {
+            FadeCurve::Linear => position,
+            FadeCurve::EqualPower => (position * FRAC_PI_2).sin(),
+            FadeCurve::SCurve => (1.0 - (position * PI).cos()) / 2.0,
+            FadeCurve::Logarithmic if position == 0.0 => 0.0,
+            FadeCurve::Logarithmic => db_to_gain(MIN_VOLUME_DB * (1.0 - position)),
+        }
+    }
+}
+
+/// A fade at one end of a clip
+///
+/// `length` is in microseconds. A length of zero doesn't fade
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fade {
+    pub length: u64,
+    pub curve: FadeCurve,
+}
+
+/// The end of a clip a fade is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FadeEdge {
+    In,
+    Out,
+}
+
+/// The fades of a clip in frames at the output sample rate
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope {
+    fade_in: usize,
+    fade_in_curve: FadeCurve,
+    fade_out: usize,
+    fade_out_curve: FadeCurve,
+    /// The length of the clip
+    length: usize,
+}
+
+impl Envelope {
+    /// The envelope of a clip `length` frames long at `sample_rate`.
+    ///
+    /// Fades that are longer than the clip together are shortened in proportion
+    pub fn new(fade_in: Fade, fade_out: Fade, length: usize, sample_rate: f64) -> Envelope {
+        let frames =
+            |fade: &Fade| (fade.length as f64 * sample_rate / 1_000_000.0).round() as usize;
+        let (mut fade_in_frames, mut fade_out_frames) = (frames(&fade_in), frames(&fade_out));
+
+        let total = fade_in_frames + fade_out_frames;
+        if total > length {
+            fade_in_frames = fade_in_frames * length / total;
+            fade_out_frames = length - fade_in_frames;
+        }
+
+        Envelope {
+            fade_in: fade_in_frames,
+            fade_in_curve: fade_in.curve,
+            fade_out: fade_out_frames,
+            fade_out_curve: fade_out.curve,
+            length,
+        }
+    }
+
+    /// The gain at `frame` from the start of the clip
+    pub fn gain(&self, frame: usize) -> f32 {
+        let mut gain = 1.0;
+
+        if frame < self.fade_in {
+            gain *= self
+                .fade_in_curve
+                .gain((frame as f32 + 0.5) / self.fade_in as f32);
+        }
+
+        let until_end = self.length.saturating_sub(frame);
+        if until_end <= self.fade_out {
+            gain *= self
+                .fade_out_curve
+                .gain((until_end as f32 - 0.5) / self.fade_out as f32);
+        }
+
+        gain
+    }
+}
+
+impl Track {
+    /// The fades of the clip at `index`, lengthened to crossfade with the clips it overlaps
+    pub fn clip_fades(&self, index: usize) -> (Fade, Fade) {
+        let clip = &self.clips[index];
+        let (mut fade_in, mut fade_out) = (clip.fade_in, clip.fade_out);
+
+        for (other_index, other) in self.clips.iter().enumerate() {
+            if other_index == index {
+                continue;
+            }
+
+            // Another clip is playing when this one starts
+            if other.start < clip.start && other.end() > clip.start {
+                fade_in.length = fade_in.length.max(other.end().min(clip.end()) - clip.start);
+            }
+
+            // Another clip starts while this one is playing
+            if other.start > clip.start && other.start < clip.end() {
+                fade_out.length = fade_out.length.max(clip.end() - other.start);
+            }
+        }
+
+        (fade_in, fade_out)
+    }
+
+    /// Set the fade at `edge` of the clip at `index`.
+    ///
+    /// The fade is shortened so the fades don't overlap
+    pub fn set_fade(&mut self, index: usize, edge: FadeEdge, mut fade: Fade) {
+        let clip = &mut self.clips[index];
+        let length = clip.end() - clip.start;
+
+        match edge {
+            FadeEdge::In => {
+                fade.length = fade.length.min(length - clip.fade_out.length.min(length));
+                clip.fade_in = fade;
+            }
+            FadeEdge::Out => {
+                fade.length = fade.length.min(length - clip.fade_in.length.min(length));
+                clip.fade_out = fade;
+            }
+        }
+
+        self.invalidate_view();
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    automation::AutomationLane,
+    bus::{AuxSend, Output},
+    channel::GainMatrix,
+    clip::Clip,
+    id::Id,
+    mixer::TrackLevels,
+    processor::Insert,
+    track::Track,
+};
+
+/// The maximum number of steps that can be undone
+const HISTORY_LIMIT: usize = 256;
+
+/// A change to the session that can be undone and redone.
+///
+/// Commands are recorded after they have been applied, so the first call is always `revert`
+pub trait Command: Send + Sync {
+    /// A short description of the change shown in the edit menu
+    fn name(&self) -> &str;
+
+    fn apply(&mut self, tracks: &[Arc<RwLock<Track>>]);
+
+    fn revert(&mut self, tracks: &[Arc<RwLock<Track>>]);
+
+    /// Commands recorded during the same gesture (e.g. dragging a fader) are merged into one undo step
+    fn gesture(&self) -> Option<egui::Id> {
+        None
+    }
+
+    /// Fold `next`, which was applied after this command, into this command.
+    ///
+    /// `next` is given back if the commands can't be merged
+    fn merge(&mut self, next: Box<dyn Command>) -> Result<(), Box<dyn Command>> {
+        Err(next)
+    }
+}
+
+/// Values of a track that can be stored in the history.
+///
+/// Snapshots should be cheap and may share resources with the original since only one of them is on the track at a time
+pub trait Snapshot {
+    fn snapshot(&self) -> Self;
+}
+
+impl Snapshot for Vec<Clip> {
+    fn snapshot(&self) -> Self {
+        self.iter().map(Clip::snapshot).collect()
+    }
+}
+
+impl Snapshot for Vec<Insert> {
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl Snapshot for Option<GainMatrix> {
+    fn snapshot(&self) -> Self {
+        *self
+    }
+}
+
+impl Snapshot for TrackLevels {
+    fn snapshot(&self) -> Self {
+        *self
+    }
+}
+
+impl Snapshot for Output {
+    fn snapshot(&self) -> Self {
+        *self
+    }
+}
+
+impl Snapshot for Vec<AuxSend> {
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl Snapshot for Vec<AutomationLane> {
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// A change to a single field of a track.
+///
+/// The command holds the value the field doesn't currently have, so applying and reverting both swap it with the track's
+pub struct Swap<T> {
+    name: String,
+    track: Id,
+    field: fn(&mut Track) -> &mut T,
+    value: T,
+    gesture: Option<egui::Id>,
+}
+
+impl<T: Snapshot> Swap<T> {
+    /// Run `edit` on `track` and record the previous value of `field` so the edit can be undone
+    pub fn record<R>(
+        name: impl Into<String>,
+        track: &mut Track,
+        field: fn(&mut Track) -> &mut T,
+        edit: impl FnOnce(&mut Track) -> R,
+    ) -> (Swap<T>, R) {
+        let value = field(track).snapshot();
+        let result = edit(track);
+
+        (
+            Swap {
+                name: name.into(),
+                track: track.id,
+                field,
+                value,
+                gesture: None,
+            },
+            result,
+        )
+    }
+
+    /// Merge the command with others recorded during the same `gesture`
+    pub fn with_gesture(mut self, gesture: egui::Id) -> Swap<T> {
+        self.gesture = Some(gesture);
+        self
+    }
+
+    fn swap(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        let Some(track) = tracks
+            .iter()
+            .find(|track| track.read().unwrap().id == self.track)
+        else {
+            return;
+        };
+
+        let mut track = track.write().unwrap();
+        std::mem::swap((self.field)(&mut track), &mut self.value);
+        track.invalidate_view();
+    }
+}
+
+impl<T: Snapshot + Send + Sync> Command for Swap<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        self.swap(tracks)
+    }
+
+    fn revert(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        self.swap(tracks)
+    }
+
+    fn gesture(&self) -> Option<egui::Id> {
+        self.gesture
+    }
+
+    fn merge(&mut self, next: Box<dyn Command>) -> Result<(), Box<dyn Command>> {
+        // This command already holds the value from before the gesture started
+        if next.gesture().is_some() && next.gesture() == self.gesture {
+            Ok(())
+        } else {
+            Err(next)
+        }
+    }
+}
+
+/// A change to a parameter of an insert.
+///
+/// Clones of an insert share its processor, so the change is undone wherever the insert is now, on a track or a bus
+pub struct ParameterChange {
+    name: String,
+    insert: Insert,
+    index: usize,
+    /// The value the parameter doesn't currently have
+    value: f32,
+    gesture: Option<egui::Id>,
+}
+
+impl ParameterChange {
+    /// Record that the parameter at `index` of `insert` was changed from `previous`
+    pub fn new(insert: &Insert, index: usize, previous: f32) -> ParameterChange {
+        let name = insert
+            .processor()
+            .parameters()
+            .get(index)
+            .map_or("Parameter", |parameter| parameter.name);
+
+        ParameterChange {
+            name: format!("Change {name}"),
+            insert: insert.clone(),
+            index,
+            value: previous,
+            gesture: None,
+        }
+    }
+
+    /// Merge the command with others recorded during the same `gesture`
+    pub fn with_gesture(mut self, gesture: egui::Id) -> ParameterChange {
+        self.gesture = Some(gesture);
+        self
+    }
+
+    fn swap(&mut self) {
+        let mut processor = self.insert.processor();
+        let value = processor.parameter(self.index);
+
+        processor.set_parameter(self.index, self.value);
+        self.value = value;
+    }
+}
+
+impl Command for ParameterChange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self, _tracks: &[Arc<RwLock<Track>>]) {
+        self.swap()
+    }
+
+    fn revert(&mut self, _tracks: &[Arc<RwLock<Track>>]) {
+        self.swap()
+    }
+
+    fn gesture(&self) -> Option<egui::Id> {
+        self.gesture
+    }
+
+    fn merge(&mut self, next: Box<dyn Command>) -> Result<(), Box<dyn Command>> {
+        // This command already holds the value from before the gesture started
+        if next.gesture().is_some() && next.gesture() == self.gesture {
+            Ok(())
+        } else {
+            Err(next)
+        }
+    }
+}
+
+/// Several commands that are undone and redone as one step
+pub struct Group {
+    name: String,
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Group {
+    pub fn new(name: impl Into<String>, commands: Vec<Box<dyn Command>>) -> Group {
+        Group {
+            name: name.into(),
+            commands,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl Command for Group {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        for command in &mut self.commands {
+            command.apply(tracks);
+        }
+    }
+
+    fn revert(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        for command in self.commands.iter_mut().rev() {
+            command.revert(tracks);
+        }
+    }
+}
+
+/// The undo and redo stacks of the session
+#[derive(Default)]
+pub struct History {
+    undo: VecDeque<Box<dyn Command>>,
+    redo: Vec<Box<dyn Command>>,
+
+    /// The gesture the last command was recorded during, if it hasn't ended yet
+    open_gesture: Option<egui::Id>,
+}
+
+impl History {
+    /// Record a command that has already been applied
+    pub fn push(&mut self, command: impl Command + 'static) {
+        self.push_boxed(Box::new(command))
+    }
+
+    pub fn push_boxed(&mut self, command: Box<dyn Command>) {
+        self.redo.clear();
+
+        let gesture = command.gesture();
+        let command = match self.undo.back_mut() {
+            Some(last) if gesture.is_some() && gesture == self.open_gesture => {
+                match last.merge(command) {
+                    Ok(()) => return,
+                    Err(command) => command,
+                }
+            }
+            _ => command,
+        };
+
+        self.undo.push_back(command);
+        self.open_gesture = gesture;
+
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Stop merging commands into the last one. Should be called when a gesture like a drag ends
+    pub fn end_gesture(&mut self) {
+        self.open_gesture = None;
+    }
+
+    pub fn undo(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        self.open_gesture = None;
+
+        if let Some(mut command) = self.undo.pop_back() {
+            command.revert(tracks);
+            self.redo.push(command);
+        }
+    }
+
+    pub fn redo(&mut self, tracks: &[Arc<RwLock<Track>>]) {
+        self.open_gesture = None;
+
+        if let Some(mut command) = self.redo.pop() {
+            command.apply(tracks);
+            self.undo.push_back(command);
+        }
+    }
+
+    /// The name of the command that would be undone next
+    pub fn undo_name(&self) -> Option<&str> {
+        self.undo.back().map(|command| command.name())
+    }
+
+    /// The name of the command that would be redone next
+    pub fn redo_name(&self) -> Option<&str> {
+        self.redo.last().map(|command| command.name())
+    }
+
+    /// Forget every command. Should be called when the tracks are replaced
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open_gesture = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+
+    fn tracks() -> Vec<Arc<RwLock<Track>>> {
+        let state = Arc::new(RwLock::new(State::new(egui::Context::default(), None)));
+        vec![Arc::new(RwLock::new(Track::new(
+            "Track",
+            Vec::new(),
+            state,
+        )))]
+    }
+
+    fn volume(tracks: &[Arc<RwLock<Track>>]) -> f32 {
+        tracks[0].read().unwrap().levels.volume
+    }
+
+    /// Set the volume of the track and record it
+    fn set_volume(
+        history: &mut History,
+        tracks: &[Arc<RwLock<Track>>],
+        volume: f32,
+        gesture: Option<egui::Id>,
+    ) {
+        let mut track = tracks[0].write().unwrap();
+        let (command, ()) = Swap::record(
+            "Volume",
+            &mut track,
+            |track| &mut track.levels,
+            |track| {
+                track.levels.volume = volume;
+            },
+        );
+
+        match gesture {
+            Some(gesture) => history.push(command.with_gesture(gesture)),
+            None => history.push(command),
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let tracks = tracks();
+        let mut history = History::default();
+        assert_eq!(history.undo_name(), None);
+
+        set_volume(&mut history, &tracks, -6.0, None);
+        set_volume(&mut history, &tracks, -12.0, None);
+        assert_eq!(history.undo_name(), Some("Volume"));
+
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), -6.0);
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), 0.0);
+        assert_eq!(history.undo_name(), None);
+
+        // Nothing left to undo
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), 0.0);
+
+        history.redo(&tracks);
+        assert_eq!(volume(&tracks), -6.0);
+        assert_eq!(history.redo_name(), Some("Volume"));
+
+        // A new change forgets what could be redone
+        set_volume(&mut history, &tracks, -3.0, None);
+        assert_eq!(history.redo_name(), None);
+        history.redo(&tracks);
+        assert_eq!(volume(&tracks), -3.0);
+
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), -6.0);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let tracks = tracks();
+        let mut history = History::default();
+
+        for step in 1..=HISTORY_LIMIT + 10 {
+            set_volume(&mut history, &tracks, -(step as f32), None);
+        }
+
+        for _ in 0..HISTORY_LIMIT {
+            assert!(history.undo_name().is_some());
+            history.undo(&tracks);
+        }
+
+        // The oldest steps were forgotten
+        assert_eq!(history.undo_name(), None);
+        assert_eq!(volume(&tracks), -10.0);
+    }
+
+    #[test]
+    fn gestures_merge() {
+        let tracks = tracks();
+        let mut history = History::default();
+        let drag = egui::Id::new("drag");
+
+        set_volume(&mut history, &tracks, -1.0, None);
+        for step in 2..10 {
+            set_volume(&mut history, &tracks, -(step as f32), Some(drag));
+        }
+        history.end_gesture();
+
+        // The same gesture starting again is a new step
+        set_volume(&mut history, &tracks, -20.0, Some(drag));
+        set_volume(&mut history, &tracks, -21.0, Some(drag));
+        // So is another gesture
+        set_volume(&mut history, &tracks, -30.0, Some(egui::Id::new("other")));
+
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), -21.0);
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), -9.0);
+        history.undo(&tracks);
+        assert_eq!(volume(&tracks), -1.0);
+
+        history.redo(&tracks);
+        assert_eq!(volume(&tracks), -9.0);
+    }
+}
@@ -0,0 +1,521 @@
+use std::{
+    f64::consts::PI,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use biquad::{Biquad, Coefficients, DirectForm2Transposed};
+
+use crate::channel::{Layout, Speakers};
+
+/// Blocks are measured every 100ms
+const STEPS_PER_SECOND: u32 = 10;
+/// Momentary loudness is measured over 400ms
+const MOMENTARY_STEPS: usize = 4;
+/// Short-term loudness is measured over 3s
+const SHORT_TERM_STEPS: usize = 30;
+
+/// Blocks quieter than this are left out of the integrated loudness and the loudness range
+const ABSOLUTE_GATE: f32 = -70.0;
+/// Blocks more than this far below the ungated loudness are left out of the integrated loudness
+const INTEGRATED_RELATIVE_GATE: f32 = -10.0;
+/// Blocks more than this far below the ungated loudness are left out of the loudness range
+const RANGE_RELATIVE_GATE: f32 = -20.0;
+/// The loudness range is the distance between these percentiles of the short-term loudness
+const RANGE_PERCENTILES: (f64, f64) = (0.10, 0.95);
+
+/// The histograms hold loudness from the absolute gate up to +10 LUFS in steps of 0.1 LU
+const HISTOGRAM_BINS: usize = 800;
+const HISTOGRAM_RESOLUTION: f32 = 0.1;
+
+/// Convert the weighted mean square of a block to LUFS
+fn energy_to_loudness(energy: f64) -> f32 {
+    (-0.691 + 10.0 * energy.log10()) as f32
+}
+
+/// How much a channel counts towards the loudness.
+///
+/// Surround channels are weighted +1.5dB and the LFE is left out, as in ITU-R BS.1770
+fn channel_weight(speaker: Option<Speakers>) -> f64 {
+    const SURROUND: Speakers = Speakers::SideLeft
+        .union(Speakers::SideRight)
+        .union(Speakers::RearLeft)
+        .union(Speakers::RearRight);
+
+    match speaker {
+        Some(speaker) if speaker == Speakers::Subwoofer => 0.0,
+        Some(speaker) if SURROUND.contains(speaker) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// The K-weighting filter of ITU-R BS.1770: a high shelf modelling the head followed by a high pass.
+///
+/// The coefficients are derived for the sample rate so the response matches the 48kHz reference everywhere
+struct KWeighting {
+    shelf: DirectForm2Transposed<f64>,
+    high_pass: DirectForm2Transposed<f64>,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> KWeighting {
+        let sample_rate = sample_rate as f64;
+
+        let shelf = {
+            let frequency = 1681.974450955533;
+            let gain = 3.999843853973347;
+            let q = 0.7071752369554196;
+
+            let k = (PI * frequency / sample_rate).tan();
+            let vh = 10f64.powf(gain / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+
+            Coefficients {
+                b0: (vh + vb * k / q + k * k) / a0,
+                b1: 2.0 * (k * k - vh) / a0,
+                b2: (vh - vb * k / q + k * k) / a0,
+                a1: 2.0 * (k * k - 1.0) / a0,
+                a2: (1.0 - k / q + k * k) / a0,
+            }
+        };
+
+        let high_pass = {
+            let frequency = 38.13547087602444;
+            let q = 0.5003270373238773;
+
+            let k = (PI * frequency / sample_rate).tan();
+            let a0 = 1.0 + k / q + k * k;
+
+            Coefficients {
+                b0: 1.0,
+                b1: -2.0,
+                b2: 1.0,
+                a1: 2.0 * (k * k - 1.0) / a0,
+                a2: (1.0 - k / q + k * k) / a0,
+            }
+        };
+
+        KWeighting {
+            shelf: DirectForm2Transposed::<f64>::new(shelf),
+            high_pass: DirectForm2Transposed::<f64>::new(high_pass),
+        }
+    }
+
+    fn run(&mut self, sample: f32) -> f64 {
+        self.high_pass.run(self.shelf.run(sample as f64))
+    }
+
+    fn reset(&mut self) {
+        self.shelf.reset_state();
+        self.high_pass.reset_state();
+    }
+}
+
+/// The blocks above the absolute gate, sorted by their loudness.
+///
+/// Gating with a histogram doesn't need to keep every block, so it works for any length without allocating
+struct Histogram {
+    /// The summed energy of the blocks in each bin
+    energy: Box<[f64; HISTOGRAM_BINS]>,
+    count: Box<[u64; HISTOGRAM_BINS]>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            energy: Box::new([0.0; HISTOGRAM_BINS]),
+            count: Box::new([0; HISTOGRAM_BINS]),
+        }
+    }
+
+    fn bin(loudness: f32) -> usize {
+        (((loudness - ABSOLUTE_GATE) / HISTOGRAM_RESOLUTION).max(0.0) as usize)
+            .min(HISTOGRAM_BINS - 1)
+    }
+
+    /// The loudness at the middle of `bin`
+    fn bin_loudness(bin: usize) -> f32 {
+        ABSOLUTE_GATE + (bin as f32 + 0.5) * HISTOGRAM_RESOLUTION
+    }
+
+    fn add(&mut self, energy: f64) {
+        let loudness = energy_to_loudness(energy);
+        if loudness <= ABSOLUTE_GATE {
+            return;
+        }
+
+        let bin = Histogram::bin(loudness);
+        self.energy[bin] += energy;
+        self.count[bin] += 1;
+    }
+
+    fn clear(&mut self) {
+        self.energy.fill(0.0);
+        self.count.fill(0);
+    }
+
+    /// The first bin above the gate `relative_gate` LU below the loudness of every block
+    fn relative_gate(&self, relative_gate: f32) -> Option<usize> {
+        let count: u64 = self.count.iter().sum();
+        if count == 0 {
+            return None;
+        }
+
+        let energy: f64 = self.energy.iter().sum();
+        let gate = energy_to_loudness(energy / count as f64) + relative_gate;
+
+        Some(Histogram::bin(gate))
+    }
+
+    /// The loudness of the blocks above the relative gate
+    fn integrated(&self) -> Option<f32> {
+        let gate = self.relative_gate(INTEGRATED_RELATIVE_GATE)?;
+
+        let count: u64 = self.count[gate..].iter().sum();
+        let energy: f64 = self.energy[gate..].iter().sum();
+
+        (count > 0).then(|| energy_to_loudness(energy / count as f64))
+    }
+
+    /// The spread of the loudness of the blocks above the relative gate, following EBU Tech 3342
+    fn range(&self) -> Option<f32> {
+        let gate = self.relative_gate(RANGE_RELATIVE_GATE)?;
+
+        let count: u64 = self.count[gate..].iter().sum();
+        if count == 0 {
+            return None;
+        }
+
+        let percentile = |fraction: f64| {
+            let target = (fraction * (count - 1) as f64).round() as u64;
+            let mut seen = 0;
+
+            for (bin, bin_count) in self.count.iter().enumerate().skip(gate) {
+                seen += bin_count;
+                if seen > target {
+                    return Histogram::bin_loudness(bin);
+                }
+            }
+
+            Histogram::bin_loudness(HISTOGRAM_BINS - 1)
+        };
+
+        Some(percentile(RANGE_PERCENTILES.1) - percentile(RANGE_PERCENTILES.0))
+    }
+}
+
+/// The readings of a loudness measurement in LUFS, and LU for the range.
+///
+/// A reading is `None` until enough audio has been measured for it, or if everything measured was below the gate
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoudnessReport {
+    pub momentary: Option<f32>,
+    pub short_term: Option<f32>,
+    pub integrated: Option<f32>,
+    pub range: Option<f32>,
+    pub max_momentary: Option<f32>,
+    pub max_short_term: Option<f32>,
+}
+
+/// Measures loudness following ITU-R BS.1770 and EBU R128.
+///
+/// Nothing is allocated after it has been created, so it can run in the audio thread
+pub struct LoudnessAnalyzer {
+    filters: Vec<KWeighting>,
+    weights: Vec<f64>,
+
+    /// The length of a 100ms step in frames
+    step_frames: usize,
+    /// The weighted sum of squares of the current step and how many frames it has so far
+    step_energy: f64,
+    step_position: usize,
+
+    /// The mean square of the last steps, as a ring buffer
+    steps: [f64; SHORT_TERM_STEPS],
+    /// The number of steps measured since the last reset
+    step_count: usize,
+
+    momentary_blocks: Histogram,
+    short_term_blocks: Histogram,
+
+    momentary: Option<f32>,
+    short_term: Option<f32>,
+    max_momentary: Option<f32>,
+    max_short_term: Option<f32>,
+}
+
+impl LoudnessAnalyzer {
+    /// Create an analyzer for interleaved audio with `channels` playing the speakers of `layout`
+    pub fn new(sample_rate: u32, channels: u16, layout: Option<Layout>) -> LoudnessAnalyzer {
+        LoudnessAnalyzer {
+            filters: (0..channels)
+                .map(|_| KWeighting::new(sample_rate))
+                .collect(),
+            weights: (0..channels as usize)
+                .map(|channel| channel_weight(Layout::channel_speaker(layout, channel)))
+                .collect(),
+
+            step_frames: (sample_rate / STEPS_PER_SECOND).max(1) as usize,
+            step_energy: 0.0,
+            step_position: 0,
+
+            steps: [0.0; SHORT_TERM_STEPS],
+            step_count: 0,
+
+            momentary_blocks: Histogram::new(),
+            short_term_blocks: Histogram::new(),
+
+            momentary: None,
+            short_term: None,
+            max_momentary: None,
+            max_short_term: None,
+        }
+    }
+
+    /// Measure an entire signal at once
+    pub fn analyze(
+        data: &[f32],
+        sample_rate: u32,
+        channels: u16,
+        layout: Option<Layout>,
+    ) -> LoudnessReport {
+        let mut analyzer = LoudnessAnalyzer::new(sample_rate, channels, layout);
+        analyzer.process(data);
+        analyzer.report()
+    }
+
+    /// Forget everything measured so far
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().for_each(KWeighting::reset);
+        self.step_energy = 0.0;
+        self.step_position = 0;
+        self.step_count = 0;
+
+        self.momentary_blocks.clear();
+        self.short_term_blocks.clear();
+
+        self.momentary = None;
+        self.short_term = None;
+        self.max_momentary = None;
+        self.max_short_term = None;
+    }
+
+    /// Measure a buffer of interleaved samples
+    pub fn process(&mut self, buffer: &[f32]) {
+        for frame in buffer.chunks_exact(self.filters.len().max(1)) {
+            for ((sample, filter), weight) in frame.iter().zip(&mut self.filters).zip(&self.weights)
+            {
+                let filtered = filter.run(*sample);
+                self.step_energy += weight * filtered * filtered;
+            }
+
+            self.step_position += 1;
+            if self.step_position == self.step_frames {
+                self.end_step();
+            }
+        }
+    }
+
+    /// Store the finished step and measure the blocks ending with it
+    fn end_step(&mut self) {
+        self.steps[self.step_count % SHORT_TERM_STEPS] = self.step_energy / self.step_frames as f64;
+        self.step_count += 1;
+        self.step_energy = 0.0;
+        self.step_position = 0;
+
+        // The mean square over the last `steps`
+        let block = |steps: usize| {
+            (self.step_count >= steps).then(|| {
+                (1..=steps)
+                    .map(|back| self.steps[(self.step_count - back) % SHORT_TERM_STEPS])
+                    .sum::<f64>()
+                    / steps as f64
+            })
+        };
+
+        if let Some(energy) = block(MOMENTARY_STEPS) {
+            let loudness = energy_to_loudness(energy);
+            self.momentary_blocks.add(energy);
+            self.momentary = Some(loudness);
+            self.max_momentary = Some(self.max_momentary.map_or(loudness, |max| max.max(loudness)));
+        }
+
+        if let Some(energy) = block(SHORT_TERM_STEPS) {
+            let loudness = energy_to_loudness(energy);
+            self.short_term_blocks.add(energy);
+            self.short_term = Some(loudness);
+            self.max_short_term = Some(
+                self.max_short_term
+                    .map_or(loudness, |max| max.max(loudness)),
+            );
+        }
+    }
+
+    pub fn report(&self) -> LoudnessReport {
+        LoudnessReport {
+            momentary: self.momentary,
+            short_term: self.short_term,
+            integrated: self.momentary_blocks.integrated(),
+            range: self.short_term_blocks.range(),
+            max_momentary: self.max_momentary,
+            max_short_term: self.max_short_term,
+        }
+    }
+}
+
+/// The latest readings of an analyzer running in the audio thread, shared with the UI without locking
+pub struct SharedLoudness {
+    /// The readings of a `LoudnessReport` in order, as f32 bits. NaN stands for `None`
+    readings: [AtomicU32; 6],
+    /// Set by the UI to have the analyzer start over
+    reset: AtomicBool,
+}
+
+impl Default for SharedLoudness {
+    fn default() -> Self {
+        let shared = SharedLoudness {
+            readings: Default::default(),
+            reset: AtomicBool::new(false),
+        };
+        shared.publish(&LoudnessReport::default());
+
+        shared
+    }
+}
+
+impl SharedLoudness {
+    pub fn publish(&self, report: &LoudnessReport) {
+        let readings = [
+            report.momentary,
+            report.short_term,
+            report.integrated,
+            report.range,
+            report.max_momentary,
+            report.max_short_term,
+        ];
+
+        for (shared, reading) in self.readings.iter().zip(readings) {
+            shared.store(reading.unwrap_or(f32::NAN).to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn read(&self) -> LoudnessReport {
+        let reading = |index: usize| {
+            Some(f32::from_bits(self.readings[index].load(Ordering::Relaxed)))
+                .filter(|reading| !reading.is_nan())
+        };
+
+        LoudnessReport {
+            momentary: reading(0),
+            short_term: reading(1),
+            integrated: reading(2),
+            range: reading(3),
+            max_momentary: reading(4),
+            max_short_term: reading(5),
+        }
+    }
+
+    /// Have the analyzer forget what it has measured the next time it runs
+    pub fn request_reset(&self) {
+        self.publish(&LoudnessReport::default());
+        self.reset.store(true, Ordering::Relaxed);
+    }
+
+    /// If a reset was requested since the last call
+    pub fn take_reset(&self) -> bool {
+        self.reset.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mixer::db_to_gain, util::sine};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A stereo 1 kHz sine made of segments of `(level in dBFS, seconds)`, like the EBU test signals
+    fn tones(segments: &[(f32, f32)]) -> LoudnessReport {
+        let data: Vec<f32> = segments
+            .iter()
+            .flat_map(|&(level, seconds)| {
+                let frames = (seconds * SAMPLE_RATE as f32) as usize;
+                sine(1000.0, db_to_gain(level), SAMPLE_RATE, frames, 2)
+            })
+            .collect();
+
+        LoudnessAnalyzer::analyze(&data, SAMPLE_RATE, 2, Some(Layout::Stereo))
+    }
+
+    /// The integrated loudness test signals of EBU Tech 3341, which should read within 0.1 LU
+    #[test]
+    fn integrated_conformance() {
+        let cases: [(&[(f32, f32)], f32); 5] = [
+            (&[(-23.0, 20.0)], -23.0),
+            (&[(-33.0, 20.0)], -33.0),
+            (&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)], -23.0),
+            (
+                &[
+                    (-72.0, 10.0),
+                    (-36.0, 10.0),
+                    (-23.0, 60.0),
+                    (-36.0, 10.0),
+                    (-72.0, 10.0),
+                ],
+                -23.0,
+            ),
+            (&[(-26.0, 20.0), (-20.0, 20.1), (-26.0, 20.0)], -23.0),
+        ];
+
+        for (segments, expected) in cases {
+            let integrated = tones(segments).integrated.unwrap();
+            assert!(
+                (integrated - expected).abs() <= 0.1,
+                "{segments:?}: {integrated} != {expected}",
+            );
+        }
+    }
+
+    /// The loudness range test signals of EBU Tech 3342, which should read within 1 LU
+    #[test]
+    fn range_conformance() {
+        let cases: [(&[(f32, f32)], f32); 4] = [
+            (&[(-20.0, 20.0), (-30.0, 20.0)], 10.0),
+            (&[(-20.0, 20.0), (-15.0, 20.0)], 5.0),
+            (&[(-40.0, 20.0), (-20.0, 20.0)], 20.0),
+            (
+                &[
+                    (-50.0, 20.0),
+                    (-35.0, 20.0),
+                    (-20.0, 20.0),
+                    (-35.0, 20.0),
+                    (-50.0, 20.0),
+                ],
+                15.0,
+            ),
+        ];
+
+        for (segments, expected) in cases {
+            let range = tones(segments).range.unwrap();
+            assert!(
+                (range - expected).abs() <= 1.0,
+                "{segments:?}: {range} != {expected}",
+            );
+        }
+    }
+
+    /// A steady tone reads the same momentary, short term and integrated loudness
+    #[test]
+    fn steady_tone() {
+        let report = tones(&[(-23.0, 20.0)]);
+
+        for loudness in [
+            report.momentary,
+            report.short_term,
+            report.max_momentary,
+            report.max_short_term,
+        ] {
+            assert!((loudness.unwrap() + 23.0).abs() <= 0.1, "{loudness:?}");
+        }
+    }
+}
@@ -3,64 +3,65 @@
 
 use std::{
     collections::HashMap,
-    path::Path,
+    io,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
+    thread::JoinHandle,
 };
 
+use backend::{BackendKind, OutputBackend};
+use bus::Output;
 use channel::Speakers;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use clip::Clip;
+use console::{Console, View};
+use cpal::traits::{DeviceTrait, HostTrait};
+use edit::Clipboard;
+use history::{Command, Group, Swap};
 use id::Id;
-use playback::start_audio;
-use sample::WaveViewSampleState;
+use loudness::LoudnessReport;
+use meter::MeterDisplay;
+use normalize::{NormalizeSettings, NormalizeTarget};
+use playback::{prepare_inserts, start_audio};
+use render::{RenderSession, RenderSettings};
+use sample::WaveViewClipState;
+use settings::{OutputDeviceInfo, OutputSettings, COMMON_SAMPLE_RATES};
 use track::Track;
+use wave_file::BitDepth;
 use wave_view::WaveViewState;
 
-use crate::state::State;
+use crate::state::{Graphics, State};
 
+mod automation;
+mod backend;
+mod bus;
 mod channel;
+mod clip;
+mod console;
+mod dynamics;
+mod edit;
+mod equalizer;
+mod fade;
+mod history;
 mod id;
+mod loudness;
+mod meter;
+mod mixer;
+mod normalize;
 mod playback;
+mod processor;
+mod project;
+mod render;
 mod resampler;
 mod sample;
+mod settings;
 mod state;
 mod track;
+mod transport;
 mod util;
+mod wave_file;
 mod wave_view;
 
-fn load_channel(n: u32, state: &Arc<RwLock<State>>) -> Arc<RwLock<Track>> {
-    let sample = Arc::new(
-        sample::Sample::load_from_file(
-            format!("res/sounds/channel{n}.wav"),
-            Some(format!("Channel {n}")),
-            state,
-        )
-        .unwrap(),
-    );
-
-    let sample2 = Arc::new(
-        sample::Sample::load_from_file(
-            format!("res/sounds/channel{n}.wav"),
-            Some(format!("Channel {n} -- 2")),
-            state,
-        )
-        .unwrap(),
-    );
-
-    let track = Arc::new(RwLock::new(Track::new(
-        format!("Track c{n}"),
-        vec![sample, sample2],
-        Speakers::from(n as u16 - 1),
-        state.clone(),
-    )));
-
-    track
-}
-
-fn load_channel_path(
-    path: impl AsRef<Path>,
-    to: Speakers,
-    state: &Arc<RwLock<State>>,
-) -> Arc<RwLock<Track>> {
+fn load_channel_path(path: impl AsRef<Path>, state: &Arc<RwLock<State>>) -> Arc<RwLock<Track>> {
     let sample = Arc::new(
         sample::Sample::load_from_file(
             // format!("res/sounds/channel{n}.wav"),
@@ -83,9 +84,7 @@ fn load_channel_path(
     let track = Arc::new(RwLock::new(Track::new(
         // format!("Track c{n}"),
         path.as_ref().file_name().unwrap().to_str().unwrap(),
-        vec![sample],
-        // Speakers::from(n as u16 - 1),
-        to,
+        vec![Clip::new(sample, 0)],
         state.clone(),
     )));
 
@@ -96,22 +95,9 @@ fn main() {
     // Log to stdout (if you run with `RUST_LOG=debug`).
     tracing_subscriber::fmt::init();
 
-    let host = cpal::default_host();
-    for device in host.output_devices().unwrap() {
-        println!("{:?}", device.name());
-        for config in device.supported_output_configs().unwrap() {
-            println!("    {config:?}");
-        }
-    }
-
-    let device = host
-        .output_devices()
-        .expect("unable to iterate output devices")
-        .find(|_| false)
-        // .find(|device| device.name().unwrap() == "Speakers (Realtek(R) Audio)")
-        // .find(|device| device.name().unwrap() == "Speakers (Focusrite USB Audio)")
-        .or_else(|| host.default_output_device())
-        .expect("Unable to find any output devices!");
+    // Use the output device chosen in a previous run. Without one, only the null and file backends work
+    let output_settings = OutputSettings::load();
+    let device = output_settings.find_device(&cpal::default_host());
 
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1920.0, 1080.0)),
@@ -133,40 +119,158 @@ fn main() {
                 .renderer
                 .write()
                 .paint_callback_resources
-                .insert(HashMap::<Id, Arc<WaveViewSampleState>>::new());
+                .insert(HashMap::<Id, Arc<WaveViewClipState>>::new());
 
             // Create application state
-            let state = Arc::new(RwLock::new(State {
-                playing: true,
-                current_time: None,
-                play_time: None,
+            let state = Arc::new(RwLock::new(State::new(
+                frame,
+                Some(Graphics {
+                    wgpu_ctx: wgpu_render_state.clone(),
+                    wave_view_state,
+                }),
+            )));
+
+            // Open the project passed on the command line, otherwise load the test sample
+            let mut project_path = std::env::args().nth(1).map(PathBuf::from);
+            let mut project_error = None;
 
-                egui_ctx: frame,
-                wgpu_ctx: wgpu_render_state.clone(),
+            let tracks = match &project_path {
+                Some(path) => match project::load(path, &state) {
+                    Ok((tracks, buses)) => {
+                        *state.read().unwrap().buses.write().unwrap() = buses;
 
-                wave_view_state,
-            }));
+                        tracks
+                    }
+                    // Start with an empty session and show what went wrong in the open dialog
+                    Err(e) => {
+                        let path = project_path.take().unwrap_or_default();
+                        project_error = Some((path, e));
 
-            // Load test sample
-            // let tracks: Vec<_> = (1..=2).map(|cn| load_channel(cn, &state)).collect();
-            
-            let tracks = vec![
-                load_channel_path("sample_short.wav", Speakers::FrontLeft, &state),
-                // load_channel_path("res/sounds/sine_inverse.wav", Speakers::FrontRight, &state),
-            ];
+                        Vec::new()
+                    }
+                },
+                None => vec![
+                    load_channel_path("sample_short.wav", &state),
+                    // load_channel_path("res/sounds/sine_inverse.wav", &state),
+                ],
+            };
 
-            let stream = start_audio(device, tracks.clone(), state.clone());
+            let mut app =
+                Application::new(cc, tracks, state, device, output_settings, project_path);
+            if let Some((path, e)) = project_error {
+                app.project_dialog = Some(ProjectDialog::Open(path.display().to_string()));
+                app.project_error = Some(e.to_string());
+            }
+            app.restart_audio();
 
-            Box::new(Application::new(cc, tracks, state, vec![stream]))
+            Box::new(app)
         }),
     )
     .unwrap();
 }
 
+/// A project file dialog that is currently open and the path typed into it
+#[derive(Clone)]
+enum ProjectDialog {
+    Open(String),
+    SaveAs(String),
+}
+
+/// The audio settings window and the devices it lists
+struct AudioSettingsWindow {
+    settings: OutputSettings,
+    devices: Vec<OutputDeviceInfo>,
+    default_device: Option<String>,
+}
+
+impl AudioSettingsWindow {
+    fn new(settings: OutputSettings) -> AudioSettingsWindow {
+        let host = cpal::default_host();
+
+        AudioSettingsWindow {
+            settings,
+            devices: OutputDeviceInfo::enumerate(&host),
+            default_device: host
+                .default_output_device()
+                .and_then(|device| device.name().ok()),
+        }
+    }
+
+    /// The device the settings currently point to
+    fn device(&self) -> Option<&OutputDeviceInfo> {
+        let name = self
+            .settings
+            .device
+            .as_ref()
+            .or(self.default_device.as_ref())?;
+        self.devices.iter().find(|device| &device.name == name)
+    }
+}
+
+/// Show the readings of a loudness measurement
+fn loudness_grid(ui: &mut egui::Ui, id: &str, report: &LoudnessReport) {
+    let reading = |reading: Option<f32>, unit: &str| {
+        reading.map_or("-".to_string(), |reading| format!("{reading:.1} {unit}"))
+    };
+
+    egui::Grid::new(id).num_columns(2).show(ui, |ui| {
+        for (name, value) in [
+            ("Momentary", reading(report.momentary, "LUFS")),
+            ("Short-term", reading(report.short_term, "LUFS")),
+            ("Integrated", reading(report.integrated, "LUFS")),
+            ("Loudness Range", reading(report.range, "LU")),
+            ("Max Momentary", reading(report.max_momentary, "LUFS")),
+            ("Max Short-term", reading(report.max_short_term, "LUFS")),
+        ] {
+            ui.label(name);
+            ui.monospace(value);
+            ui.end_row();
+        }
+    });
+}
+
+/// The width of the output meter in the top panel
+const MASTER_METER_LENGTH: f32 = 200.0;
+
 struct Application {
-    streams: Vec<cpal::Stream>,
+    device: Option<cpal::Device>,
+    output_settings: OutputSettings,
+    output: Option<Box<dyn OutputBackend>>,
+    audio_error: Option<String>,
+    audio_settings: Option<AudioSettingsWindow>,
+
     tracks: Vec<Arc<RwLock<Track>>>,
     state: Arc<RwLock<State>>,
+    master_meter: MeterDisplay,
+
+    project_path: Option<PathBuf>,
+    project_dialog: Option<ProjectDialog>,
+    project_error: Option<String>,
+
+    show_render: bool,
+    render_settings: RenderSettings,
+    render_path: String,
+    render_status: Option<String>,
+    /// The render running in the background and the file it writes to
+    bouncing: Option<(String, JoinHandle<io::Result<()>>)>,
+
+    show_loudness: bool,
+    /// The sample analyzed in the loudness window. `None` analyzes the mix
+    loudness_source: Option<Id>,
+    /// What was last analyzed and its loudness
+    loudness_analysis: Option<(String, LoudnessReport)>,
+    /// The settings of the normalize window, if it is open
+    normalize: Option<NormalizeSettings>,
+    /// Why the last clip edit failed, shown until it is dismissed
+    edit_error: Option<String>,
+
+    show_buses: bool,
+
+    /// What the central panel shows
+    view: View,
+    console: Console,
+
+    clipboard: Clipboard,
 }
 
 impl Application {
@@ -174,7 +278,9 @@ impl Application {
         cc: &eframe::CreationContext<'_>,
         tracks: Vec<Arc<RwLock<Track>>>,
         state: Arc<RwLock<State>>,
-        streams: Vec<cpal::Stream>,
+        device: Option<cpal::Device>,
+        output_settings: OutputSettings,
+        project_path: Option<PathBuf>,
     ) -> Self {
         // Set open sans regular as the default font family
         let mut fonts = egui::FontDefinitions::default();
@@ -199,38 +305,1288 @@ impl Application {
         visuals.override_text_color = Some(egui::Color32::from_rgb(255, 255, 255));
         cc.egui_ctx.set_visuals(visuals);
 
+        let render_settings = RenderSettings::for_tracks(&tracks);
+
         Application {
+            device,
+            output_settings,
             tracks,
             state,
-            streams,
+            master_meter: MeterDisplay::default(),
+            output: None,
+            audio_error: None,
+            audio_settings: None,
+
+            project_path,
+            project_dialog: None,
+            project_error: None,
+
+            show_render: false,
+            render_settings,
+            render_path: "mixdown.wav".to_string(),
+            render_status: None,
+            bouncing: None,
+
+            show_loudness: false,
+            loudness_source: None,
+            loudness_analysis: None,
+            normalize: None,
+            edit_error: None,
+
+            show_buses: false,
+
+            view: View::default(),
+            console: Console::default(),
+
+            clipboard: Clipboard::default(),
         }
     }
 
-    pub fn play(&self) {
+    pub fn play(&mut self) {
+        {
+            let mut state = self.state.write().unwrap();
+            if !state.playing {
+                state.transport.mark_play_start();
+            }
+            state.playing = true;
+        }
+
+        // The state is unlocked first since backends may wait for a buffer to finish
+        if let Some(output) = &mut self.output {
+            output.play();
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.state.write().unwrap().playing = false;
+
+        if let Some(output) = &mut self.output {
+            output.pause();
+        }
+    }
+
+    /// Replace the tracks of the session and restart the audio stream with them
+    pub fn set_tracks(&mut self, tracks: Vec<Arc<RwLock<Track>>>) {
+        self.render_settings.bit_depth = RenderSettings::for_tracks(&tracks).bit_depth;
+        self.tracks = tracks;
+        self.restart_audio();
+
         let mut state = self.state.write().unwrap();
-        state.playing = true;
+        state.history.clear();
+        state.selection = None;
+        state.selected_tracks.clear();
+        state.focused_track = None;
+    }
+
+    /// Rebuild the output backend and resamplers, e.g. after the output settings changed. Playback is paused
+    pub fn restart_audio(&mut self) {
+        self.pause();
+
+        // Drop the old backend before building a new one on the same device
+        self.output = None;
+
+        match start_audio(
+            self.device.as_ref(),
+            &self.output_settings,
+            self.tracks.clone(),
+            self.state.clone(),
+        ) {
+            Ok(output) => {
+                self.output = Some(output);
+                self.audio_error = None;
+            }
+            Err(e) => self.audio_error = Some(format!("Unable to start audio: {e}")),
+        }
+    }
+
+    /// Switch to the output backend and configuration in `settings` and remember them for the next run.
+    /// The audio is restarted unless only dither changed
+    pub fn set_output_settings(&mut self, settings: OutputSettings) {
+        let dither_only = OutputSettings {
+            dither: settings.dither,
+            ..self.output_settings.clone()
+        } == settings;
+
+        if dither_only {
+            // Dither is switched on the running stream, playback goes on
+            if let Some(output) = &mut self.output {
+                output.set_dither(settings.dither);
+            }
+            self.output_settings = settings;
+        } else {
+            // The old device is closed before the new one is opened
+            self.pause();
+            self.output = None;
+
+            self.device = settings.find_device(&cpal::default_host());
+            self.output_settings = settings;
+            self.restart_audio();
+        }
+
+        if let Err(e) = self.output_settings.save() {
+            self.audio_error = Some(format!("Unable to save settings: {e}"));
+        }
+    }
+
+    /// Open the project at `path`, replacing the current session
+    pub fn open_project(&mut self, path: impl Into<PathBuf>) -> io::Result<()> {
+        let path = path.into();
+        let (tracks, buses) = project::load(&path, &self.state)?;
+
+        *self.state.read().unwrap().buses.write().unwrap() = buses;
+        self.set_tracks(tracks);
+        self.project_path = Some(path);
+
+        Ok(())
+    }
+
+    /// Save the session to `path` and use it for subsequent saves
+    pub fn save_project(&mut self, path: impl Into<PathBuf>) -> io::Result<()> {
+        let path = path.into();
+        let buses = self.state.read().unwrap().buses.clone();
+        project::save(&path, &self.tracks, &buses.read().unwrap())?;
+
+        self.project_path = Some(path);
+
+        Ok(())
+    }
+
+    /// Save the session to the current project path, or ask for one if there is none
+    pub fn save(&mut self) {
+        match self.project_path.clone() {
+            Some(path) => {
+                self.project_error = self.save_project(path).err().map(|e| e.to_string());
+            }
+            None => self.save_as(),
+        }
+    }
+
+    /// Ask for a path to save the session to
+    pub fn save_as(&mut self) {
+        let path = self
+            .project_path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "project.json".to_string());
+
+        self.project_dialog = Some(ProjectDialog::SaveAs(path));
+    }
+
+    fn file_menu(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Open...").clicked() {
+            self.project_dialog = Some(ProjectDialog::Open(String::new()));
+            ui.close_menu();
+        }
+        if ui.button("Save").clicked() {
+            self.save();
+            ui.close_menu();
+        }
+        if ui.button("Save As...").clicked() {
+            self.save_as();
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Render...").clicked() {
+            self.show_render = true;
+            ui.close_menu();
+        }
+    }
+
+    fn audio_menu(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Output Settings...").clicked() {
+            self.audio_settings = Some(AudioSettingsWindow::new(self.output_settings.clone()));
+            ui.close_menu();
+        }
+
+        let mut settings = self.output_settings.clone();
+
+        if ui
+            .checkbox(&mut settings.dither, "Dither Integer Output")
+            .on_hover_text("Add dither when the output device uses 8 or 16 bit samples")
+            .changed()
+        {
+            self.set_output_settings(settings);
+        }
+
+        ui.separator();
+
+        if ui.button("Loudness...").clicked() {
+            self.show_loudness = true;
+            ui.close_menu();
+        }
+
+        if ui.button("Buses...").clicked() {
+            self.show_buses = true;
+            ui.close_menu();
+        }
+    }
+
+    fn audio_settings_window(&mut self, ctx: &egui::Context) {
+        let Some(window) = &mut self.audio_settings else {
+            return;
+        };
+
+        let mut open = true;
+        let mut apply = false;
+        let mut refresh = false;
+
+        egui::Window::new("Audio Settings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let device = window.device();
+                let (channel_counts, sample_rates, buffer_sizes) =
+                    if window.settings.backend == BackendKind::Device {
+                        (
+                            device.map(|device| device.channel_counts()),
+                            device.map(|device| device.sample_rates(window.settings.channels)),
+                            device.map(|device| device.buffer_sizes(window.settings.channels)),
+                        )
+                    } else {
+                        // Nothing is played, so any format can be used
+                        (
+                            Some((1..=8).collect()),
+                            Some(COMMON_SAMPLE_RATES.to_vec()),
+                            Some((5..=13).map(|power| 1 << power).collect()),
+                        )
+                    };
+                let device_names: Vec<_> = window
+                    .devices
+                    .iter()
+                    .map(|device| device.name.clone())
+                    .collect();
+
+                let settings = &mut window.settings;
+                let or_default =
+                    |value: Option<String>| value.unwrap_or_else(|| "Default".to_string());
+
+                egui::Grid::new("audio-settings-grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Backend");
+                        let previous_backend = settings.backend.clone();
+                        egui::ComboBox::from_id_source("audio-backend")
+                            .selected_text(settings.backend.name())
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(
+                                    &mut settings.backend,
+                                    BackendKind::Device,
+                                    "Device",
+                                );
+                                ui.selectable_value(
+                                    &mut settings.backend,
+                                    BackendKind::Null,
+                                    "Null",
+                                );
+                                if !matches!(settings.backend, BackendKind::File { .. }) {
+                                    ui.selectable_value(
+                                        &mut settings.backend,
+                                        BackendKind::File {
+                                            path: PathBuf::from("output.wav"),
+                                        },
+                                        "File",
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        if let BackendKind::File { path } = &mut settings.backend {
+                            ui.label("Output File");
+                            let mut text = path.display().to_string();
+                            if ui.text_edit_singleline(&mut text).changed() {
+                                *path = PathBuf::from(text);
+                            }
+                            ui.end_row();
+                        }
+
+                        // The format chosen for one backend may not be supported by another
+                        if settings.backend != previous_backend {
+                            settings.sample_rate = None;
+                            settings.channels = None;
+                            settings.buffer_size = None;
+                        }
+
+                        ui.label("Device");
+                        let previous_device = settings.device.clone();
+                        egui::ComboBox::from_id_source("audio-device")
+                            .selected_text(or_default(settings.device.clone()))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut settings.device, None, "Default");
+                                for name in device_names {
+                                    ui.selectable_value(
+                                        &mut settings.device,
+                                        Some(name.clone()),
+                                        name,
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        // The configuration of the previous device may not be supported by the new one
+                        if settings.device != previous_device {
+                            settings.sample_rate = None;
+                            settings.channels = None;
+                            settings.buffer_size = None;
+                        }
+
+                        ui.label("Channels");
+                        egui::ComboBox::from_id_source("audio-channels")
+                            .selected_text(or_default(settings.channels.map(|c| c.to_string())))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut settings.channels, None, "Default");
+                                for channels in channel_counts.into_iter().flatten() {
+                                    ui.selectable_value(
+                                        &mut settings.channels,
+                                        Some(channels),
+                                        channels.to_string(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Sample Rate");
+                        egui::ComboBox::from_id_source("audio-sample-rate")
+                            .selected_text(or_default(
+                                settings.sample_rate.map(|rate| format!("{rate} Hz")),
+                            ))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut settings.sample_rate, None, "Default");
+                                for rate in sample_rates.into_iter().flatten() {
+                                    ui.selectable_value(
+                                        &mut settings.sample_rate,
+                                        Some(rate),
+                                        format!("{rate} Hz"),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Buffer Size");
+                        egui::ComboBox::from_id_source("audio-buffer-size")
+                            .selected_text(or_default(
+                                settings.buffer_size.map(|size| format!("{size} frames")),
+                            ))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut settings.buffer_size, None, "Default");
+                                for size in buffer_sizes.into_iter().flatten() {
+                                    ui.selectable_value(
+                                        &mut settings.buffer_size,
+                                        Some(size),
+                                        format!("{size} frames"),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Dither");
+                        ui.checkbox(&mut settings.dither, "");
+                        ui.end_row();
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        apply = true;
+                    }
+                    if ui.button("Refresh Devices").clicked() {
+                        refresh = true;
+                    }
+                });
+
+                ui.label(format!(
+                    "Playing at {} Hz",
+                    self.state.read().unwrap().transport.sample_rate()
+                ));
+
+                if let Some(error) = &self.audio_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+        if refresh {
+            let settings = window.settings.clone();
+            *window = AudioSettingsWindow::new(settings);
+        }
+
+        if apply {
+            let settings = window.settings.clone();
+            self.set_output_settings(settings);
+        }
+
+        if !open {
+            self.audio_settings = None;
+        }
+    }
+
+    fn edit_menu(&mut self, ui: &mut egui::Ui) {
+        let (undo_name, redo_name) = {
+            let state = self.state.read().unwrap();
+            (
+                state.history.undo_name().map(str::to_string),
+                state.history.redo_name().map(str::to_string),
+            )
+        };
+
+        let undo_text = match &undo_name {
+            Some(name) => format!("Undo {name}"),
+            None => "Undo".to_string(),
+        };
+        if ui
+            .add_enabled(undo_name.is_some(), egui::Button::new(undo_text))
+            .clicked()
+        {
+            self.undo();
+            ui.close_menu();
+        }
+
+        let redo_text = match &redo_name {
+            Some(name) => format!("Redo {name}"),
+            None => "Redo".to_string(),
+        };
+        if ui
+            .add_enabled(redo_name.is_some(), egui::Button::new(redo_text))
+            .clicked()
+        {
+            self.redo();
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Loop Selection").clicked() {
+            self.toggle_loop();
+            ui.close_menu();
+        }
+        if ui.button("Split at Cursor").clicked() {
+            self.split();
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Cut").clicked() {
+            self.cut();
+            ui.close_menu();
+        }
+        if ui.button("Copy").clicked() {
+            self.copy();
+            ui.close_menu();
+        }
+        if ui.button("Paste").clicked() {
+            self.paste();
+            ui.close_menu();
+        }
+        if ui.button("Delete").clicked() {
+            self.delete(false);
+            ui.close_menu();
+        }
+        if ui.button("Ripple Delete").clicked() {
+            self.delete(true);
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Normalize...").clicked() {
+            self.normalize = Some(NormalizeSettings::default());
+            ui.close_menu();
+        }
+        if ui.button("Apply Inserts to Clips").clicked() {
+            self.apply_inserts();
+            ui.close_menu();
+        }
+    }
+
+    /// The indices of the tracks edits apply to: the selected tracks, or the focused track if none are selected
+    fn edit_targets(&self) -> Vec<usize> {
+        let state = self.state.read().unwrap();
+
+        self.tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| {
+                let id = track.read().unwrap().id;
+
+                if state.selected_tracks.is_empty() {
+                    state.focused_track == Some(id)
+                } else {
+                    state.selected_tracks.contains(&id)
+                }
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Run `edit` on each of the tracks at `targets` and record the changes to their clips as one undo step.
+    ///
+    /// `edit` returns `None` if it left the clips of the track alone, so nothing is recorded for it
+    fn edit_clips<R>(
+        &mut self,
+        name: &str,
+        targets: &[usize],
+        mut edit: impl FnMut(&mut Track) -> Option<R>,
+    ) -> Vec<Option<R>> {
+        let mut results = Vec::with_capacity(targets.len());
+        let mut commands: Vec<Box<dyn Command>> = Vec::with_capacity(targets.len());
+
+        for &index in targets {
+            let mut track = self.tracks[index].write().unwrap();
+            let (command, result) =
+                Swap::record(name, &mut track, |track| &mut track.clips, &mut edit);
+
+            if result.is_some() {
+                commands.push(Box::new(command));
+            }
+            results.push(result);
+        }
+
+        let group = Group::new(name, commands);
+        if !group.is_empty() {
+            self.state.write().unwrap().history.push(group);
+        }
+
+        results
+    }
+
+    /// Split the clips on the targeted tracks at the edit cursor
+    pub fn split(&mut self) {
+        let cursor = self.state.read().unwrap().transport.time();
+        let targets = self.edit_targets();
+
+        self.edit_clips("Split", &targets, |track| {
+            track.split_at(cursor).then_some(())
+        });
+    }
+
+    /// Copy the selection of the targeted tracks to the clipboard
+    pub fn copy(&mut self) {
+        let Some(selection) = self.state.read().unwrap().selection.clone() else {
+            return;
+        };
+
+        let targets = self.edit_targets();
+        let Some(&first) = targets.first() else {
+            return;
+        };
+
+        self.clipboard = Clipboard {
+            tracks: targets
+                .into_iter()
+                .map(|index| {
+                    let clips = self.tracks[index].read().unwrap().copy_range(&selection);
+                    (index - first, clips)
+                })
+                .collect(),
+        };
+    }
 
-        self.streams.iter().for_each(|s| s.play().unwrap());
+    /// Move the selection of the targeted tracks to the clipboard, leaving a gap
+    pub fn cut(&mut self) {
+        let Some(selection) = self.state.write().unwrap().selection.take() else {
+            return;
+        };
+
+        let targets = self.edit_targets();
+        let Some(&first) = targets.first() else {
+            return;
+        };
+
+        let clips = self.edit_clips("Cut", &targets, |track| track.cut_range(&selection));
+
+        self.clipboard = Clipboard {
+            tracks: targets
+                .into_iter()
+                .map(|index| index - first)
+                .zip(clips.into_iter().map(Option::unwrap_or_default))
+                .collect(),
+        };
+    }
+
+    /// Remove the selection from the targeted tracks. With `ripple` later clips are moved back to close the gap
+    pub fn delete(&mut self, ripple: bool) {
+        let Some(selection) = self.state.write().unwrap().selection.take() else {
+            return;
+        };
+
+        let targets = self.edit_targets();
+        let name = if ripple { "Ripple Delete" } else { "Delete" };
+
+        self.edit_clips(name, &targets, |track| {
+            track.delete_range(&selection, ripple).then_some(())
+        });
+    }
+
+    /// Normalize the clips of the targeted tracks that overlap the selection, or all of their clips without a selection
+    pub fn normalize_clips(&mut self, settings: &NormalizeSettings) {
+        let selection = self.state.read().unwrap().selection.clone();
+        let targets = self.edit_targets();
+
+        self.edit_clips("Normalize", &targets, |track| {
+            track
+                .normalize_clips(selection.as_ref(), settings)
+                .then_some(())
+        });
+    }
+
+    /// Process the clips of the targeted tracks that overlap the selection, or all of their clips without a selection,
+    /// through the inserts of their track, and bypass the inserts so the result sounds the same
+    pub fn apply_inserts(&mut self) {
+        let selection = self.state.read().unwrap().selection.clone();
+        let targets = self.edit_targets();
+        let name = "Apply Inserts";
+
+        let mut commands: Vec<Box<dyn Command>> = Vec::with_capacity(targets.len() * 2);
+        for index in targets {
+            let mut track = self.tracks[index].write().unwrap();
+            let (clips, result) = Swap::record(
+                name,
+                &mut track,
+                |track| &mut track.clips,
+                |track| track.apply_inserts(selection.as_ref()),
+            );
+
+            match result {
+                Ok(true) => {
+                    let (inserts, ()) = Swap::record(
+                        name,
+                        &mut track,
+                        |track| &mut track.inserts,
+                        |track| {
+                            for insert in &mut track.inserts {
+                                insert.bypass = true;
+                            }
+                        },
+                    );
+
+                    commands.push(Box::new(clips));
+                    commands.push(Box::new(inserts));
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    self.edit_error = Some(format!(
+                        "Unable to apply the inserts of {}: {e}",
+                        track.name
+                    ));
+                    break;
+                }
+            }
+        }
+
+        let group = Group::new(name, commands);
+        if !group.is_empty() {
+            self.state.write().unwrap().history.push(group);
+
+            // The new samples need resamplers
+            self.restart_audio();
+        }
+    }
+
+    fn edit_error_window(&mut self, ctx: &egui::Context) {
+        let Some(error) = &self.edit_error else {
+            return;
+        };
+
+        let mut open = true;
+        let mut dismissed = false;
+
+        egui::Window::new("Unable to Edit")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.colored_label(egui::Color32::RED, error);
+
+                if ui.button("OK").clicked() {
+                    dismissed = true;
+                }
+            });
+
+        if dismissed || !open {
+            self.edit_error = None;
+        }
+    }
+
+    fn normalize_window(&mut self, ctx: &egui::Context) {
+        let Some(settings) = &mut self.normalize else {
+            return;
+        };
+
+        let mut open = true;
+        let mut normalize = false;
+
+        let has_selection = self.state.read().unwrap().selection.is_some();
+
+        egui::Window::new("Normalize")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("normalize-grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Target");
+                        egui::ComboBox::from_id_source("normalize-target")
+                            .selected_text(settings.target.name())
+                            .show_ui(ui, |ui| {
+                                for target in NormalizeTarget::ALL {
+                                    if ui
+                                        .selectable_label(settings.target == target, target.name())
+                                        .clicked()
+                                        && settings.target != target
+                                    {
+                                        settings.target = target;
+                                        settings.level = target.default_level();
+                                    }
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Level");
+                        ui.add(
+                            egui::DragValue::new(&mut settings.level)
+                                .clamp_range(-70.0..=0.0)
+                                .speed(0.1)
+                                .fixed_decimals(1)
+                                .suffix(format!(" {}", settings.target.unit())),
+                        );
+                        ui.end_row();
+
+                        ui.label("Link Channels");
+                        ui.checkbox(&mut settings.linked, "")
+                            .on_hover_text("Give every channel the same gain");
+                        ui.end_row();
+                    });
+
+                ui.label(if has_selection {
+                    "Clips of the selected tracks that overlap the selection"
+                } else {
+                    "Every clip of the selected tracks"
+                });
+
+                if ui.button("Normalize").clicked() {
+                    normalize = true;
+                }
+            });
+
+        let settings = *settings;
+        if !open {
+            self.normalize = None;
+        }
+
+        if normalize {
+            self.normalize_clips(&settings);
+        }
+    }
+
+    /// Paste the clipboard at the edit cursor, starting at the focused track
+    pub fn paste(&mut self) {
+        if self.clipboard.is_empty() {
+            return;
+        }
+
+        let (cursor, focused) = {
+            let state = self.state.read().unwrap();
+            (state.transport.time(), state.focused_track)
+        };
+
+        let first = self
+            .tracks
+            .iter()
+            .position(|track| Some(track.read().unwrap().id) == focused)
+            .unwrap_or(0);
+
+        let clipboard = std::mem::take(&mut self.clipboard);
+        let (targets, clips): (Vec<_>, Vec<_>) = clipboard
+            .tracks
+            .iter()
+            .filter(|(offset, _)| first + offset < self.tracks.len())
+            .map(|(offset, clips)| (first + offset, clips))
+            .unzip();
+
+        let mut clips = clips.into_iter();
+        self.edit_clips("Paste", &targets, |track| {
+            let clips = clips.next()?;
+            track.paste(clips, cursor).then_some(())
+        });
+
+        self.clipboard = clipboard;
     }
 
-    pub fn pause(&self) {
+    pub fn undo(&mut self) {
+        self.state.write().unwrap().history.undo(&self.tracks);
+    }
+
+    pub fn redo(&mut self) {
+        self.state.write().unwrap().history.redo(&self.tracks);
+    }
+
+    /// Clear the selection
+    pub fn deselect(&mut self) {
         let mut state = self.state.write().unwrap();
-        state.playing = false;
+        state.selection = None;
+        state.selected_tracks.clear();
+    }
+
+    fn project_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.project_dialog else {
+            return;
+        };
+
+        let (title, path) = match dialog {
+            ProjectDialog::Open(path) => ("Open Project", path),
+            ProjectDialog::SaveAs(path) => ("Save Project As", path),
+        };
+
+        let mut open = true;
+        let mut confirmed = false;
+
+        egui::Window::new(title)
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    let response = ui.text_edit_singleline(path);
+
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        confirmed = true;
+                    }
+                });
+
+                if let Some(error) = &self.project_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                if ui.button("OK").clicked() {
+                    confirmed = true;
+                }
+            });
+
+        if confirmed {
+            let result = match self.project_dialog.clone() {
+                Some(ProjectDialog::Open(path)) => self.open_project(path),
+                Some(ProjectDialog::SaveAs(path)) => self.save_project(path),
+                None => Ok(()),
+            };
+
+            // Keep the dialog open to show what went wrong
+            match result {
+                Ok(()) => {
+                    self.project_dialog = None;
+                    self.project_error = None;
+                }
+                Err(e) => self.project_error = Some(e.to_string()),
+            }
+        } else if !open {
+            self.project_dialog = None;
+            self.project_error = None;
+        }
+    }
+
+    /// Start rendering the session to `render_path` with the current render settings.
+    ///
+    /// The render runs on a thread of its own on a copy of the session, `poll_bounce` picks up the result
+    pub fn bounce(&mut self) {
+        if self.bouncing.is_some() {
+            return;
+        }
+
+        let buses = self.state.read().unwrap().buses.clone();
+        let session = RenderSession::new(&self.tracks, &buses.read().unwrap());
+        let egui_ctx = self.state.read().unwrap().egui_ctx.clone();
+        let path = self.render_path.clone();
+        let settings = self.render_settings;
+
+        let handle = std::thread::spawn(move || {
+            let result = render::bounce(&session, &path, &settings);
+            egui_ctx.request_repaint();
+
+            result
+        });
+
+        self.render_status = Some(format!("Rendering to {}...", self.render_path));
+        self.bouncing = Some((self.render_path.clone(), handle));
+    }
+
+    /// Show the result of the render once it is done
+    fn poll_bounce(&mut self) {
+        if !self
+            .bouncing
+            .as_ref()
+            .is_some_and(|(_, handle)| handle.is_finished())
+        {
+            return;
+        }
+
+        let Some((path, handle)) = self.bouncing.take() else {
+            return;
+        };
+
+        self.render_status = Some(match handle.join() {
+            Ok(Ok(())) => format!("Rendered to {path}"),
+            Ok(Err(e)) => format!("Failed to render: {e}"),
+            Err(_) => "Failed to render: the render panicked".to_string(),
+        });
+    }
 
-        self.streams.iter().for_each(|s| s.pause().unwrap());
+    fn render_window(&mut self, ctx: &egui::Context) {
+        self.poll_bounce();
+
+        let mut open = self.show_render;
+        let mut bounce = false;
+
+        egui::Window::new("Render")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let settings = &mut self.render_settings;
+
+                egui::Grid::new("render-settings-grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("File");
+                        ui.text_edit_singleline(&mut self.render_path);
+                        ui.end_row();
+
+                        ui.label("Sample Rate");
+                        egui::ComboBox::from_id_source("render-sample-rate")
+                            .selected_text(format!("{} Hz", settings.sample_rate))
+                            .show_ui(ui, |ui| {
+                                for rate in [22050, 44100, 48000, 88200, 96000, 192000] {
+                                    ui.selectable_value(
+                                        &mut settings.sample_rate,
+                                        rate,
+                                        format!("{rate} Hz"),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Channels");
+                        ui.add(
+                            egui::DragValue::new(&mut settings.channels)
+                                .clamp_range(1..=Speakers::MAX_COUNT as u16),
+                        );
+                        ui.end_row();
+
+                        ui.label("Bit Depth");
+                        egui::ComboBox::from_id_source("render-bit-depth")
+                            .selected_text(settings.bit_depth.name())
+                            .show_ui(ui, |ui| {
+                                for bit_depth in BitDepth::ALL {
+                                    ui.selectable_value(
+                                        &mut settings.bit_depth,
+                                        bit_depth,
+                                        bit_depth.name(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Dither");
+                        ui.add_enabled(
+                            settings.bit_depth.is_int(),
+                            egui::Checkbox::new(&mut settings.dither, ""),
+                        );
+                        ui.end_row();
+                    });
+
+                if ui
+                    .add_enabled(self.bouncing.is_none(), egui::Button::new("Render"))
+                    .clicked()
+                {
+                    bounce = true;
+                }
+
+                if let Some(status) = &self.render_status {
+                    ui.label(status);
+                }
+            });
+
+        self.show_render = open;
+
+        if bounce {
+            self.bounce();
+        }
+    }
+
+    /// Measure the loudness of the source chosen in the loudness window
+    fn analyze_loudness(&mut self) {
+        let sample = self.loudness_source.and_then(|id| {
+            self.tracks.iter().find_map(|track| {
+                let track = track.read().unwrap();
+                track
+                    .clips
+                    .iter()
+                    .find(|clip| clip.sample.id == id)
+                    .map(|clip| clip.sample.clone())
+            })
+        });
+
+        let buses = self.state.read().unwrap().buses.clone();
+        self.loudness_analysis = Some(match sample {
+            Some(sample) => (sample.name.clone(), sample.loudness()),
+            None => (
+                "Mix".to_string(),
+                render::loudness(
+                    &RenderSession::new(&self.tracks, &buses.read().unwrap()),
+                    &self.render_settings,
+                ),
+            ),
+        });
+    }
+
+    fn loudness_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_loudness;
+        let mut analyze = false;
+
+        egui::Window::new("Loudness")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let master_loudness = self.state.read().unwrap().master_loudness.clone();
+
+                ui.heading("Output");
+                loudness_grid(ui, "loudness-output-grid", &master_loudness.read());
+                if ui.button("Reset").clicked() {
+                    master_loudness.request_reset();
+                }
+
+                ui.separator();
+                ui.heading("Analyze");
+
+                let mut samples: Vec<_> = Vec::new();
+                for track in &self.tracks {
+                    for clip in &track.read().unwrap().clips {
+                        if !samples.iter().any(|(id, _)| *id == clip.sample.id) {
+                            samples.push((clip.sample.id, clip.sample.name.clone()));
+                        }
+                    }
+                }
+
+                let source_name = |source: Option<Id>| {
+                    source
+                        .and_then(|id| samples.iter().find(|(sample, _)| *sample == id))
+                        .map_or("Mix".to_string(), |(_, name)| name.clone())
+                };
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("loudness-source")
+                        .selected_text(source_name(self.loudness_source))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.loudness_source, None, "Mix")
+                                .on_hover_text("Render the mix with the render settings");
+                            for (id, name) in &samples {
+                                ui.selectable_value(&mut self.loudness_source, Some(*id), name);
+                            }
+                        });
+
+                    if ui.button("Analyze").clicked() {
+                        analyze = true;
+                    }
+                });
+
+                if let Some((name, report)) = &self.loudness_analysis {
+                    ui.label(name);
+                    loudness_grid(ui, "loudness-analysis-grid", report);
+                }
+            });
+
+        self.show_loudness = open;
+
+        if analyze {
+            self.analyze_loudness();
+        }
+    }
+
+    /// Draw the editor of the master and aux buses
+    fn buses_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_buses;
+        let mut removed = None;
+
+        egui::Window::new("Buses")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                let (buses, channels) = {
+                    let state = self.state.read().unwrap();
+                    (state.buses.clone(), state.output_channels)
+                };
+                let channels = match channels {
+                    0 => 2,
+                    channels => channels,
+                };
+
+                let tracks: Vec<_> = self
+                    .tracks
+                    .iter()
+                    .map(|track| {
+                        let track = track.read().unwrap();
+                        (track.id, track.name.clone())
+                    })
+                    .collect();
+
+                removed = buses.write().unwrap().ui(ui, channels, &tracks);
+            });
+
+        self.show_buses = open;
+
+        // Tracks that went to the removed bus go to the master instead
+        if let Some(bus) = removed {
+            for track in &self.tracks {
+                let mut track = track.write().unwrap();
+
+                if track.output == Output::Bus(bus) {
+                    track.output = Output::Master;
+                }
+                track.sends.retain(|send| send.bus != bus);
+            }
+        }
+    }
+
+    /// Pause and return to where playback was started from, or to the beginning if already there
+    pub fn stop(&mut self) {
+        self.pause();
+
+        self.state.read().unwrap().transport.return_to_start();
     }
 
-    pub fn stop(&self) {
+    /// Loop the selection if there is one, otherwise turn looping of the existing loop region on or off
+    pub fn toggle_loop(&mut self) {
         let mut state = self.state.write().unwrap();
-        state.playing = false;
 
-        self.streams.iter().for_each(|s| s.pause().unwrap());
+        if let Some(selection) = state.selection.clone() {
+            let transport = state.transport.clone();
+            let region = transport.frames_at(selection.start)..transport.frames_at(selection.end);
+
+            if !region.is_empty() {
+                state.loop_region = Some(region);
+                state.looping = true;
+                return;
+            }
+        }
+
+        if state.loop_region.is_some() {
+            state.looping = !state.looping;
+        }
+    }
+
+    pub fn toggle_playback(&mut self) {
+        if self.state.read().unwrap().playing {
+            self.pause();
+        } else {
+            self.play();
+        }
     }
 }
 
 impl eframe::App for Application {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // File shortcuts
+        let (open, save, save_as) = ctx.input_mut(|input| {
+            (
+                input.consume_key(egui::Modifiers::COMMAND, egui::Key::O),
+                input.consume_key(egui::Modifiers::COMMAND, egui::Key::S),
+                input.consume_key(
+                    egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                    egui::Key::S,
+                ),
+            )
+        });
+
+        if open {
+            self.project_dialog = Some(ProjectDialog::Open(String::new()));
+        }
+        if save {
+            self.save();
+        }
+        if save_as {
+            self.save_as();
+        }
+
+        // Transport and edit shortcuts. These are ignored while typing in a text field
+        if !ctx.wants_keyboard_input() {
+            let (
+                toggle_playback,
+                stop,
+                toggle_loop,
+                undo,
+                redo,
+                split,
+                cut,
+                copy,
+                paste,
+                delete,
+                ripple_delete,
+                deselect,
+            ) = ctx.input_mut(|input| {
+                (
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::Space),
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::L),
+                    input.consume_key(egui::Modifiers::COMMAND, egui::Key::Z),
+                    input.consume_key(
+                        egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                        egui::Key::Z,
+                    ),
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::S),
+                    input.consume_key(egui::Modifiers::COMMAND, egui::Key::X),
+                    input.consume_key(egui::Modifiers::COMMAND, egui::Key::C),
+                    input.consume_key(egui::Modifiers::COMMAND, egui::Key::V),
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::Delete)
+                        || input.consume_key(egui::Modifiers::NONE, egui::Key::Backspace),
+                    input.consume_key(egui::Modifiers::SHIFT, egui::Key::Delete)
+                        || input.consume_key(egui::Modifiers::SHIFT, egui::Key::Backspace),
+                    input.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                )
+            });
+
+            if toggle_playback {
+                self.toggle_playback();
+            }
+            if stop {
+                self.stop();
+            }
+            if toggle_loop {
+                self.toggle_loop();
+            }
+            if undo {
+                self.undo();
+            }
+            if redo {
+                self.redo();
+            }
+            if split {
+                self.split();
+            }
+            if cut {
+                self.cut();
+            }
+            if copy {
+                self.copy();
+            }
+            if paste {
+                self.paste();
+            }
+            if delete {
+                self.delete(false);
+            }
+            if ripple_delete {
+                self.delete(true);
+            }
+            if deselect {
+                self.deselect();
+            }
+        }
+
         egui::TopBottomPanel::top("editor-main-heading").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| self.file_menu(ui));
+                ui.menu_button("Edit", |ui| self.edit_menu(ui));
+                ui.menu_button("Audio", |ui| self.audio_menu(ui));
+            });
+
             ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
                 ui.heading("Audio Editor");
 
@@ -240,17 +1596,98 @@ impl eframe::App for Application {
                 if ui.button("Pause").clicked() {
                     self.pause()
                 }
+                if ui.button("Stop").clicked() {
+                    self.stop()
+                }
+
+                let (mut looping, has_loop) = {
+                    let state = self.state.read().unwrap();
+                    (state.looping, state.loop_region.is_some())
+                };
+                let loop_button = ui
+                    .add_enabled(has_loop, egui::SelectableLabel::new(looping, "Loop"))
+                    .on_disabled_hover_text("Select a range and press L to loop it");
+                if loop_button.clicked() {
+                    looping = !looping;
+                    self.state.write().unwrap().looping = looping;
+                }
+                if ui.button("Render").clicked() {
+                    self.show_render = !self.show_render;
+                }
+                if ui.button("Loudness").clicked() {
+                    self.show_loudness = !self.show_loudness;
+                }
+                if ui.button("Buses").clicked() {
+                    self.show_buses = !self.show_buses;
+                }
+
+                ui.separator();
+                ui.selectable_value(&mut self.view, View::Timeline, "Timeline");
+                ui.selectable_value(&mut self.view, View::Mixer, "Mixer");
+
+                // The console has its own master meter
+                if self.view == View::Timeline {
+                    let (meter, channels) = {
+                        let state = self.state.read().unwrap();
+                        (state.master_meter.clone(), state.output_channels)
+                    };
+                    self.master_meter
+                        .ui(ui, &meter, channels, MASTER_METER_LENGTH, false);
+                }
             });
         });
 
+        self.project_dialog(ctx);
+        self.render_window(ctx);
+        self.loudness_window(ctx);
+        self.buses_window(ctx);
+        self.normalize_window(ctx);
+        self.edit_error_window(ctx);
+        self.audio_settings_window(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            if self.view == View::Mixer {
+                self.console.ui(
+                    ui,
+                    &self.tracks,
+                    &self.state,
+                    &mut self.master_meter,
+                    &mut self.show_buses,
+                );
+                return;
+            }
+
             ui.vertical(|ui| {
                 ui.spacing_mut().item_spacing = egui::vec2(0.0, 10.0);
+
+                let names: Vec<_> = self
+                    .tracks
+                    .iter()
+                    .map(|track| {
+                        let track = track.read().unwrap();
+                        (track.id, track.name.clone())
+                    })
+                    .collect();
+
                 for track in &mut self.tracks {
                     let mut track = track.write().unwrap();
-                    track.ui(ui);
+                    track.ui(ui, &names);
                 }
-            })
+            });
         });
+
+        // Lanes are written during playback whichever view is shown
+        for track in &self.tracks {
+            track.write().unwrap().update_automation(ctx);
+        }
+
+        // Inserts added to a bus or brought back by an undo are prepared here, so the audio thread never has to
+        let (buses, format) = {
+            let state = self.state.read().unwrap();
+            (state.buses.clone(), state.insert_format())
+        };
+        if let Some(format) = format {
+            prepare_inserts(&self.tracks, &buses.read().unwrap(), format);
+        }
     }
 }
@@ -0,0 +1,411 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    channel::Speakers,
+    mixer::{db_to_gain, gain_to_db},
+};
+
+/// The 4x oversampling interpolation filter from ITU-R BS.1770, one row per phase
+///
+/// The coefficients are those of the table in BS.1770-4 Annex 2, written with as many digits as an `f32` needs.
+/// The table's values are multiples of 2^-16 so the rounding loses nothing
+const TRUE_PEAK_FILTER: [[f32; TRUE_PEAK_TAPS]; 4] = [
+    [
+        0.0017089844,
+        0.010986328,
+        -0.01965332,
+        0.033203125,
+        -0.059448242,
+        0.1373291,
+        0.97216797,
+        -0.10229492,
+        0.047607422,
+        -0.026611328,
+        0.014892578,
+        -0.008300781,
+    ],
+    [
+        -0.029174805,
+        0.029296875,
+        -0.051757812,
+        0.08911133,
+        -0.1665039,
+        0.4650879,
+        0.77978516,
+        -0.20031738,
+        0.1015625,
+        -0.05822754,
+        0.033081055,
+        -0.018920898,
+    ],
+    [
+        -0.018920898,
+        0.033081055,
+        -0.05822754,
+        0.1015625,
+        -0.20031738,
+        0.77978516,
+        0.4650879,
+        -0.1665039,
+        0.08911133,
+        -0.051757812,
+        0.029296875,
+        -0.029174805,
+    ],
+    [
+        -0.008300781,
+        0.014892578,
+        -0.026611328,
+        0.047607422,
+        -0.10229492,
+        0.97216797,
+        0.1373291,
+        -0.059448242,
+        0.033203125,
+        -0.01965332,
+        0.010986328,
+        0.0017089844,
+    ],
+];
+const TRUE_PEAK_TAPS: usize = 12;
+/// How many frames late `TruePeak` finds the peaks between samples
+pub const TRUE_PEAK_DELAY: usize = TRUE_PEAK_TAPS / 2;
+
+/// How long the highest peak stays up
+const PEAK_HOLD: Duration = Duration::from_millis(1500);
+/// How fast the peak bars fall in dB per second
+const PEAK_FALL: f32 = 20.0;
+/// The time constant of the RMS average in seconds
+const RMS_TIME: f32 = 0.3;
+/// The level at the bottom of a meter in dB
+const METER_FLOOR_DB: f32 = -60.0;
+
+/// The width of the bar of each channel
+const BAR_WIDTH: f32 = 5.0;
+const BAR_GAP: f32 = 1.0;
+/// The size of the clip indicators at the end of the bars
+const CLIP_SIZE: f32 = 6.0;
+
+/// Levels measured by the audio thread that haven't been shown yet.
+///
+/// The audio thread adds its measurements and the UI takes them, neither of them lock
+#[derive(Default)]
+pub struct Meter {
+    channels: [MeterChannel; Speakers::MAX_COUNT],
+}
+
+/// The levels of one channel. Gains are stored as f32 bits, which compare like the floats for positive values
+#[derive(Default)]
+struct MeterChannel {
+    peak: AtomicU32,
+    true_peak: AtomicU32,
+    /// Stored as f64 bits
+    sum_squares: AtomicU64,
+    frames: AtomicU64,
+}
+
+/// The levels of a channel since they were last taken from a `Meter`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Levels {
+    pub peak: f32,
+    pub true_peak: f32,
+    pub sum_squares: f64,
+    pub frames: u64,
+}
+
+impl Meter {
+    /// Add the levels measured for `channel`
+    pub fn add(&self, channel: usize, levels: &Levels) {
+        let Some(meter) = self.channels.get(channel) else {
+            return;
+        };
+
+        meter
+            .peak
+            .fetch_max(levels.peak.to_bits(), Ordering::Relaxed);
+        meter
+            .true_peak
+            .fetch_max(levels.true_peak.to_bits(), Ordering::Relaxed);
+        let _ = meter
+            .sum_squares
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + levels.sum_squares).to_bits())
+            });
+        meter.frames.fetch_add(levels.frames, Ordering::Relaxed);
+    }
+
+    /// Take the levels measured for `channel` since the last call
+    pub fn take(&self, channel: usize) -> Levels {
+        let Some(meter) = self.channels.get(channel) else {
+            return Levels::default();
+        };
+
+        Levels {
+            peak: f32::from_bits(meter.peak.swap(0, Ordering::Relaxed)),
+            true_peak: f32::from_bits(meter.true_peak.swap(0, Ordering::Relaxed)),
+            sum_squares: f64::from_bits(meter.sum_squares.swap(0, Ordering::Relaxed)),
+            frames: meter.frames.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Measures a signal in the audio thread. The levels are sent to a `Meter` after every buffer
+#[derive(Default)]
+pub struct MeterProcessor {
+    channels: [ChannelProcessor; Speakers::MAX_COUNT],
+}
+
+#[derive(Default)]
+struct ChannelProcessor {
+    levels: Levels,
+    true_peak: TruePeak,
+}
+
+/// Estimates the peaks between samples by oversampling a signal
+#[derive(Default)]
+pub struct TruePeak {
+    /// The last samples for the filter. Every sample is stored twice so the taps are always contiguous
+    history: [f32; TRUE_PEAK_TAPS * 2],
+    position: usize,
+}
+
+impl TruePeak {
+    /// Add the next sample and get the highest absolute level around it, including the sample itself
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.position = (self.position + 1) % TRUE_PEAK_TAPS;
+        self.history[self.position] = sample;
+        self.history[self.position + TRUE_PEAK_TAPS] = sample;
+
+        let taps = &self.history[self.position + 1..=self.position + TRUE_PEAK_TAPS];
+        TRUE_PEAK_FILTER
+            .iter()
+            .map(|phase| {
+                phase
+                    .iter()
+                    .zip(taps)
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum::<f32>()
+                    .abs()
+            })
+            .fold(sample.abs(), f32::max)
+    }
+
+    pub fn reset(&mut self) {
+        *self = TruePeak::default();
+    }
+}
+
+impl MeterProcessor {
+    /// Measure a sample of `channel`
+    pub fn measure(&mut self, channel: usize, sample: f32) {
+        let Some(channel) = self.channels.get_mut(channel) else {
+            return;
+        };
+
+        let true_peak = channel.true_peak.process(sample);
+
+        let levels = &mut channel.levels;
+        levels.peak = levels.peak.max(sample.abs());
+        levels.true_peak = levels.true_peak.max(true_peak);
+        levels.sum_squares += (sample * sample) as f64;
+        levels.frames += 1;
+    }
+
+    /// Measure a buffer of interleaved samples
+    pub fn measure_buffer(&mut self, buffer: &[f32], channels: usize) {
+        for frame in buffer.chunks_exact(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.measure(channel, *sample);
+            }
+        }
+    }
+
+    /// Send everything measured since the last call to `meter`
+    pub fn publish(&mut self, meter: &Meter) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            if channel.levels.frames > 0 {
+                meter.add(index, &std::mem::take(&mut channel.levels));
+            }
+        }
+    }
+}
+
+/// The levels shown by a meter, with peak hold and falloff. This lives in the UI
+#[derive(Default)]
+pub struct MeterDisplay {
+    channels: [ChannelDisplay; Speakers::MAX_COUNT],
+    last_update: Option<Instant>,
+}
+
+#[derive(Default)]
+struct ChannelDisplay {
+    peak: f32,
+    hold: f32,
+    hold_since: Option<Instant>,
+    /// The averaged mean square
+    mean_square: f32,
+    /// The highest true peak since the clip indicator was reset
+    max_true_peak: f32,
+    /// Latched when the true peak goes over 0dB
+    clipped: bool,
+}
+
+impl MeterDisplay {
+    /// Take the new levels from `meter` and let the old ones fall
+    pub fn update(&mut self, meter: &Meter, channels: usize) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_update
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_update = Some(now);
+
+        let rms_decay = (-elapsed / RMS_TIME).exp();
+
+        for (index, channel) in self.channels.iter_mut().enumerate().take(channels) {
+            let levels = meter.take(index);
+
+            let fallen = db_to_gain(gain_to_db(channel.peak) - PEAK_FALL * elapsed);
+            channel.peak = fallen.max(levels.peak);
+
+            let hold_expired = channel
+                .hold_since
+                .is_none_or(|since| now - since > PEAK_HOLD);
+            if levels.peak >= channel.hold || hold_expired {
+                channel.hold = levels.peak;
+                channel.hold_since = Some(now);
+            }
+
+            channel.mean_square *= rms_decay;
+            if levels.frames > 0 {
+                let mean_square = (levels.sum_squares / levels.frames as f64) as f32;
+                channel.mean_square += mean_square * (1.0 - rms_decay);
+            }
+
+            channel.max_true_peak = channel.max_true_peak.max(levels.true_peak);
+            channel.clipped |= levels.true_peak > 1.0;
+        }
+    }
+
+    /// Forget the highest true peak and turn off the clip indicators
+    pub fn reset_clip(&mut self) {
+        for channel in &mut self.channels {
+            channel.max_true_peak = 0.0;
+            channel.clipped = false;
+        }
+    }
+
+    /// Update and draw a bar for each of `channels`, `length` long.
+    ///
+    /// The bars go up if `vertical`, otherwise to the right. Clicking the meter resets the clip indicators
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        meter: &Meter,
+        channels: u16,
+        length: f32,
+        vertical: bool,
+    ) -> egui::Response {
+        let channels = (channels as usize).min(Speakers::MAX_COUNT);
+        self.update(meter, channels);
+
+        let thickness = (channels as f32 * (BAR_WIDTH + BAR_GAP) - BAR_GAP).max(BAR_WIDTH);
+        let size = if vertical {
+            egui::vec2(thickness, length)
+        } else {
+            egui::vec2(length, thickness)
+        };
+
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+
+        // The distance from the bottom (or left) of a bar to `gain`
+        let level = |gain: f32| {
+            ((gain_to_db(gain) - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0)
+                * (length - CLIP_SIZE)
+        };
+        // The part of the bar of `channel` from `start` to `end` along it
+        let segment = |channel: usize, start: f32, end: f32| {
+            let offset = channel as f32 * (BAR_WIDTH + BAR_GAP);
+
+            if vertical {
+                egui::Rect::from_min_max(
+                    egui::pos2(rect.left() + offset, rect.bottom() - end),
+                    egui::pos2(rect.left() + offset + BAR_WIDTH, rect.bottom() - start),
+                )
+            } else {
+                egui::Rect::from_min_max(
+                    egui::pos2(rect.left() + start, rect.top() + offset),
+                    egui::pos2(rect.left() + end, rect.top() + offset + BAR_WIDTH),
+                )
+            }
+        };
+
+        for (index, channel) in self.channels.iter().enumerate().take(channels) {
+            painter.rect_filled(
+                segment(index, 0.0, level(channel.peak)),
+                0.0,
+                egui::Color32::from_rgb(40, 110, 60),
+            );
+            painter.rect_filled(
+                segment(index, 0.0, level(channel.mean_square.sqrt())),
+                0.0,
+                egui::Color32::from_rgb(60, 200, 90),
+            );
+
+            // A thin line at the held peak
+            if channel.hold > 0.0 {
+                let hold = level(channel.hold);
+                painter.rect_filled(
+                    segment(index, hold - 1.0, hold),
+                    0.0,
+                    egui::Color32::from_rgb(230, 200, 60),
+                );
+            }
+
+            let clip_color = if channel.clipped {
+                egui::Color32::RED
+            } else {
+                egui::Color32::from_gray(50)
+            };
+            painter.rect_filled(segment(index, length - CLIP_SIZE, length), 0.0, clip_color);
+        }
+
+        if response.clicked() {
+            self.reset_clip();
+        }
+
+        // Keep repainting until the bars have fallen to the bottom
+        let floor = db_to_gain(METER_FLOOR_DB);
+        if self.channels[..channels]
+            .iter()
+            .any(|channel| channel.peak > floor || channel.mean_square.sqrt() > floor)
+        {
+            ui.ctx().request_repaint();
+        }
+
+        let channels = &self.channels[..channels];
+        let loudest = |level: fn(&ChannelDisplay) -> f32| {
+            format_db(channels.iter().map(level).fold(0.0, f32::max))
+        };
+
+        response.on_hover_text(format!(
+            "Peak {} dB\nRMS {} dB\nTrue peak {} dBTP\nClick to reset the clip indicators",
+            loudest(|channel| channel.peak),
+            loudest(|channel| channel.mean_square.sqrt()),
+            loudest(|channel| channel.max_true_peak),
+        ))
+    }
+}
+
+/// Format a gain in dB with one decimal, or `-inf` if it is silent
+fn format_db(gain: f32) -> String {
+    if gain > 0.0 {
+        format!("{:.1}", gain_to_db(gain))
+    } else {
+        "-inf".to_string()
+    }
+}
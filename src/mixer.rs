@@ -0,0 +1,758 @@
+use std::f32::consts::FRAC_PI_2;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    automation::{frame_time, process_automated_inserts, LevelAutomation},
+    bus::{Buses, MasterBus, Output},
+    channel::{channel_router, Layout, Speakers},
+    id::Id,
+    meter::MeterProcessor,
+    processor::{process_inserts, Insert},
+    track::Track,
+};
+
+/// The time it takes a smoothed gain to move about two thirds of the way to a new value
+const SMOOTHING_TIME: f32 = 0.01;
+
+/// Gains closer than this to their target jump straight to it
+const SMOOTHING_THRESHOLD: f32 = 1e-5;
+
+/// The lowest volume the faders go to. Anything at or below this is silent
+pub const MIN_VOLUME_DB: f32 = -60.0;
+pub const MAX_VOLUME_DB: f32 = 12.0;
+
+/// Convert a level in decibels to a linear gain
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Format a fader level in decibels, with levels at `MIN_VOLUME_DB` shown as silent
+pub fn format_volume(volume: f64) -> String {
+    if volume <= MIN_VOLUME_DB as f64 {
+        "-inf".to_string()
+    } else {
+        format!("{volume:.1}")
+    }
+}
+
+/// Format a pan position as the percentage to the left or right, or `C` in the center
+pub fn format_pan(pan: f64) -> String {
+    match (pan * 100.0).round() as i32 {
+        0 => "C".to_string(),
+        pan if pan < 0 => format!("L{}", -pan),
+        pan => format!("R{pan}"),
+    }
+}
+
+/// Convert a linear gain to decibels. Silence is negative infinity
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// How the level of each side changes as a track is panned.
+///
+/// The left speakers get the gain at `pan` and the right speakers the gain at `-pan`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PanLaw {
+    /// Sine/cosine taper. The total power stays the same everywhere, -3dB at the center
+    #[default]
+    ConstantPower,
+    /// Square root taper, -3dB at the center
+    Minus3dB,
+    /// Linear taper, -6dB at the center
+    Minus6dB,
+    /// Balance control. Both sides are at unity in the center and only the opposite side is faded out
+    Linear,
+}
+
+impl PanLaw {
+    pub const ALL: [PanLaw; 4] = [
+        PanLaw::ConstantPower,
+        PanLaw::Minus3dB,
+        PanLaw::Minus6dB,
+        PanLaw::Linear,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PanLaw::ConstantPower => "Constant Power",
+            PanLaw::Minus3dB => "-3 dB",
+            PanLaw::Minus6dB => "-6 dB",
+            PanLaw::Linear => "Linear",
+        }
+    }
+
+    /// The gain of the left side at `pan` (-1 is hard left, 1 is hard right)
+    pub fn left_gain(&self, pan: f32) -> f32 {
+        // How far the track is panned to the right, from 0 to 1
+        let right = (pan.clamp(-1.0, 1.0) + 1.0) / 2.0;
+
+        match self {
+            PanLaw::ConstantPower => (right * FRAC_PI_2).cos(),
+            PanLaw::Minus3dB => (1.0 - right).sqrt(),
+            PanLaw::Minus6dB => 1.0 - right,
+            PanLaw::Linear => (2.0 - 2.0 * right).min(1.0),
+        }
+    }
+}
+
+/// The level controls of a track
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrackLevels {
+    /// The volume in decibels
+    pub volume: f32,
+    /// From -1 (hard left) to 1 (hard right)
+    pub pan: f32,
+    pub pan_law: PanLaw,
+    pub mute: bool,
+    /// If any track is soloed, only soloed tracks are heard
+    pub solo: bool,
+}
+
+impl Default for TrackLevels {
+    fn default() -> Self {
+        TrackLevels {
+            volume: 0.0,
+            pan: 0.0,
+            pan_law: PanLaw::default(),
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+impl TrackLevels {
+    /// The linear volume. Silent at `MIN_VOLUME_DB`
+    pub fn volume_gain(&self) -> f32 {
+        if self.volume <= MIN_VOLUME_DB {
+            0.0
+        } else {
+            db_to_gain(self.volume)
+        }
+    }
+
+    /// If the track is heard, given whether any track is soloed
+    pub fn audible(&self, any_solo: bool) -> bool {
+        !self.mute && (self.solo || !any_solo)
+    }
+
+    /// The gain of the output channel playing `speaker` with volume and pan applied.
+    ///
+    /// Speakers on the left and right are panned. Center speakers, the subwoofer
+    /// and channels without a speaker only get the volume
+    pub fn speaker_gain(&self, speaker: Option<Speakers>) -> f32 {
+        const LEFT: Speakers = Speakers::FrontLeft
+            .union(Speakers::SideLeft)
+            .union(Speakers::RearLeft)
+            .union(Speakers::HeightLeft1)
+            .union(Speakers::HeightLeft2);
+        const RIGHT: Speakers = Speakers::FrontRight
+            .union(Speakers::SideRight)
+            .union(Speakers::RearRight)
+            .union(Speakers::HeightRight1)
+            .union(Speakers::HeightRight2);
+
+        let pan = match speaker {
+            Some(speaker) if LEFT.contains(speaker) => self.pan_law.left_gain(self.pan),
+            Some(speaker) if RIGHT.contains(speaker) => self.pan_law.left_gain(-self.pan),
+            _ => 1.0,
+        };
+
+        self.volume_gain() * pan
+    }
+}
+
+/// Applies the levels of each track while mixing them together through the buses.
+///
+/// Gains are smoothed per frame so changing them doesn't cause zipper noise.
+/// Tracks and buses are identified by their index, so a mixer should only be used with one session
+pub struct Mixer {
+    tracks: Vec<Strip>,
+    buses: Vec<Strip>,
+    master: Strip,
+    /// If the tracks and buses are measured into their meters
+    metering: bool,
+    /// The gains being moved towards
+    target: Vec<f32>,
+    /// A single track or bus is rendered here before its levels are applied
+    scratch: Vec<f32>,
+    /// The signal of a track or bus after its levels
+    faded: Vec<f32>,
+    /// What is mixed into every bus in this buffer, in the order of `Buses::buses`
+    bus_inputs: Vec<Vec<f32>>,
+    /// The signals of the tracks that key the inserts of other tracks in this buffer
+    sidechains: Vec<Sidechain>,
+    /// How far the gains move towards their targets every frame
+    smoothing: f32,
+    sample_rate: u32,
+    /// The frame of the timeline the buffer being mixed starts at, to evaluate automation
+    position: usize,
+    /// The channels of the buffer being mixed
+    channels: u16,
+}
+
+/// The levels of a track or bus while mixing
+#[derive(Default)]
+struct Strip {
+    /// The current gain of every output channel. Empty until the strip is first mixed
+    gains: Vec<f32>,
+    meter: MeterProcessor,
+}
+
+impl Strip {
+    /// Write `input` into `output` with the gains moved towards `target` every frame
+    fn apply(
+        &mut self,
+        target: &[f32],
+        smoothing: f32,
+        metering: bool,
+        input: &[f32],
+        output: &mut [f32],
+    ) {
+        let channels = target.len();
+
+        for (output, input) in output
+            .chunks_exact_mut(channels)
+            .zip(input.chunks_exact(channels))
+        {
+            for (channel, (((output, input), gain), target)) in output
+                .iter_mut()
+                .zip(input)
+                .zip(self.gains.iter_mut())
+                .zip(target)
+                .enumerate()
+            {
+                // Near loud targets the steps get too small to change the gain, it would never arrive
+                let step = (*target - *gain) * smoothing;
+                if (*target - *gain).abs() < SMOOTHING_THRESHOLD || *gain + step == *gain {
+                    *gain = *target;
+                } else {
+                    *gain += step;
+                }
+
+                *output = input * *gain;
+
+                if metering {
+                    self.meter.measure(channel, *output);
+                }
+            }
+        }
+    }
+
+    /// Write `input` into `output` with the gains set by `gains` for every frame, for automated levels.
+    ///
+    /// `gains` gets the index of the frame and the gain of every channel to fill in
+    fn apply_automated(
+        &mut self,
+        metering: bool,
+        input: &[f32],
+        output: &mut [f32],
+        mut gains: impl FnMut(usize, &mut [f32]),
+    ) {
+        let channels = self.gains.len();
+        if channels == 0 {
+            return;
+        }
+
+        for (frame, (output, input)) in output
+            .chunks_exact_mut(channels)
+            .zip(input.chunks_exact(channels))
+            .enumerate()
+        {
+            gains(frame, &mut self.gains);
+
+            for (channel, ((output, input), gain)) in
+                output.iter_mut().zip(input).zip(&self.gains).enumerate()
+            {
+                *output = input * gain;
+
+                if metering {
+                    self.meter.measure(channel, *output);
+                }
+            }
+        }
+    }
+
+    /// If the strip is silent and stays silent
+    fn silent(&self, target: &[f32]) -> bool {
+        self.gains.iter().chain(target).all(|gain| *gain == 0.0)
+    }
+
+    /// Start at the target the first time so the strip doesn't fade in
+    fn start(&mut self, target: &[f32]) {
+        if self.gains.len() != target.len() {
+            self.gains.clear();
+            self.gains.extend_from_slice(target);
+        }
+    }
+}
+
+/// Add `input` to `output` with `gain` applied
+fn add_scaled(output: &mut [f32], input: &[f32], gain: f32) {
+    if gain == 0.0 {
+        return;
+    }
+
+    output
+        .iter_mut()
+        .zip(input)
+        .for_each(|(output, input)| *output += input * gain);
+}
+
+/// The signal of a track that keys an insert on another track.
+///
+/// This is the track before its inserts, so tracks can key each other in any order
+struct Sidechain {
+    track: Id,
+    buffer: Vec<f32>,
+    /// If an insert uses the track in this buffer
+    used: bool,
+    /// If `buffer` holds the track for this buffer
+    rendered: bool,
+}
+
+/// The signal of the track `id` if it was rendered as a sidechain in this buffer
+fn sidechain_signal(sidechains: &[Sidechain], id: Id) -> Option<&[f32]> {
+    sidechains
+        .iter()
+        .find(|sidechain| sidechain.rendered && sidechain.track == id)
+        .map(|sidechain| sidechain.buffer.as_slice())
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Mixer {
+        Mixer {
+            tracks: Vec::new(),
+            buses: Vec::new(),
+            master: Strip::default(),
+            metering: false,
+            target: Vec::new(),
+            scratch: Vec::new(),
+            faded: Vec::new(),
+            bus_inputs: Vec::new(),
+            sidechains: Vec::new(),
+            smoothing: 1.0 - (-1.0 / (SMOOTHING_TIME * sample_rate as f32)).exp(),
+            sample_rate,
+            position: 0,
+            channels: 0,
+        }
+    }
+
+    /// A mixer that also measures every track and bus into its meter, for playback
+    pub fn metered(sample_rate: u32) -> Mixer {
+        Mixer {
+            metering: true,
+            ..Mixer::new(sample_rate)
+        }
+    }
+
+    /// The sample rate the mixer runs at
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get ready to mix a buffer of `len` samples with `channels` channels, starting at the frame `position` of the timeline.
+    /// The inputs of the buses are cleared
+    pub fn start_buffer(&mut self, buses: &Buses, position: usize, channels: u16, len: usize) {
+        self.position = position;
+        self.channels = channels;
+
+        let count = buses.buses().len();
+        self.bus_inputs.resize_with(count, Vec::new);
+        self.buses.resize_with(count, Strip::default);
+
+        for input in &mut self.bus_inputs {
+            input.clear();
+            input.resize(len, 0.0);
+        }
+
+        for sidechain in &mut self.sidechains {
+            sidechain.used = false;
+            sidechain.rendered = false;
+        }
+    }
+
+    /// Find the tracks `inserts` are keyed from, for the inserts of a track or bus.
+    ///
+    /// They have to be rendered with `render_sidechain` before any track is mixed
+    pub fn find_sidechains(&mut self, inserts: &[Insert]) {
+        for id in inserts.iter().filter_map(|insert| insert.sidechain) {
+            match self
+                .sidechains
+                .iter_mut()
+                .find(|sidechain| sidechain.track == id)
+            {
+                Some(sidechain) => sidechain.used = true,
+                None => self.sidechains.push(Sidechain {
+                    track: id,
+                    buffer: Vec::new(),
+                    used: true,
+                    rendered: false,
+                }),
+            }
+        }
+    }
+
+    /// Render `track` into its sidechain buffer if another track is keyed from it.
+    ///
+    /// `render` is the same as for `mix_track`, the track isn't rendered again when it's mixed
+    pub fn render_sidechain(&mut self, track: &Track, len: usize, render: impl FnOnce(&mut [f32])) {
+        let Some(sidechain) = self
+            .sidechains
+            .iter_mut()
+            .find(|sidechain| sidechain.used && sidechain.track == track.id)
+        else {
+            return;
+        };
+
+        sidechain.buffer.clear();
+        sidechain.buffer.resize(len, 0.0);
+        render(&mut sidechain.buffer);
+        sidechain.rendered = true;
+    }
+
+    /// The gains of every output channel for `levels`
+    fn set_target(&mut self, levels: &TrackLevels, audible: bool, channels: u16) {
+        let layout = Layout::from_channel_count(channels);
+
+        self.target.clear();
+        self.target.extend((0..channels as usize).map(|channel| {
+            if audible {
+                levels.speaker_gain(Layout::channel_speaker(layout, channel))
+            } else {
+                0.0
+            }
+        }));
+    }
+
+    /// Mix `track` (at `index`) into its output and sends with its levels applied.
+    ///
+    /// `render` writes the track's signal into a zeroed buffer of the same size as `master`.
+    /// It isn't called when the track is silent. The signal then goes through the track's inserts before the levels are applied.
+    /// Tracks going to the master are added to `master`, the others to the input of their bus.
+    /// Automated levels and parameters follow their lanes from the position given to `start_buffer`.
+    /// If the mixer is metered, the track after its levels is measured into the track's meter
+    pub fn mix_track(
+        &mut self,
+        index: usize,
+        track: &Track,
+        audible: bool,
+        buses: &Buses,
+        master: &mut [f32],
+        render: impl FnOnce(&mut [f32]),
+    ) {
+        self.set_target(&track.levels, audible, self.channels);
+        // Automated levels are followed exactly instead of being smoothed
+        let automation = LevelAutomation::new(track).filter(|_| audible);
+
+        if self.tracks.len() <= index {
+            self.tracks.resize_with(index + 1, Strip::default);
+        }
+        let strip = &mut self.tracks[index];
+        strip.start(&self.target);
+
+        // Pre-fader sends are heard even with the volume all the way down
+        let pre_fader = audible && track.sends.iter().any(|send| send.pre_fader);
+        if strip.silent(&self.target) && !pre_fader && automation.is_none() {
+            return;
+        }
+
+        let sidechains = &self.sidechains;
+        let rendered = |id| sidechain_signal(sidechains, id);
+
+        self.scratch.clear();
+        match rendered(track.id) {
+            Some(signal) => self.scratch.extend_from_slice(signal),
+            None => {
+                self.scratch.resize(master.len(), 0.0);
+                render(&mut self.scratch);
+            }
+        }
+        process_automated_inserts(
+            &track.inserts,
+            &track.automation,
+            &mut self.scratch,
+            self.position,
+            self.sample_rate,
+            self.channels,
+            rendered,
+        );
+
+        self.faded.resize(master.len(), 0.0);
+        match automation {
+            Some(automation) => {
+                let layout = Layout::from_channel_count(self.channels);
+                let (position, sample_rate) = (self.position, self.sample_rate);
+
+                strip.apply_automated(
+                    self.metering,
+                    &self.scratch,
+                    &mut self.faded,
+                    |frame, gains| {
+                        let levels =
+                            automation.levels_at(frame_time(position + frame, sample_rate));
+
+                        for (channel, gain) in gains.iter_mut().enumerate() {
+                            *gain = levels.speaker_gain(Layout::channel_speaker(layout, channel));
+                        }
+                    },
+                )
+            }
+            None => strip.apply(
+                &self.target,
+                self.smoothing,
+                self.metering,
+                &self.scratch,
+                &mut self.faded,
+            ),
+        }
+        if self.metering {
+            strip.meter.publish(&track.meter);
+        }
+
+        add_scaled(
+            destination(track.output, buses, &mut self.bus_inputs, master),
+            &self.faded,
+            1.0,
+        );
+
+        for send in &track.sends {
+            let Some(bus) = buses.index(send.bus) else {
+                continue;
+            };
+
+            let signal = if send.pre_fader {
+                &self.scratch
+            } else {
+                &self.faded
+            };
+            let gain = if audible { send.gain() } else { 0.0 };
+            add_scaled(&mut self.bus_inputs[bus], signal, gain);
+        }
+    }
+
+    /// Mix every bus into its output and sends, after all tracks have been mixed.
+    ///
+    /// Buses are mixed in order, so buses feeding other buses come first
+    pub fn mix_buses(&mut self, buses: &Buses, master: &mut [f32]) {
+        for &index in buses.order() {
+            let bus = &buses.buses()[index];
+            let mut input = std::mem::take(&mut self.bus_inputs[index]);
+
+            let sidechains = &self.sidechains;
+            process_inserts(
+                &bus.inserts,
+                &mut input,
+                self.sample_rate,
+                self.channels,
+                |id| sidechain_signal(sidechains, id),
+            );
+
+            let audible = bus.levels.audible(false);
+            self.set_target(&bus.levels, audible, self.channels);
+
+            let strip = &mut self.buses[index];
+            strip.start(&self.target);
+            self.faded.resize(input.len(), 0.0);
+            strip.apply(
+                &self.target,
+                self.smoothing,
+                self.metering,
+                &input,
+                &mut self.faded,
+            );
+            if self.metering {
+                strip.meter.publish(&bus.meter);
+            }
+
+            let output = destination(bus.output(), buses, &mut self.bus_inputs, master);
+            match bus.mapping {
+                Some(mapping) => channel_router(
+                    self.channels,
+                    self.channels,
+                    &self.faded,
+                    output,
+                    0,
+                    &Some(mapping.into()),
+                    |_| 1.0,
+                ),
+                None => add_scaled(output, &self.faded, 1.0),
+            }
+
+            for send in bus.sends() {
+                let Some(target) = buses.index(send.bus) else {
+                    continue;
+                };
+
+                let signal = if send.pre_fader { &input } else { &self.faded };
+                let gain = if audible { send.gain() } else { 0.0 };
+                add_scaled(&mut self.bus_inputs[target], signal, gain);
+            }
+
+            self.bus_inputs[index] = input;
+        }
+    }
+
+    /// Run the mix in `output` through the inserts and volume of the master bus
+    pub fn mix_master(&mut self, master: &MasterBus, output: &mut [f32]) {
+        let sidechains = &self.sidechains;
+        process_inserts(
+            &master.inserts,
+            output,
+            self.sample_rate,
+            self.channels,
+            |id| sidechain_signal(sidechains, id),
+        );
+
+        self.target.clear();
+        self.target.resize(self.channels as usize, master.gain());
+        self.master.start(&self.target);
+
+        self.faded.resize(output.len(), 0.0);
+        self.master
+            .apply(&self.target, self.smoothing, false, output, &mut self.faded);
+        output.copy_from_slice(&self.faded);
+    }
+}
+
+/// The buffer the signal going to `output` is added to
+fn destination<'a>(
+    output: Output,
+    buses: &Buses,
+    bus_inputs: &'a mut [Vec<f32>],
+    master: &'a mut [f32],
+) -> &'a mut [f32] {
+    match output {
+        Output::Bus(id) => match buses.index(id) {
+            Some(index) => &mut bus_inputs[index],
+            // The bus was removed
+            None => master,
+        },
+        Output::Master => master,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn assert_close(actual: f32, expected: f32, what: &str) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{what}: {actual} instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn pan_laws() {
+        let centers = [
+            (PanLaw::ConstantPower, -3.01),
+            (PanLaw::Minus3dB, -3.01),
+            (PanLaw::Minus6dB, -6.02),
+            (PanLaw::Linear, 0.0),
+        ];
+
+        for (law, center) in centers {
+            let name = law.name();
+            assert_close(gain_to_db(law.left_gain(0.0)), center, name);
+
+            // The side panned to is at unity, the other one silent
+            assert_close(law.left_gain(-1.0), 1.0, name);
+            assert_close(law.left_gain(1.0), 0.0, name);
+            // Past the ends is the same as the ends
+            assert_close(law.left_gain(-2.0), 1.0, name);
+            assert_close(law.left_gain(2.0), 0.0, name);
+        }
+
+        for pan in [-0.8, -0.3, 0.0, 0.4, 0.9] {
+            let law = PanLaw::ConstantPower;
+            let power = law.left_gain(pan).powi(2) + law.left_gain(-pan).powi(2);
+            assert_close(power, 1.0, "Constant power");
+        }
+    }
+
+    #[test]
+    fn speaker_gains() {
+        let mut levels = TrackLevels {
+            volume: -6.0,
+            pan: 1.0,
+            ..TrackLevels::default()
+        };
+        let volume = db_to_gain(-6.0);
+
+        for (speaker, gain) in [
+            (Some(Speakers::FrontLeft), 0.0),
+            (Some(Speakers::SideLeft), 0.0),
+            (Some(Speakers::HeightLeft2), 0.0),
+            (Some(Speakers::FrontRight), volume),
+            (Some(Speakers::RearRight), volume),
+            // Only the volume applies to these
+            (Some(Speakers::Center), volume),
+            (Some(Speakers::Subwoofer), volume),
+            (None, volume),
+        ] {
+            assert_close(levels.speaker_gain(speaker), gain, &format!("{speaker:?}"));
+        }
+
+        levels.volume = MIN_VOLUME_DB;
+        for speaker in [Some(Speakers::FrontRight), Some(Speakers::Center), None] {
+            assert_eq!(levels.speaker_gain(speaker), 0.0, "{speaker:?}");
+        }
+    }
+
+    #[test]
+    fn solo_and_mute() {
+        for (mute, solo, any_solo, audible) in [
+            (false, false, false, true),
+            (false, false, true, false),
+            (false, true, true, true),
+            (true, false, false, false),
+            (true, true, true, false),
+        ] {
+            let levels = TrackLevels {
+                mute,
+                solo,
+                ..TrackLevels::default()
+            };
+            assert_eq!(
+                levels.audible(any_solo),
+                audible,
+                "mute: {mute}, solo: {solo}, any solo: {any_solo}"
+            );
+        }
+    }
+
+    #[test]
+    fn gains_are_smoothed() {
+        let smoothing = Mixer::new(SAMPLE_RATE).smoothing;
+        let mut strip = Strip::default();
+        strip.start(&[1.0, 1.0]);
+
+        // Ramp the left channel down and the right channel up over a second
+        let target = [0.0, 2.0];
+        let frames = SAMPLE_RATE as usize;
+        let input = vec![1.0; frames * 2];
+        let mut output = vec![0.0; frames * 2];
+        strip.apply(&target, smoothing, false, &input, &mut output);
+
+        let left: Vec<_> = output.iter().step_by(2).copied().collect();
+        let right: Vec<_> = output.iter().skip(1).step_by(2).copied().collect();
+
+        // No jumps, the gains move a little every frame
+        assert!(left[0] > 0.99 && right[0] < 1.01);
+        assert!(left.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(right.windows(2).all(|pair| pair[1] >= pair[0]));
+
+        // About two thirds of the way after the smoothing time
+        let time = (SMOOTHING_TIME * SAMPLE_RATE as f32) as usize;
+        assert!((left[time] - 1.0 / std::f32::consts::E).abs() < 0.01);
+        assert!((right[time] - (2.0 - 1.0 / std::f32::consts::E)).abs() < 0.01);
+
+        // And exactly there in the end
+        assert_eq!(strip.gains, target);
+        assert_eq!(output[output.len() - 2..], target);
+    }
+}
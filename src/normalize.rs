@@ -0,0 +1,253 @@
+use std::ops::Range;
+
+use crate::{
+    channel::{Layout, Speakers},
+    clip::Clip,
+    loudness::LoudnessAnalyzer,
+    mixer::db_to_gain,
+    track::Track,
+};
+
+/// What a clip is normalized to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizeTarget {
+    /// The highest sample, in dBFS
+    Peak,
+    /// The integrated loudness, in LUFS
+    Loudness,
+}
+
+impl NormalizeTarget {
+    pub const ALL: [NormalizeTarget; 2] = [NormalizeTarget::Peak, NormalizeTarget::Loudness];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalizeTarget::Peak => "Peak",
+            NormalizeTarget::Loudness => "Loudness",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            NormalizeTarget::Peak => "dBFS",
+            NormalizeTarget::Loudness => "LUFS",
+        }
+    }
+
+    /// A sensible level to normalize to
+    pub fn default_level(&self) -> f32 {
+        match self {
+            NormalizeTarget::Peak => -1.0,
+            NormalizeTarget::Loudness => -23.0,
+        }
+    }
+}
+
+/// How clips are normalized
+///
+/// `level` is in the unit of the target
+/// `linked` if `true` every channel gets the same gain, otherwise each channel is normalized on its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizeSettings {
+    pub target: NormalizeTarget,
+    pub level: f32,
+    pub linked: bool,
+}
+
+impl Default for NormalizeSettings {
+    fn default() -> Self {
+        NormalizeSettings {
+            target: NormalizeTarget::Peak,
+            level: NormalizeTarget::Peak.default_level(),
+            linked: true,
+        }
+    }
+}
+
+impl Clip {
+    /// The highest absolute sample of each channel of the clip
+    fn peaks(&self) -> [f32; Speakers::MAX_COUNT] {
+        let channels = self.sample.header.channel_count as usize;
+        let mut peaks = [0.0f32; Speakers::MAX_COUNT];
+
+        for frame in self.data().chunks_exact(channels) {
+            for (peak, sample) in peaks.iter_mut().zip(frame) {
+                *peak = peak.max(sample.abs());
+            }
+        }
+
+        peaks
+    }
+
+    /// The integrated loudness of the channels of the clip together
+    fn loudness(&self) -> Option<f32> {
+        LoudnessAnalyzer::analyze(
+            self.data(),
+            self.sample.header.sampling_rate,
+            self.sample.header.channel_count,
+            self.sample.layout,
+        )
+        .integrated
+    }
+
+    /// The integrated loudness of each channel of the clip on its own
+    fn channel_loudness(&self) -> [Option<f32>; Speakers::MAX_COUNT] {
+        let channels = self.sample.header.channel_count as usize;
+        let mut loudness = [None; Speakers::MAX_COUNT];
+
+        for (channel, loudness) in loudness.iter_mut().enumerate().take(channels) {
+            let mut analyzer =
+                LoudnessAnalyzer::new(self.sample.header.sampling_rate, 1, Some(Layout::Mono));
+            for frame in self.data().chunks_exact(channels) {
+                analyzer.process(&frame[channel..=channel]);
+            }
+
+            *loudness = analyzer.report().integrated;
+        }
+
+        loudness
+    }
+
+    /// Set the gain of the clip so it reaches the level in `settings`.
+    ///
+    /// The previous gain is replaced. Channels that are silent (or entirely below the loudness gate) keep their gain.
+    /// Returns `true` if the gain changed
+    pub fn normalize(&mut self, settings: &NormalizeSettings) -> bool {
+        let channels = self.sample.header.channel_count as usize;
+
+        // The level of each channel in linear gain, or `None` if it can't be measured
+        let mut levels = [None; Speakers::MAX_COUNT];
+        match (settings.target, settings.linked) {
+            (NormalizeTarget::Peak, true) => {
+                let peak = self.peaks().into_iter().fold(0.0, f32::max);
+                levels.fill(Some(peak));
+            }
+            (NormalizeTarget::Peak, false) => {
+                for (level, peak) in levels.iter_mut().zip(self.peaks()) {
+                    *level = Some(peak);
+                }
+            }
+            (NormalizeTarget::Loudness, true) => {
+                levels.fill(self.loudness().map(db_to_gain));
+            }
+            (NormalizeTarget::Loudness, false) => {
+                for (level, loudness) in levels.iter_mut().zip(self.channel_loudness()) {
+                    *level = loudness.map(db_to_gain);
+                }
+            }
+        }
+
+        let target = db_to_gain(settings.level);
+        let previous = self.gain;
+
+        for (gain, level) in self.gain.iter_mut().zip(levels).take(channels) {
+            if let Some(level) = level.filter(|level| *level > 0.0) {
+                *gain = target / level;
+            }
+        }
+
+        self.gain != previous
+    }
+}
+
+impl Track {
+    /// Normalize the clips that overlap `range` (in microseconds), or every clip if there is no range.
+    ///
+    /// Returns `true` if the gain of any clip changed
+    pub fn normalize_clips(
+        &mut self,
+        range: Option<&Range<u64>>,
+        settings: &NormalizeSettings,
+    ) -> bool {
+        let mut changed = false;
+
+        for clip in &mut self.clips {
+            let overlaps =
+                range.is_none_or(|range| clip.start < range.end && clip.end() > range.start);
+
+            if overlaps {
+                changed |= clip.normalize(settings);
+            }
+        }
+
+        if changed {
+            self.invalidate_view();
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{mixer::gain_to_db, sample::Sample, util::sine};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A stereo clip with a louder left channel than right channel
+    fn clip() -> Clip {
+        let left = sine(1000.0, 0.5, SAMPLE_RATE, SAMPLE_RATE as usize * 2, 1);
+        let right = sine(1000.0, 0.125, SAMPLE_RATE, SAMPLE_RATE as usize * 2, 1);
+        let data = left
+            .into_iter()
+            .zip(right)
+            .flat_map(|(l, r)| [l, r])
+            .collect();
+
+        Clip::new(Arc::new(Sample::from_data(data, SAMPLE_RATE, 2)), 0)
+    }
+
+    /// The level of each channel of `clip` after its gain, in the unit of `target`
+    fn levels(clip: &Clip, target: NormalizeTarget) -> [f32; 2] {
+        let gain = |channel: usize| gain_to_db(clip.gain[channel]);
+        match target {
+            NormalizeTarget::Peak => {
+                let peaks = clip.peaks();
+                [0, 1].map(|channel| gain_to_db(peaks[channel]) + gain(channel))
+            }
+            NormalizeTarget::Loudness => {
+                let loudness = clip.channel_loudness();
+                [0, 1].map(|channel| loudness[channel].unwrap() + gain(channel))
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_reaches_target() {
+        for target in NormalizeTarget::ALL {
+            let level = target.default_level();
+
+            let mut independent = clip();
+            assert!(independent.normalize(&NormalizeSettings {
+                target,
+                level,
+                linked: false,
+            }));
+            for channel in levels(&independent, target) {
+                assert!((channel - level).abs() < 0.1, "{channel} != {level}");
+            }
+
+            let mut linked = clip();
+            assert!(linked.normalize(&NormalizeSettings {
+                target,
+                level,
+                linked: true,
+            }));
+            // Both channels get the same gain, so the channels stay 12 dB apart
+            assert_eq!(linked.gain[0], linked.gain[1]);
+            let [left, right] = levels(&linked, target);
+            assert!((left - right - 12.04).abs() < 0.1, "{left} - {right}");
+
+            match target {
+                NormalizeTarget::Peak => assert!((left - level).abs() < 0.1, "{left} != {level}"),
+                NormalizeTarget::Loudness => {
+                    let loudness = linked.loudness().unwrap() + gain_to_db(linked.gain[0]);
+                    assert!((loudness - level).abs() < 0.1, "{loudness} != {level}");
+                }
+            }
+        }
+    }
+}
@@ -1,62 +1,246 @@
-use std::sync::{atomic::Ordering, Arc, RwLock};
-
-use cpal::{
-    traits::{DeviceTrait, StreamTrait},
-    SampleFormat,
+use std::{
+    collections::HashSet,
+    ops::Range,
+    sync::{Arc, RwLock},
 };
-use tracing::{info, warn};
+
+use tracing::info;
 
 use crate::{
-    channel::{channel_router, channel_router_split_input},
+    backend::{BackendKind, CpalBackend, FileBackend, NullBackend, OutputBackend},
+    bus::Buses,
+    channel::{channel_router, channel_router_split_input, GainMatrix, Layout},
+    fade::Envelope,
+    loudness::LoudnessAnalyzer,
+    meter::MeterProcessor,
+    mixer::Mixer,
+    processor::Format,
     resampler::Resampler,
+    settings::OutputSettings,
     state::State,
     track::Track,
+    transport::Transport,
 };
 
-const MAX_RESAMPLING_BUFFER: usize = 16192;
-
+/// Samples are resampled in chunks of this many output frames.
+///
+/// Playback and offline renders use the same size so they resample identically
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+/// The buffer size used when the device doesn't report one
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+/// The format simulated by the null and file backends when the settings don't choose one
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const DEFAULT_CHANNELS: u16 = 2;
+
+/// Start the output backend chosen in `settings`, paused, playing `tracks`.
+///
+/// `device` is only needed by the device backend.
 pub fn start_audio(
-    device: cpal::Device,
+    device: Option<&cpal::Device>,
+    settings: &OutputSettings,
     tracks: Vec<Arc<RwLock<Track>>>,
     state: Arc<RwLock<State>>,
-) -> cpal::Stream {
-    let mut supported_configs_range = device
-        .supported_output_configs()
-        .expect("error while querying configs");
-    // supported_configs_range.next();
-    // supported_configs_range.next();
-    let supported_config = supported_configs_range
-        .next()
-        .expect("no supported config?!")
-        .with_max_sample_rate();
-
-    info!("Channel Count: {}", supported_config.channels());
-    info!("Sample Rate: {}", supported_config.sample_rate().0);
-    info!("Buffer Size: {:?}", supported_config.buffer_size());
-    info!("Config: {:?}", supported_config.config());
-
-    let target_sample_rate = supported_config.sample_rate();
-    let target_sample_count = supported_config.channels();
-
-    // indicies in the form of (sample_index, current_index, absolute_index)
-    let mut output_indicies = vec![(0, 0, 0); tracks.len()];
-
-    let buffer_size = match supported_config.buffer_size() {
-        cpal::SupportedBufferSize::Range { max, .. } => *max,
-        _ => 0,
+) -> Result<Box<dyn OutputBackend>, cpal::BuildStreamError> {
+    let sample_rate = settings.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = settings.channels.unwrap_or(DEFAULT_CHANNELS);
+    let buffer_size = settings
+        .buffer_size
+        .map_or(DEFAULT_BUFFER_SIZE, |size| size as usize);
+
+    let backend: Box<dyn OutputBackend> = match &settings.backend {
+        BackendKind::Device => {
+            let device = device.ok_or(cpal::BuildStreamError::DeviceNotAvailable)?;
+            Box::new(CpalBackend::new(device, settings, tracks, state)?)
+        }
+        BackendKind::Null => {
+            let engine = PlaybackEngine::new(tracks, state, sample_rate, channels, buffer_size);
+            Box::new(NullBackend::new(engine, buffer_size))
+        }
+        BackendKind::File { path } => {
+            let engine = PlaybackEngine::new(tracks, state, sample_rate, channels, buffer_size);
+            Box::new(FileBackend::new(engine, buffer_size, path))
+        }
     };
 
-    let resample_buffer_size = (buffer_size as usize).min(MAX_RESAMPLING_BUFFER);
+    info!("Output Backend: {}", settings.backend.name());
+
+    Ok(backend)
+}
+
+/// Mixes the tracks at the playhead into buffers of interleaved samples.
+///
+/// This is what every output backend pulls its audio from
+pub struct PlaybackEngine {
+    tracks: Vec<Arc<RwLock<Track>>>,
+    buses: Arc<RwLock<Buses>>,
+    resamplers: Vec<Resampler>,
+    mixer: Mixer,
+    /// Measures the mix into the master meter
+    master_meter: MeterProcessor,
+    /// Measures the loudness of the mix into the master loudness
+    loudness: LoudnessAnalyzer,
+
+    state: Arc<RwLock<State>>,
+    transport: Arc<Transport>,
+
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl PlaybackEngine {
+    /// Create an engine that plays `tracks` with the given output format.
+    ///
+    /// The playhead is moved to `sample_rate` and the samples that need it are resampled in the background.
+    /// `buffer_size` is the most frames asked for at once, the inserts are prepared for it
+    pub fn new(
+        tracks: Vec<Arc<RwLock<Track>>>,
+        state: Arc<RwLock<State>>,
+        sample_rate: u32,
+        channels: u16,
+        buffer_size: usize,
+    ) -> PlaybackEngine {
+        let (transport, buses) = {
+            let mut state = state.write().unwrap();
+            state.set_sample_rate(sample_rate);
+            state.output_channels = channels;
+            state.max_block_size = buffer_size;
+
+            (state.transport.clone(), state.buses.clone())
+        };
+
+        let format = Format {
+            sample_rate,
+            channels,
+            max_block_size: buffer_size,
+        };
+        prepare_inserts(&tracks, &buses.read().unwrap(), format);
+
+        let resamplers = create_resamplers(&tracks, sample_rate);
+
+        // spawn one thread per track for resampling
+        for (track_index, resamplers) in resamplers.clone().into_iter().enumerate() {
+            std::thread::spawn(move || {
+                resamplers.resample_all();
 
-    let resamplers: Vec<_> = tracks
+                info!("Channel: `{}` fully resampled", track_index);
+            });
+        }
+
+        PlaybackEngine {
+            tracks,
+            buses,
+            resamplers,
+            mixer: Mixer::metered(sample_rate),
+            master_meter: MeterProcessor::default(),
+            loudness: LoudnessAnalyzer::new(
+                sample_rate,
+                channels,
+                Layout::from_channel_count(channels),
+            ),
+            state,
+            transport,
+            sample_rate,
+            channels,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Block until every sample has been resampled to the output rate
+    #[cfg(test)]
+    pub fn finish_resampling(&self) {
+        use std::sync::atomic::Ordering;
+
+        let complete = |resampler: &Resampler| {
+            resampler
+                .iter()
+                .all(|(_, complete)| complete.load(Ordering::SeqCst))
+        };
+
+        while !self.resamplers.iter().all(complete) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    /// Fill `sample_data` with the mix at the playhead and advance the playhead past it
+    pub fn process(&mut self, sample_data: &mut [f32]) {
+        sample_data.fill(0.0);
+
+        let loop_region = self.state.read().unwrap().active_loop();
+
+        let start = self.transport.position();
+        let mut mix = MixState {
+            position: start as usize,
+            loop_region: loop_region.map(|region| region.start as usize..region.end as usize),
+            resamplers: &self.resamplers,
+            mixer: &mut self.mixer,
+            channels: self.channels,
+        };
+        mix_tracks_looped(
+            &self.tracks,
+            &self.buses.read().unwrap(),
+            &mut mix,
+            sample_data,
+        );
+
+        self.transport.advance(start, mix.position as u64);
+
+        self.master_meter
+            .measure_buffer(sample_data, self.channels as usize);
+
+        let state = self.state.read().unwrap();
+        self.master_meter.publish(&state.master_meter);
+
+        if state.master_loudness.take_reset() {
+            self.loudness.reset();
+        }
+        self.loudness.process(sample_data);
+        state.master_loudness.publish(&self.loudness.report());
+        state.egui_ctx.request_repaint();
+    }
+}
+
+/// Prepare the inserts of every track and bus for `format`, see `Insert::prepare`
+pub fn prepare_inserts(tracks: &[Arc<RwLock<Track>>], buses: &Buses, format: Format) {
+    for track in tracks {
+        for insert in &track.read().unwrap().inserts {
+            insert.prepare(format);
+        }
+    }
+    for bus in buses.buses() {
+        for insert in &bus.inserts {
+            insert.prepare(format);
+        }
+    }
+    for insert in &buses.master.inserts {
+        insert.prepare(format);
+    }
+}
+
+/// Create a resampler for every sample in each track that doesn't match the target sample rate.
+///
+/// The returned vector has one `Resampler` per track, in the same order as `tracks`.
+/// Samples used by multiple clips are only resampled once
+pub fn create_resamplers(tracks: &[Arc<RwLock<Track>>], target_sample_rate: u32) -> Vec<Resampler> {
+    let mut seen = HashSet::new();
+
+    tracks
         .iter()
         .map(|track| {
             let track = track.read().unwrap();
             let track: Vec<_> = track
-                .samples
+                .clips
                 .iter()
+                .map(|clip| &clip.sample)
+                .filter(|sample| seen.insert(sample.id))
                 .map(|sample| {
-                    if sample.header.sampling_rate != target_sample_rate.0 {
+                    if sample.header.sampling_rate != target_sample_rate {
                         let params = rubato::InterpolationParameters {
                             sinc_len: 256,
                             f_cutoff: 0.95,
@@ -66,16 +250,16 @@ pub fn start_audio(
                         };
 
                         let resampler = rubato::SincFixedOut::<f32>::new(
-                            target_sample_rate.0 as f64 / sample.header.sampling_rate as f64,
+                            target_sample_rate as f64 / sample.header.sampling_rate as f64,
                             2.0,
                             params,
-                            resample_buffer_size,
+                            RESAMPLER_CHUNK_SIZE,
                             sample.header.channel_count as _,
                         )
                         .unwrap();
 
                         let buffer = vec![
-                            Vec::with_capacity(sample.len());
+                            Vec::with_capacity(sample.frames());
                             sample.header.channel_count as usize
                         ];
                         let index = 0usize;
@@ -87,151 +271,226 @@ pub fn start_audio(
                 })
                 .collect();
 
-            (
-                Resampler::from(track),
-                [Vec::<f32>::with_capacity(resample_buffer_size)],
-            )
+            Resampler::from(track)
         })
-        .collect();
-
-    let thread_resamplers = resamplers.clone();
-
-    // spawn one thread per track for resampling
-    for (track_index, (resamplers, mut output_buffer)) in thread_resamplers.into_iter().enumerate()
-    {
-        std::thread::spawn(move || 'outer: loop {
-            for (sample_index, (resampler, complete)) in resamplers.iter().enumerate() {
-                if complete.load(Ordering::SeqCst) {
-                    continue;
-                }
-
-                if resampler.resample(&mut output_buffer) {
-                    info!(
-                        "Channel: `{}` Sample: `{}` resampled... - len: {}, {} samples",
-                        track_index,
-                        resampler.sample().name,
-                        resampler.buffer().read().unwrap()[0].len(),
-                        resamplers.len()
-                    );
-
-                    // This sample has been fully resampled
-                    complete.store(true, Ordering::SeqCst);
-
-                    if sample_index == resamplers.len() - 1 {
-                        info!(
-                            "Channel: `{}` Sample: `{}` fully resampled",
-                            track_index,
-                            resampler.sample().name
-                        );
-                        break 'outer;
-                    }
-                }
+        .collect()
+}
 
-                break;
-            }
-        });
-    }
+/// Mix every track into `sample_data` through `buses` with the levels applied. The output is expected to be zeroed beforehand.
+///
+/// `position` is the frame on the timeline at the start of `sample_data` and is advanced by its length
+/// `resamplers` should have been created for the sample rate of `mixer` with `create_resamplers`
+pub fn mix_tracks(
+    tracks: &[Arc<RwLock<Track>>],
+    buses: &Buses,
+    position: &mut usize,
+    resamplers: &[Resampler],
+    mixer: &mut Mixer,
+    target_sample_count: u16,
+    sample_data: &mut [f32],
+) {
+    let any_solo = tracks.iter().any(|track| track.read().unwrap().levels.solo);
+    let target_sample_rate = mixer.sample_rate() as u64;
 
-    let write_data_f32 = move |sample_data: &mut [f32], info: &cpal::OutputCallbackInfo| {
-        {
-            let mut state = state.write().unwrap();
-            state.play_time.get_or_insert(info.timestamp().playback);
-            state.current_time = Some(info.timestamp().playback);
-        }
+    mixer.start_buffer(buses, *position, target_sample_count, sample_data.len());
 
-        sample_data.fill(0.0);
+    // Tracks that key inserts on other tracks and buses are rendered first
+    for track in tracks {
+        mixer.find_sidechains(&track.read().unwrap().inserts);
+    }
+    for bus in buses.buses() {
+        mixer.find_sidechains(&bus.inserts);
+    }
+    mixer.find_sidechains(&buses.master.inserts);
+    for track in tracks {
+        let track = track.read().unwrap();
 
-        for ((track, indicies), resampler) in tracks
-            .iter()
-            .zip(output_indicies.iter_mut())
-            .zip(resamplers.iter())
-        {
+        mixer.render_sidechain(&track, sample_data.len(), |output| {
             write_track(
-                track,
-                &mut indicies.0,
-                &mut indicies.1,
-                &mut indicies.2,
+                &track,
+                *position,
                 target_sample_count,
-                target_sample_rate.0 as u64,
-                sample_data,
-                &resampler.0,
+                target_sample_rate,
+                output,
+                resamplers,
             )
-        }
+        });
+    }
 
-        let state = state.read().unwrap();
-        state.egui_ctx.request_repaint();
-    };
+    for (index, track) in tracks.iter().enumerate() {
+        let track = track.read().unwrap();
+
+        mixer.mix_track(
+            index,
+            &track,
+            track.levels.audible(any_solo),
+            buses,
+            sample_data,
+            |output| {
+                write_track(
+                    &track,
+                    *position,
+                    target_sample_count,
+                    target_sample_rate,
+                    output,
+                    resamplers,
+                )
+            },
+        );
+    }
 
-    let write_data_i16 = move |_: &mut [i16], _: &cpal::OutputCallbackInfo| println!("i16");
+    mixer.mix_buses(buses, sample_data);
+    mixer.mix_master(&buses.master, sample_data);
 
-    let write_data_u16 = move |_: &mut [u16], _: &cpal::OutputCallbackInfo| println!("u16");
+    *position += sample_data.len() / target_sample_count as usize;
+}
 
-    let stream = match supported_config.sample_format() {
-        SampleFormat::F32 => {
-            device.build_output_stream(&supported_config.config(), write_data_f32, |_| {}, None)
-        }
-        SampleFormat::I16 => {
-            device.build_output_stream(&supported_config.config(), write_data_i16, |_| {}, None)
-        }
-        SampleFormat::U16 => {
-            device.build_output_stream(&supported_config.config(), write_data_u16, |_| {}, None)
-        }
-        sample_format => panic!("Unsupported sample format '{sample_format}'"),
-    }
-    .unwrap();
+/// What is carried over from one buffer of a looped mix to the next
+///
+/// `resamplers` should have been created for the sample rate of `mixer` with `create_resamplers`
+/// `channels` is the number of channels of the output
+pub struct MixState<'a> {
+    /// The frame on the timeline at the start of the next buffer
+    pub position: usize,
+    /// The region jumped back over whenever its end is reached
+    pub loop_region: Option<Range<usize>>,
+    pub resamplers: &'a [Resampler],
+    pub mixer: &'a mut Mixer,
+    pub channels: u16,
+}
 
-    stream.pause().unwrap();
+/// Mix every track into `sample_data` like `mix_tracks`, jumping back to the start of the loop region whenever its end is reached.
+///
+/// The jump is sample accurate, even if it happens in the middle of the buffer or several times in one buffer.
+/// A position that is already past the end of the loop isn't moved
+pub fn mix_tracks_looped(
+    tracks: &[Arc<RwLock<Track>>],
+    buses: &Buses,
+    state: &mut MixState,
+    sample_data: &mut [f32],
+) {
+    let channels = state.channels as usize;
+    let mut remaining = sample_data;
+
+    while !remaining.is_empty() {
+        let frames = remaining.len() / channels;
+
+        // The number of frames until the loop end, if it's reached in this buffer
+        let until_loop_end = state
+            .loop_region
+            .as_ref()
+            .filter(|region| region.contains(&state.position))
+            .map(|region| region.end - state.position)
+            .filter(|&until_end| until_end <= frames);
+
+        let Some(until_loop_end) = until_loop_end else {
+            mix_tracks(
+                tracks,
+                buses,
+                &mut state.position,
+                state.resamplers,
+                state.mixer,
+                state.channels,
+                remaining,
+            );
+            break;
+        };
+
+        let (before_end, after_end) = remaining.split_at_mut(until_loop_end * channels);
+        mix_tracks(
+            tracks,
+            buses,
+            &mut state.position,
+            state.resamplers,
+            state.mixer,
+            state.channels,
+            before_end,
+        );
 
-    stream
+        state.position = state.loop_region.as_ref().unwrap().start;
+        remaining = after_end;
+    }
 }
 
+/// Write every clip of a track that overlaps the buffer into `sample_data`.
+///
+/// Gaps between clips are left untouched (silent)
 fn write_track(
-    track: &Arc<RwLock<Track>>,
-    current_sample_index: &mut usize,
-    current_index: &mut usize,
-    absolute_index: &mut usize,
+    track: &Track,
+    position: usize,
     target_sample_count: u16,
     target_sample_rate: u64,
     sample_data: &mut [f32],
-    resampler: &Resampler,
+    resamplers: &[Resampler],
 ) {
-    let track = track.read().unwrap();
-    let Some((sample, sample_index)) = track.sample_at_sample_index(*absolute_index, target_sample_rate as f64) else {
-        warn!("Sample at index {} not found! (rate: {})", *absolute_index, target_sample_rate);
-        return;
-    };
+    let output_channels = target_sample_count as usize;
+    let buffer_range = position..position + sample_data.len() / output_channels;
 
-    if sample_index > *current_sample_index {
-        *current_index = 0;
-        *current_sample_index += 1;
-    }
+    for (index, clip) in track.clips.iter().enumerate() {
+        let clip_range = clip.frame_range(target_sample_rate as f64);
 
+        let start = clip_range.start.max(buffer_range.start);
+        let end = clip_range.end.min(buffer_range.end);
+        if start >= end {
+            continue;
+        }
 
-    let data = sample.data.as_thirty_two_float().unwrap();
+        let sample = &clip.sample;
+        let output = &mut sample_data
+            [(start - position) * output_channels..(end - position) * output_channels];
+        // Frame in the sample data (at the output rate) to start reading from
+        let source_index = clip.source_offset(target_sample_rate as f64) + start - clip_range.start;
+
+        // Tracks without their own routing are routed by the layout of each sample
+        let routing = track
+            .routing
+            .unwrap_or_else(|| {
+                GainMatrix::between(
+                    sample.layout,
+                    sample.header.channel_count,
+                    Layout::from_channel_count(target_sample_count),
+                    target_sample_count,
+                )
+            })
+            .with_input_gains(&clip.gain);
+        let routing = Some(routing);
+
+        let (fade_in, fade_out) = track.clip_fades(index);
+        let envelope = Envelope::new(
+            fade_in,
+            fade_out,
+            clip_range.len(),
+            target_sample_rate as f64,
+        );
+        // The frame of the clip at the start of the output
+        let clip_frame = start - clip_range.start;
+        let envelope = |frame| envelope.gain(clip_frame + frame);
 
-    let adjusted_len = sample_data.len() / target_sample_count as usize;
+        let resampler = resamplers
+            .iter()
+            .find_map(|resampler| resampler.get(sample.id));
 
-    if let Some(Some((resampler, _))) = resampler.iter_all().nth(sample_index) {
-        let buffer = resampler.buffer().read().unwrap();
-        // println!("Writing {} - {} {}", sample.name, current_index, buffer[0].len());
-        channel_router_split_input(
-            sample.header.channel_count,
-            target_sample_count,
-            &buffer,
-            sample_data,
-            *current_index,
-            &track.channel_mapping,
-            // &None,
-        );
-    } else {
-        channel_router(
-            sample.header.channel_count,
-            target_sample_count,
-            data,
-            sample_data,
-            *current_index,
-        );
+        if let Some(resampler) = resampler {
+            let buffer = resampler.buffer().read().unwrap();
+            channel_router_split_input(
+                sample.header.channel_count,
+                target_sample_count,
+                &buffer,
+                output,
+                source_index,
+                &routing,
+                envelope,
+            );
+        } else {
+            channel_router(
+                sample.header.channel_count,
+                target_sample_count,
+                &sample.data,
+                output,
+                source_index,
+                &routing,
+                envelope,
+            );
+        }
     }
-    *absolute_index += adjusted_len;
-    *current_index += adjusted_len;
 }
@@ -0,0 +1,789 @@
+use std::{
+    ops::RangeInclusive,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dynamics::{gain_reduction_ui, Compressor, Gate, Limiter},
+    equalizer::Equalizer,
+    history::ParameterChange,
+    id::{get_id_mgr, Id},
+    mixer::db_to_gain,
+};
+
+/// A parameter of a processor, as shown in the insert editor and stored in projects
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub range: RangeInclusive<f32>,
+    /// If the slider moves in proportion to the value, like for frequencies
+    pub logarithmic: bool,
+    /// The value a new processor starts at. Double clicking a slider goes back to it
+    pub default: f32,
+}
+
+impl Parameter {
+    /// Where `value` is in the range, from 0 to 1. Logarithmic parameters are spaced like their sliders
+    pub fn normalize(&self, value: f32) -> f32 {
+        let (start, end) = (*self.range.start(), *self.range.end());
+        let position = if self.logarithmic && start > 0.0 {
+            (value / start).ln() / (end / start).ln()
+        } else {
+            (value - start) / (end - start)
+        };
+
+        position.clamp(0.0, 1.0)
+    }
+
+    /// The value at `position` in the range, the opposite of `normalize`
+    pub fn denormalize(&self, position: f32) -> f32 {
+        let (start, end) = (*self.range.start(), *self.range.end());
+        let position = position.clamp(0.0, 1.0);
+
+        if self.logarithmic && start > 0.0 {
+            start * (end / start).powf(position)
+        } else {
+            start + (end - start) * position
+        }
+    }
+}
+
+/// Something that changes audio in place, like an equalizer or a compressor.
+///
+/// Buffers are interleaved with the channel count given to `prepare`.
+/// `process` runs in the audio thread, so it shouldn't allocate or block
+pub trait AudioProcessor: Send {
+    fn kind(&self) -> ProcessorKind;
+
+    /// Get ready to process blocks of up to `max_block_size` frames.
+    ///
+    /// This is called outside of the audio thread before the first block and whenever the format changes, so it may allocate
+    fn prepare(&mut self, sample_rate: u32, channels: u16, max_block_size: usize);
+
+    fn process(&mut self, buffer: &mut [f32]);
+
+    /// Forget any audio processed so far, like filter state or tails
+    fn reset(&mut self);
+
+    /// The number of frames the output is delayed by
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn parameters(&self) -> &[Parameter];
+
+    fn parameter(&self, index: usize) -> f32;
+
+    /// Set the parameter at `index`. Values are clamped to the range of the parameter
+    fn set_parameter(&mut self, index: usize, value: f32);
+
+    /// If the level can be detected from another track, see `process_sidechain`
+    fn accepts_sidechain(&self) -> bool {
+        false
+    }
+
+    /// Process `buffer` with the level detected from `sidechain` instead, the signal of another track in the same format
+    fn process_sidechain(&mut self, buffer: &mut [f32], _sidechain: &[f32]) {
+        self.process(buffer);
+    }
+
+    /// How far the processor is turning the signal down in decibels, for processors that do
+    fn gain_reduction(&self) -> Option<f32> {
+        None
+    }
+
+    /// The controls of the processor in the insert editor. By default every parameter gets a slider
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        for index in 0..self.parameters().len() {
+            self.parameter_slider(ui, index);
+        }
+    }
+
+    /// A slider for the parameter at `index`. Double clicking it goes back to the default
+    fn parameter_slider(&mut self, ui: &mut egui::Ui, index: usize) -> egui::Response {
+        let parameter = self.parameters()[index].clone();
+        let mut value = self.parameter(index);
+
+        let slider = egui::Slider::new(&mut value, parameter.range)
+            .text(parameter.name)
+            .suffix(format!(" {}", parameter.unit))
+            .logarithmic(parameter.logarithmic);
+
+        let response = ui.add(slider);
+        if response.double_clicked() {
+            self.set_parameter(index, parameter.default);
+        } else if response.changed() {
+            self.set_parameter(index, value);
+        }
+
+        response
+    }
+}
+
+/// The processors that can be inserted on a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessorKind {
+    Gain,
+    Equalizer,
+    Compressor,
+    Limiter,
+    Gate,
+}
+
+impl ProcessorKind {
+    pub const ALL: [ProcessorKind; 5] = [
+        ProcessorKind::Gain,
+        ProcessorKind::Equalizer,
+        ProcessorKind::Compressor,
+        ProcessorKind::Limiter,
+        ProcessorKind::Gate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessorKind::Gain => "Gain",
+            ProcessorKind::Equalizer => "Equalizer",
+            ProcessorKind::Compressor => "Compressor",
+            ProcessorKind::Limiter => "Limiter",
+            ProcessorKind::Gate => "Gate / Expander",
+        }
+    }
+
+    /// Create a processor of this kind with every parameter at its default
+    pub fn create(&self) -> Box<dyn AudioProcessor> {
+        match self {
+            ProcessorKind::Gain => Box::new(Gain::default()),
+            ProcessorKind::Equalizer => Box::new(Equalizer::default()),
+            ProcessorKind::Compressor => Box::new(Compressor::default()),
+            ProcessorKind::Limiter => Box::new(Limiter::default()),
+            ProcessorKind::Gate => Box::new(Gate::default()),
+        }
+    }
+}
+
+/// The format processors are prepared for before the audio thread runs them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Format {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// The most frames processed at once
+    pub max_block_size: usize,
+}
+
+impl Format {
+    /// If a processor prepared for this format can also process `other` without being prepared again
+    fn covers(&self, other: &Format) -> bool {
+        self.sample_rate == other.sample_rate
+            && self.channels == other.channels
+            && self.max_block_size >= other.max_block_size
+    }
+}
+
+/// A processor shared between the track and the audio thread
+struct SharedProcessor {
+    processor: Box<dyn AudioProcessor>,
+    format: Option<Format>,
+    /// If the processor was skipped in the last block
+    bypassed: bool,
+    /// The parameters changed in the insert editor since they were last taken, for automation
+    touched: Vec<usize>,
+}
+
+/// The copy of a processor the insert editor draws
+struct Editor {
+    processor: Box<dyn AudioProcessor>,
+    format: Option<Format>,
+}
+
+/// A processor in the insert chain of a track.
+///
+/// Clones share the processor, so snapshots in the history bring back the same processor with its settings.
+/// The editor draws a copy of the processor, so the audio thread never waits for the user interface
+#[derive(Clone)]
+pub struct Insert {
+    pub id: Id,
+    /// Bypassed inserts pass the audio through untouched
+    pub bypass: bool,
+    /// The track the level is detected from instead of the input, for processors that accept a sidechain
+    pub sidechain: Option<Id>,
+    processor: Arc<Mutex<SharedProcessor>>,
+    editor: Arc<Mutex<Editor>>,
+}
+
+impl Insert {
+    pub fn new(processor: Box<dyn AudioProcessor>) -> Insert {
+        let editor = Editor {
+            processor: processor.kind().create(),
+            format: None,
+        };
+
+        Insert {
+            id: get_id_mgr().gen_id(),
+            bypass: false,
+            sidechain: None,
+            processor: Arc::new(Mutex::new(SharedProcessor {
+                processor,
+                format: None,
+                bypassed: false,
+                touched: Vec::new(),
+            })),
+            editor: Arc::new(Mutex::new(editor)),
+        }
+    }
+
+    /// A copy of the insert with a processor of its own at the same settings, e.g. to render offline while the insert plays
+    pub fn duplicate(&self) -> Insert {
+        Insert {
+            id: self.id,
+            bypass: self.bypass,
+            sidechain: self.sidechain,
+            ..Insert::new(self.copy_processor())
+        }
+    }
+
+    /// A new processor of the same kind with the same parameters
+    pub fn copy_processor(&self) -> Box<dyn AudioProcessor> {
+        let original = self.processor();
+        let mut processor = original.kind().create();
+        for index in 0..original.parameters().len() {
+            processor.set_parameter(index, original.parameter(index));
+        }
+
+        processor
+    }
+
+    /// Lock the processor to read or change its parameters
+    pub fn processor(&self) -> ProcessorGuard<'_> {
+        ProcessorGuard(self.processor.lock().unwrap())
+    }
+
+    /// The parameters changed in the insert editor since the last call
+    pub fn take_touched(&self) -> Vec<usize> {
+        std::mem::take(&mut self.processor.lock().unwrap().touched)
+    }
+
+    /// Prepare the processor for `format` unless it already is.
+    ///
+    /// Preparing allocates and clears the processor, so this is done outside of the audio thread
+    /// when the insert is added or the output is started
+    pub fn prepare(&self, format: Format) {
+        let mut shared = self.processor.lock().unwrap();
+
+        if shared
+            .format
+            .is_some_and(|prepared| prepared.covers(&format))
+        {
+            return;
+        }
+
+        shared
+            .processor
+            .prepare(format.sample_rate, format.channels, format.max_block_size);
+        shared.format = Some(format);
+    }
+
+    /// Run `buffer` through the processor.
+    ///
+    /// `sidechain` is the signal of the sidechain track if it was found.
+    /// The audio passes through untouched if the processor wasn't prepared for the format with `prepare`.
+    /// Buffers longer than the prepared block size are processed in several blocks
+    fn process(
+        &self,
+        buffer: &mut [f32],
+        sidechain: Option<&[f32]>,
+        sample_rate: u32,
+        channels: u16,
+    ) {
+        self.process_automated(buffer, sidechain, sample_rate, channels, |_| {
+            std::iter::empty()
+        });
+    }
+
+    /// Run `buffer` through the processor like `process`, with parameters that change during the buffer.
+    ///
+    /// `values` gives the index and value of every automated parameter at a frame of the buffer, from 0 to 1 across the range
+    /// of the parameter. The buffer is split wherever one of them changes, so every frame is processed with its own values.
+    /// The processor is only locked once
+    pub fn process_automated<I: Iterator<Item = (usize, f32)>>(
+        &self,
+        buffer: &mut [f32],
+        sidechain: Option<&[f32]>,
+        sample_rate: u32,
+        channels: u16,
+        values: impl Fn(usize) -> I,
+    ) {
+        let mut shared = self.processor.lock().unwrap();
+
+        if self.bypass {
+            shared.bypassed = true;
+            return;
+        }
+
+        let Some(format) = shared
+            .format
+            .filter(|format| format.sample_rate == sample_rate && format.channels == channels)
+        else {
+            return;
+        };
+
+        if shared.bypassed {
+            // Whatever was left from before the bypass doesn't belong to the audio now
+            shared.processor.reset();
+            shared.bypassed = false;
+        }
+
+        let channels = channels as usize;
+        let frames = buffer.len() / channels;
+        let sidechain = sidechain.filter(|sidechain| sidechain.len() == buffer.len());
+        let processor = &mut shared.processor;
+
+        let mut start = 0;
+        while start < frames {
+            for (index, value) in values(start) {
+                if let Some(parameter) = processor.parameters().get(index) {
+                    let value = parameter.denormalize(value);
+                    processor.set_parameter(index, value);
+                }
+            }
+
+            // The block goes on until a value changes or it's as long as the processor was prepared for
+            let limit = (start + format.max_block_size.max(1)).min(frames);
+            let mut end = start + 1;
+            while end < limit && values(end).eq(values(end - 1)) {
+                end += 1;
+            }
+
+            let block = start * channels..end * channels;
+            match sidechain {
+                Some(sidechain) => {
+                    processor.process_sidechain(&mut buffer[block.clone()], &sidechain[block])
+                }
+                None => processor.process(&mut buffer[block]),
+            }
+
+            start = end;
+        }
+    }
+
+    /// Draw the controls of the processor and the gain reduction, for processors that have one.
+    ///
+    /// The controls are drawn on a copy of the processor. The processor itself is only locked to copy the parameters
+    /// to the copy and the changed ones back. Returns the parameters that were changed, with their previous values
+    fn ui(&self, ui: &mut egui::Ui) -> Vec<(usize, f32)> {
+        let (values, format, reduction) = {
+            let shared = self.processor.lock().unwrap();
+            let processor = &shared.processor;
+            let values: Vec<f32> = (0..processor.parameters().len())
+                .map(|index| processor.parameter(index))
+                .collect();
+
+            (values, shared.format, processor.gain_reduction())
+        };
+
+        let mut editor = self.editor.lock().unwrap();
+        if editor.format != format {
+            // The copy is drawn at the rate of the processor, e.g. for the response of the equalizer
+            if let Some(format) = format {
+                editor.processor.prepare(
+                    format.sample_rate,
+                    format.channels,
+                    format.max_block_size,
+                );
+            }
+            editor.format = format;
+        }
+
+        for (index, &value) in values.iter().enumerate() {
+            if editor.processor.parameter(index) != value {
+                editor.processor.set_parameter(index, value);
+            }
+        }
+
+        editor.processor.ui(ui);
+        if let Some(reduction) = reduction {
+            gain_reduction_ui(ui, reduction);
+        }
+
+        let changed: Vec<_> = values
+            .into_iter()
+            .enumerate()
+            .filter(|&(index, value)| editor.processor.parameter(index) != value)
+            .collect();
+
+        if !changed.is_empty() {
+            let mut processor = self.processor();
+            for &(index, _) in &changed {
+                processor.set_parameter(index, editor.processor.parameter(index));
+                processor.touch(index);
+            }
+        }
+
+        changed
+    }
+}
+
+/// Access to the processor of an insert
+pub struct ProcessorGuard<'a>(MutexGuard<'a, SharedProcessor>);
+
+impl<'a> std::ops::Deref for ProcessorGuard<'a> {
+    type Target = dyn AudioProcessor;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.processor.as_ref()
+    }
+}
+
+impl<'a> std::ops::DerefMut for ProcessorGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.processor.as_mut()
+    }
+}
+
+impl<'a> ProcessorGuard<'a> {
+    /// Remember that the parameter at `index` was changed in the editor, see `Insert::take_touched`
+    pub fn touch(&mut self, index: usize) {
+        if !self.0.touched.contains(&index) {
+            self.0.touched.push(index);
+        }
+    }
+}
+
+/// Run `buffer` through every insert in order.
+///
+/// `sidechain` finds the signal of a track for the inserts that are keyed from one
+pub fn process_inserts<'a>(
+    inserts: &[Insert],
+    buffer: &mut [f32],
+    sample_rate: u32,
+    channels: u16,
+    sidechain: impl Fn(Id) -> Option<&'a [f32]>,
+) {
+    if channels == 0 {
+        return;
+    }
+
+    for insert in inserts {
+        let sidechain = insert.sidechain.and_then(&sidechain);
+        insert.process(buffer, sidechain, sample_rate, channels);
+    }
+}
+
+/// The total latency of the inserts that aren't bypassed, in frames
+pub fn inserts_latency(inserts: &[Insert]) -> usize {
+    inserts
+        .iter()
+        .filter(|insert| !insert.bypass)
+        .map(|insert| insert.processor().latency())
+        .sum()
+}
+
+/// A change to the position of an insert in the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InsertAction {
+    Up,
+    Down,
+    Remove,
+}
+
+/// What was changed in the editor of an insert chain
+#[derive(Default)]
+pub struct ChainEdit {
+    /// The name of the change to the chain if there was one
+    pub change: Option<&'static str>,
+    /// The parameters that were changed in place, ready to be recorded in the history
+    pub parameters: Vec<ParameterChange>,
+}
+
+/// Draw the editor of an insert chain, of a track or a bus.
+///
+/// `sources` are the tracks the inserts can be keyed from.
+/// Parameters are changed in place. Changes made while a button is held down share a gesture, so a drag is undone in one step
+pub fn insert_chain_ui(
+    ui: &mut egui::Ui,
+    id: egui::Id,
+    inserts: &mut Vec<Insert>,
+    sources: &[(Id, String)],
+) -> ChainEdit {
+    // The name of the change to the chain
+    let mut change = None;
+    let mut parameters = Vec::new();
+    let dragging = ui.input(|input| input.pointer.any_down());
+
+    if inserts.is_empty() {
+        ui.label("No inserts");
+    }
+
+    let count = inserts.len();
+    // The insert that was moved or removed
+    let mut action = None;
+
+    for (index, insert) in inserts.iter_mut().enumerate() {
+        let kind = insert.processor().kind();
+
+        ui.horizontal(|ui| {
+            let mut active = !insert.bypass;
+            if ui
+                .checkbox(&mut active, kind.name())
+                .on_hover_text("Uncheck to bypass")
+                .changed()
+            {
+                insert.bypass = !active;
+                change = Some("Bypass Insert");
+            }
+
+            if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                action = Some((index, InsertAction::Up));
+            }
+            if ui
+                .add_enabled(index + 1 < count, egui::Button::new("Down"))
+                .clicked()
+            {
+                action = Some((index, InsertAction::Down));
+            }
+            if ui.button("Remove").clicked() {
+                action = Some((index, InsertAction::Remove));
+            }
+        });
+
+        ui.indent(insert.id, |ui| {
+            for (index, previous) in insert.ui(ui) {
+                let mut parameter = ParameterChange::new(insert, index, previous);
+                if dragging {
+                    parameter = parameter.with_gesture(egui::Id::new((insert.id, index)));
+                }
+
+                parameters.push(parameter);
+            }
+
+            if !insert.processor().accepts_sidechain() {
+                return;
+            }
+
+            let source_name = |source: Option<Id>| {
+                source
+                    .and_then(|source| sources.iter().find(|(track, _)| *track == source))
+                    .map_or("None", |(_, name)| name.as_str())
+            };
+
+            egui::ComboBox::from_label("Sidechain")
+                .selected_text(source_name(insert.sidechain))
+                .show_ui(ui, |ui| {
+                    let sources = sources.iter().map(|(track, _)| Some(*track));
+
+                    for source in std::iter::once(None).chain(sources) {
+                        if ui
+                            .selectable_label(insert.sidechain == source, source_name(source))
+                            .clicked()
+                        {
+                            insert.sidechain = source;
+                            change = Some("Set Sidechain");
+                        }
+                    }
+                });
+        });
+
+        ui.separator();
+    }
+
+    match action {
+        Some((index, InsertAction::Up)) => {
+            inserts.swap(index, index - 1);
+            change = Some("Move Insert");
+        }
+        Some((index, InsertAction::Down)) => {
+            inserts.swap(index, index + 1);
+            change = Some("Move Insert");
+        }
+        Some((index, InsertAction::Remove)) => {
+            inserts.remove(index);
+            change = Some("Remove Insert");
+        }
+        None => {}
+    }
+
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(id.with("add"))
+            .selected_text("Add")
+            .show_ui(ui, |ui| {
+                for kind in ProcessorKind::ALL {
+                    if ui.selectable_label(false, kind.name()).clicked() {
+                        inserts.push(Insert::new(kind.create()));
+                        change = Some("Add Insert");
+                    }
+                }
+            });
+
+        ui.label(format!("Latency: {} samples", inserts_latency(inserts)));
+    });
+
+    ChainEdit { change, parameters }
+}
+
+/// Changes the level and polarity of the signal
+#[derive(Debug, Clone, Default)]
+pub struct Gain {
+    /// In decibels
+    gain: f32,
+    /// Flips the polarity when above 0.5
+    invert: f32,
+}
+
+impl Gain {
+    const PARAMETERS: [Parameter; 2] = [
+        Parameter {
+            name: "Gain",
+            unit: "dB",
+            range: -24.0..=24.0,
+            logarithmic: false,
+            default: 0.0,
+        },
+        Parameter {
+            name: "Invert",
+            unit: "",
+            range: 0.0..=1.0,
+            logarithmic: false,
+            default: 0.0,
+        },
+    ];
+}
+
+impl AudioProcessor for Gain {
+    fn kind(&self) -> ProcessorKind {
+        ProcessorKind::Gain
+    }
+
+    fn prepare(&mut self, _sample_rate: u32, _channels: u16, _max_block_size: usize) {}
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        let mut gain = db_to_gain(self.gain);
+        if self.invert > 0.5 {
+            gain = -gain;
+        }
+
+        buffer.iter_mut().for_each(|sample| *sample *= gain);
+    }
+
+    fn reset(&mut self) {}
+
+    fn parameters(&self) -> &[Parameter] {
+        &Gain::PARAMETERS
+    }
+
+    fn parameter(&self, index: usize) -> f32 {
+        match index {
+            0 => self.gain,
+            1 => self.invert,
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: usize, value: f32) {
+        let Some(parameter) = Gain::PARAMETERS.get(index) else {
+            return;
+        };
+        let value = value.clamp(*parameter.range.start(), *parameter.range.end());
+
+        match index {
+            0 => self.gain = value,
+            _ => self.invert = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history::History, util::sine};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn format(max_block_size: usize) -> Format {
+        Format {
+            sample_rate: SAMPLE_RATE,
+            channels: 2,
+            max_block_size,
+        }
+    }
+
+    fn gain(db: f32) -> Insert {
+        let insert = Insert::new(ProcessorKind::Gain.create());
+        insert.processor().set_parameter(0, db);
+        insert
+    }
+
+    #[test]
+    fn inserts_process_once_prepared() {
+        let inserts = [gain(-6.0)];
+        let input = sine(997.0, 0.5, SAMPLE_RATE, 256, 2);
+
+        let mut output = input.clone();
+        process_inserts(&inserts, &mut output, SAMPLE_RATE, 2, |_| None);
+        assert_eq!(output, input);
+
+        inserts[0].prepare(format(256));
+        process_inserts(&inserts, &mut output, SAMPLE_RATE, 2, |_| None);
+        for (output, input) in output.iter().zip(&input) {
+            assert!((output - input * db_to_gain(-6.0)).abs() < 1e-6);
+        }
+
+        // A different format isn't processed until the insert is prepared for it
+        let mut mono = input.clone();
+        process_inserts(&inserts, &mut mono, SAMPLE_RATE, 1, |_| None);
+        assert_eq!(mono, input);
+    }
+
+    #[test]
+    fn long_buffers_are_processed_in_blocks() {
+        const BLOCK: usize = 64;
+
+        let insert = Insert::new(ProcessorKind::Limiter.create());
+        insert.processor().set_parameter(0, 12.0);
+        insert.prepare(format(BLOCK));
+
+        let mut limiter = ProcessorKind::Limiter.create();
+        limiter.set_parameter(0, 12.0);
+        limiter.prepare(SAMPLE_RATE, 2, BLOCK);
+
+        let input = sine(997.0, 0.5, SAMPLE_RATE, 1000, 2);
+        let mut output = input.clone();
+        insert.process(&mut output, None, SAMPLE_RATE, 2);
+
+        let mut expected = input;
+        for block in expected.chunks_mut(BLOCK * 2) {
+            limiter.process(block);
+        }
+
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parameter_changes_are_undone() {
+        let insert = gain(0.0);
+        let mut history = History::default();
+        let gesture = egui::Id::new("drag");
+
+        // A drag over three values is undone in one step
+        for (previous, value) in [(0.0, 1.0), (1.0, 2.0), (2.0, 3.0)] {
+            insert.processor().set_parameter(0, value);
+            history.push(ParameterChange::new(&insert, 0, previous).with_gesture(gesture));
+        }
+        history.end_gesture();
+
+        insert.processor().set_parameter(0, 4.0);
+        history.push(ParameterChange::new(&insert, 0, 3.0));
+        assert_eq!(history.undo_name(), Some("Change Gain"));
+
+        history.undo(&[]);
+        assert_eq!(insert.processor().parameter(0), 3.0);
+        history.undo(&[]);
+        assert_eq!(insert.processor().parameter(0), 0.0);
+
+        history.redo(&[]);
+        assert_eq!(insert.processor().parameter(0), 3.0);
+        history.redo(&[]);
+        assert_eq!(insert.processor().parameter(0), 4.0);
+    }
+}
@@ -0,0 +1,833 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    automation::{AutomationLane, AutomationMode, AutomationTarget, Breakpoint},
+    bus::{AuxSend, Bus, Buses, MasterBus, Output},
+    channel::{ChannelMapping, GainMatrix, Speakers},
+    clip::Clip,
+    fade::Fade,
+    id::Id,
+    mixer::TrackLevels,
+    processor::{Insert, ProcessorKind},
+    sample::Sample,
+    state::State,
+    track::Track,
+};
+
+/// The version of the project format this build writes.
+/// Projects with a newer version are rejected when loading
+///
+/// Version history:
+/// 1. Clips are whole samples laid back to back
+/// 2. Clips have a timeline position and a range of the sample
+/// 3. Tracks store a gain matrix instead of a channel mapping
+/// 4. Clips have a gain per channel
+/// 5. Clips have fades
+/// 6. Tracks have insert chains
+/// 7. Inserts can be keyed from another track
+/// 8. Buses, sends and the master bus
+/// 9. Tracks have automation lanes
+pub const PROJECT_VERSION: u32 = 9;
+
+/// The on-disk representation of a session
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub version: u32,
+    pub tracks: Vec<TrackFile>,
+    #[serde(default)]
+    pub buses: Vec<BusFile>,
+    #[serde(default)]
+    pub master: MasterFile,
+}
+
+/// The on-disk representation of a track
+///
+/// `routing` is stored as the gains from each input channel to each speaker, one row per input.
+/// `channel_mapping` is only read from older projects. It is the speaker bits each input channel is mapped to
+/// `output` is the index of the bus the track goes to. A missing `output` goes to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrackFile {
+    pub name: String,
+    pub view_range: Range<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_mapping: Option<Vec<Option<u16>>>,
+    #[serde(default)]
+    pub routing: Option<Vec<Vec<f32>>>,
+    #[serde(default)]
+    pub levels: TrackLevels,
+    #[serde(default)]
+    pub inserts: Vec<InsertFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sends: Vec<SendFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub automation: Vec<LaneFile>,
+    pub clips: Vec<ClipFile>,
+}
+
+/// The on-disk representation of an aux bus
+///
+/// `mapping` is the speaker bits each channel of the bus is mapped to. A missing `mapping` passes every channel through
+/// `output` is the index of the bus the bus goes to. A missing `output` goes to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BusFile {
+    pub name: String,
+    #[serde(default)]
+    pub levels: TrackLevels,
+    #[serde(default)]
+    pub inserts: Vec<InsertFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<Vec<Option<u16>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sends: Vec<SendFile>,
+}
+
+/// The on-disk representation of the master bus. `volume` is in decibels
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MasterFile {
+    #[serde(default)]
+    pub volume: f32,
+    #[serde(default)]
+    pub inserts: Vec<InsertFile>,
+}
+
+/// The on-disk representation of a send
+///
+/// `bus` is the index of the bus the send goes to and `level` is in decibels
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendFile {
+    pub bus: usize,
+    pub level: f32,
+    #[serde(default)]
+    pub pre_fader: bool,
+}
+
+impl SendFile {
+    /// `bus_ids` are the ids of the buses in the project, in order. Sends to missing buses are skipped
+    fn from_sends(sends: &[AuxSend], bus_ids: &[Id]) -> Vec<SendFile> {
+        sends
+            .iter()
+            .filter_map(|send| {
+                Some(SendFile {
+                    bus: bus_ids.iter().position(|id| *id == send.bus)?,
+                    level: send.level,
+                    pre_fader: send.pre_fader,
+                })
+            })
+            .collect()
+    }
+
+    fn into_send(self, bus_ids: &[Id]) -> Option<AuxSend> {
+        Some(AuxSend {
+            bus: *bus_ids.get(self.bus)?,
+            level: self.level,
+            pre_fader: self.pre_fader,
+        })
+    }
+}
+
+/// The on-disk representation of an automation lane
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LaneFile {
+    pub target: TargetFile,
+    #[serde(default)]
+    pub mode: AutomationMode,
+    pub points: Vec<Breakpoint>,
+}
+
+/// The control an automation lane changes. `insert` is the index of the insert on the track
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum TargetFile {
+    Volume,
+    Pan,
+    Parameter { insert: usize, index: usize },
+}
+
+impl LaneFile {
+    /// `inserts` are the inserts of the track. Lanes of parameters of removed inserts are skipped
+    fn from_lane(lane: &AutomationLane, inserts: &[Insert]) -> Option<LaneFile> {
+        let target = match lane.target {
+            AutomationTarget::Volume => TargetFile::Volume,
+            AutomationTarget::Pan => TargetFile::Pan,
+            AutomationTarget::Parameter { insert, index } => TargetFile::Parameter {
+                insert: inserts.iter().position(|other| other.id == insert)?,
+                index,
+            },
+        };
+
+        Some(LaneFile {
+            target,
+            mode: lane.mode,
+            points: lane.points().to_vec(),
+        })
+    }
+
+    fn into_lane(self, inserts: &[Insert]) -> Option<AutomationLane> {
+        let target = match self.target {
+            TargetFile::Volume => AutomationTarget::Volume,
+            TargetFile::Pan => AutomationTarget::Pan,
+            TargetFile::Parameter { insert, index } => AutomationTarget::Parameter {
+                insert: inserts.get(insert)?.id,
+                index,
+            },
+        };
+
+        Some(AutomationLane::with_points(target, self.mode, self.points))
+    }
+}
+
+/// The on-disk representation of an insert
+///
+/// `parameters` are the values of the parameters of the processor in order. Missing parameters keep their default
+/// `sidechain` is the index of the track the insert is keyed from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InsertFile {
+    pub kind: ProcessorKind,
+    #[serde(default)]
+    pub bypass: bool,
+    #[serde(default)]
+    pub parameters: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidechain: Option<usize>,
+}
+
+impl InsertFile {
+    /// `track_ids` are the ids of the tracks in the project, in order
+    pub fn from_insert(insert: &Insert, track_ids: &[Id]) -> InsertFile {
+        let processor = insert.processor();
+
+        InsertFile {
+            kind: processor.kind(),
+            bypass: insert.bypass,
+            parameters: (0..processor.parameters().len())
+                .map(|index| processor.parameter(index))
+                .collect(),
+            sidechain: insert
+                .sidechain
+                .and_then(|sidechain| track_ids.iter().position(|id| *id == sidechain)),
+        }
+    }
+
+    /// The sidechain is left unset, since it can only be resolved once every track exists
+    pub fn into_insert(self) -> Insert {
+        let mut processor = self.kind.create();
+        for (index, value) in self.parameters.into_iter().enumerate() {
+            processor.set_parameter(index, value);
+        }
+
+        let mut insert = Insert::new(processor);
+        insert.bypass = self.bypass;
+
+        insert
+    }
+
+    /// Build the insert with its sidechain. `track_ids` are the ids of the tracks in the project, in order
+    pub fn into_keyed_insert(self, track_ids: &[Id]) -> Insert {
+        let sidechain = self.sidechain;
+        let mut insert = self.into_insert();
+        insert.sidechain = sidechain.and_then(|index| track_ids.get(index).copied());
+
+        insert
+    }
+}
+
+/// The on-disk representation of a clip
+///
+/// `path` is relative to the directory of the project file when possible
+/// `start` is the position on the timeline in microseconds
+/// `offset` and `length` are the range of the sample that is played in frames.
+/// A missing `length` plays until the end of the sample
+/// `gain` is the linear gain of each channel of the sample. Missing channels are at unity
+/// `fade_in` and `fade_out` lengths are in microseconds
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClipFile {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub start: u64,
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default)]
+    pub length: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gain: Vec<f32>,
+    #[serde(default)]
+    pub fade_in: Fade,
+    #[serde(default)]
+    pub fade_out: Fade,
+}
+
+impl ProjectFile {
+    /// Build the on-disk representation of `tracks` and `buses`. Audio paths are made relative to `project_dir`
+    pub fn from_session(
+        tracks: &[Arc<RwLock<Track>>],
+        buses: &Buses,
+        project_dir: &Path,
+    ) -> ProjectFile {
+        let track_ids: Vec<_> = tracks
+            .iter()
+            .map(|track| track.read().unwrap().id)
+            .collect();
+        let bus_ids: Vec<_> = buses.buses().iter().map(|bus| bus.id).collect();
+        let output_index = |output: Output| match output {
+            Output::Master => None,
+            Output::Bus(bus) => bus_ids.iter().position(|id| *id == bus),
+        };
+        let inserts_file = |inserts: &[Insert]| {
+            inserts
+                .iter()
+                .map(|insert| InsertFile::from_insert(insert, &track_ids))
+                .collect()
+        };
+
+        let tracks = tracks
+            .iter()
+            .map(|track| {
+                let track = track.read().unwrap();
+
+                TrackFile {
+                    name: track.name.clone(),
+                    view_range: track.view_range.clone(),
+                    channel_mapping: None,
+                    routing: track
+                        .routing
+                        .as_ref()
+                        .map(|routing| routing.rows(Speakers::MAX_COUNT)),
+                    levels: track.levels,
+                    inserts: inserts_file(&track.inserts),
+                    output: output_index(track.output),
+                    sends: SendFile::from_sends(&track.sends, &bus_ids),
+                    automation: track
+                        .automation
+                        .iter()
+                        .filter_map(|lane| LaneFile::from_lane(lane, &track.inserts))
+                        .collect(),
+                    clips: track
+                        .clips
+                        .iter()
+                        .map(|clip| ClipFile {
+                            name: clip.sample.name.clone(),
+                            path: relative_path(project_dir, &clip.sample.path),
+                            start: clip.start,
+                            offset: clip.offset,
+                            length: Some(clip.length),
+                            gain: if clip.has_gain() {
+                                clip.gain[..clip.sample.header.channel_count as usize].to_vec()
+                            } else {
+                                Vec::new()
+                            },
+                            fade_in: clip.fade_in,
+                            fade_out: clip.fade_out,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        ProjectFile {
+            version: PROJECT_VERSION,
+            tracks,
+            buses: buses
+                .buses()
+                .iter()
+                .map(|bus| BusFile {
+                    name: bus.name.clone(),
+                    levels: bus.levels,
+                    inserts: inserts_file(&bus.inserts),
+                    mapping: bus.mapping.as_ref().map(mapping_to_file),
+                    output: output_index(bus.output()),
+                    sends: SendFile::from_sends(bus.sends(), &bus_ids),
+                })
+                .collect(),
+            master: MasterFile {
+                volume: buses.master.volume,
+                inserts: inserts_file(&buses.master.inserts),
+            },
+        }
+    }
+
+    /// Load the audio referenced by the project and build the tracks and buses.
+    ///
+    /// Relative audio paths are resolved against `project_dir`.
+    /// Fails with `NotFound` if any referenced audio file is missing
+    pub fn into_session(
+        mut self,
+        project_dir: &Path,
+        app_state: &Arc<RwLock<State>>,
+    ) -> io::Result<(Vec<Arc<RwLock<Track>>>, Buses)> {
+        // Clips that reference the same file with the same name share the sample data
+        let mut samples: HashMap<(PathBuf, String), Arc<Sample>> = HashMap::new();
+
+        // Version 1 projects didn't store clip positions
+        let sequential = self.version < 2;
+
+        // Sidechains refer to tracks by index, they are resolved once every track exists
+        let sidechains: Vec<Vec<Option<usize>>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                track
+                    .inserts
+                    .iter()
+                    .map(|insert| insert.sidechain)
+                    .collect()
+            })
+            .collect();
+        // Outputs and sends refer to buses by index, they are resolved once every bus exists
+        let routes: Vec<(Option<usize>, Vec<SendFile>)> = self
+            .tracks
+            .iter_mut()
+            .map(|track| (track.output, std::mem::take(&mut track.sends)))
+            .collect();
+
+        let tracks = self
+            .tracks
+            .into_iter()
+            .map(|track_file| {
+                let mut end = 0;
+                let clips = track_file
+                    .clips
+                    .into_iter()
+                    .map(|clip| {
+                        let path = project_dir.join(&clip.path);
+
+                        if !path.is_file() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!(
+                                    "Audio file `{}` used by clip `{}` on track `{}` is missing",
+                                    path.display(),
+                                    clip.name,
+                                    track_file.name
+                                ),
+                            ));
+                        }
+
+                        let key = (path, clip.name);
+                        let sample = match samples.get(&key) {
+                            Some(sample) => sample.clone(),
+                            None => {
+                                let sample = Arc::new(Sample::load_from_file(
+                                    &key.0,
+                                    Some(&key.1),
+                                    app_state,
+                                )?);
+                                samples.insert(key, sample.clone());
+
+                                sample
+                            }
+                        };
+
+                        let start = if sequential { end } else { clip.start };
+                        let length = clip.length.unwrap_or(sample.frames() as u64);
+                        let (gain, fade_in, fade_out) = (clip.gain, clip.fade_in, clip.fade_out);
+                        let mut clip = Clip::with_range(sample, start, clip.offset, length);
+                        for (channel, value) in clip.gain.iter_mut().zip(gain) {
+                            *channel = value;
+                        }
+                        clip.fade_in = fade_in;
+                        clip.fade_out = fade_out;
+                        end = clip.end();
+
+                        Ok(clip)
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                let mut track = Track::new(track_file.name, clips, app_state.clone());
+
+                track.view_range = track_file.view_range;
+                track.levels = track_file.levels;
+                track.inserts = track_file
+                    .inserts
+                    .into_iter()
+                    .map(InsertFile::into_insert)
+                    .collect();
+                track.automation = track_file
+                    .automation
+                    .into_iter()
+                    .filter_map(|lane| lane.into_lane(&track.inserts))
+                    .collect();
+                track.routing = match (track_file.routing, track_file.channel_mapping) {
+                    (Some(rows), _) => Some(GainMatrix::from_rows(&rows)),
+                    // Version 2 projects stored which speakers each input is mapped to
+                    (None, Some(inputs)) => Some(mapping_from_file(&inputs).into()),
+                    (None, None) => None,
+                };
+
+                Ok(Arc::new(RwLock::new(track)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let track_ids: Vec<_> = tracks
+            .iter()
+            .map(|track| track.read().unwrap().id)
+            .collect();
+        for (track, sidechains) in tracks.iter().zip(sidechains) {
+            let mut track = track.write().unwrap();
+            for (insert, sidechain) in track.inserts.iter_mut().zip(sidechains) {
+                insert.sidechain = sidechain.and_then(|index| track_ids.get(index).copied());
+            }
+        }
+
+        let mut buses = Buses::default();
+        buses.master = MasterBus {
+            volume: self.master.volume,
+            inserts: self
+                .master
+                .inserts
+                .into_iter()
+                .map(|insert| insert.into_keyed_insert(&track_ids))
+                .collect(),
+        };
+
+        let mut bus_routes = Vec::new();
+        let bus_ids: Vec<_> = self
+            .buses
+            .into_iter()
+            .map(|bus_file| {
+                let mut bus = Bus::new(bus_file.name);
+                bus.levels = bus_file.levels;
+                bus.inserts = bus_file
+                    .inserts
+                    .into_iter()
+                    .map(|insert| insert.into_keyed_insert(&track_ids))
+                    .collect();
+                bus.mapping = bus_file.mapping.as_deref().map(mapping_from_file);
+                bus_routes.push((bus_file.output, bus_file.sends));
+
+                buses.add(bus)
+            })
+            .collect();
+
+        // Buses can only be routed once they all exist, and not into a cycle
+        for (bus, (output, sends)) in bus_ids.iter().zip(bus_routes) {
+            let output = output.and_then(|index| bus_ids.get(index).copied());
+            if let Some(target) = output {
+                if !buses.set_output(*bus, Output::Bus(target)) {
+                    warn!("Ignoring bus output that would make a cycle");
+                }
+            }
+
+            for send in sends
+                .into_iter()
+                .filter_map(|send| send.into_send(&bus_ids))
+            {
+                if !buses.add_send(*bus, send) {
+                    warn!("Ignoring bus send that would make a cycle");
+                }
+            }
+        }
+
+        for (track, (output, sends)) in tracks.iter().zip(routes) {
+            let mut track = track.write().unwrap();
+            track.output = output
+                .and_then(|index| bus_ids.get(index).copied())
+                .map_or(Output::Master, Output::Bus);
+            track.sends = sends
+                .into_iter()
+                .filter_map(|send| send.into_send(&bus_ids))
+                .collect();
+        }
+
+        Ok((tracks, buses))
+    }
+}
+
+/// The speaker bits each channel of `mapping` is mapped to
+fn mapping_to_file(mapping: &ChannelMapping) -> Vec<Option<u16>> {
+    (0..Speakers::MAX_COUNT)
+        .map(|channel| mapping[channel].map(|speakers| speakers.bits()))
+        .collect()
+}
+
+fn mapping_from_file(channels: &[Option<u16>]) -> ChannelMapping {
+    let mut mapping = ChannelMapping::empty();
+    for (channel, speakers) in channels.iter().take(Speakers::MAX_COUNT).enumerate() {
+        mapping[channel] = speakers.map(Speakers::from_bits_truncate);
+    }
+
+    mapping
+}
+
+/// Save `tracks` and `buses` as a project file at `path`
+pub fn save(
+    path: impl AsRef<Path>,
+    tracks: &[Arc<RwLock<Track>>],
+    buses: &Buses,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let project = ProjectFile::from_session(tracks, buses, &project_dir(path));
+
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(file, &project)?;
+
+    info!("Saved project to {}", path.display());
+
+    Ok(())
+}
+
+/// Load the project file at `path` and all of the audio it references
+pub fn load(
+    path: impl AsRef<Path>,
+    app_state: &Arc<RwLock<State>>,
+) -> io::Result<(Vec<Arc<RwLock<Track>>>, Buses)> {
+    let path = path.as_ref();
+
+    let file = BufReader::new(File::open(path)?);
+    let project: ProjectFile = serde_json::from_reader(file)?;
+
+    if project.version > PROJECT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Project `{}` was saved with a newer version ({}) than supported ({})",
+                path.display(),
+                project.version,
+                PROJECT_VERSION
+            ),
+        ));
+    }
+
+    info!("Loading project {}", path.display());
+
+    project.into_session(&project_dir(path), app_state)
+}
+
+/// The directory relative audio paths in the project at `path` are resolved against
+fn project_dir(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// Express `path` relative to the directory `base`.
+///
+/// If `path` can't be made absolute or is on a different root than `base` it is returned unchanged
+fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let Ok(path) = std::fs::canonicalize(path) else {
+        return path.to_path_buf();
+    };
+
+    let mut base_components = base.components().peekable();
+    let mut path_components = path.components().peekable();
+
+    // Skip the shared prefix
+    while let (Some(a), Some(b)) = (base_components.peek(), path_components.peek()) {
+        if a != b {
+            break;
+        }
+
+        base_components.next();
+        path_components.next();
+    }
+
+    // Paths on different roots (e.g. drive letters) can't be relative to each other
+    if matches!(
+        path_components.peek(),
+        Some(Component::Prefix(_) | Component::RootDir)
+    ) {
+        return path;
+    }
+
+    base_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        channel::Layout,
+        util::sine,
+        wave_file::{self, BitDepth},
+    };
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// An empty directory of its own for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "audio_editor_project_{name}_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("audio")).unwrap();
+
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    /// Write 100 ms of a stereo tone to `path`
+    fn write_tone(path: &Path) {
+        let mut file = BufWriter::new(File::create(path).unwrap());
+        wave_file::write(
+            &mut file,
+            SAMPLE_RATE,
+            2,
+            Layout::from_channel_count(2),
+            BitDepth::ThirtyTwoFloat,
+            &sine(440.0, 0.5, SAMPLE_RATE, 4800, 2),
+            false,
+        )
+        .unwrap();
+    }
+
+    fn state() -> Arc<RwLock<State>> {
+        Arc::new(RwLock::new(State::new(egui::Context::default(), None)))
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let dir = test_dir("round_trip");
+        let audio = dir.join("audio").join("tone.wav");
+        write_tone(&audio);
+
+        let state = state();
+        let sample = Arc::new(Sample::load_from_file(&audio, None::<&str>, &state).unwrap());
+
+        let mut buses = Buses::default();
+        let reverb = buses.add(Bus::new("Reverb"));
+        let group = buses.add(Bus::new("Group"));
+        assert!(buses.set_output(reverb, Output::Bus(group)));
+        buses.master.volume = -3.0;
+
+        let mut first = Track::new("First", Vec::new(), state.clone());
+        let mut clip = Clip::with_range(sample.clone(), 250_000, 480, 2400);
+        clip.gain[1] = 0.5;
+        first.clips = vec![Clip::new(sample, 0), clip];
+        first.levels.volume = -6.0;
+        first.levels.pan = 0.25;
+        first.output = Output::Bus(group);
+        first.sends = vec![AuxSend::new(reverb)];
+
+        let limiter = Insert::new(ProcessorKind::Limiter.create());
+        limiter.processor().set_parameter(0, 9.0);
+        first.inserts = vec![limiter];
+
+        let first = Arc::new(RwLock::new(first));
+        let second = Track::new("Second", Vec::new(), state.clone());
+        let second_id = second.id;
+        first.write().unwrap().inserts[0].sidechain = Some(second_id);
+        let tracks = vec![first, Arc::new(RwLock::new(second))];
+
+        let path = dir.join("session.json");
+        save(&path, &tracks, &buses).unwrap();
+
+        // Audio next to the project is stored relative to it
+        let project: ProjectFile =
+            serde_json::from_reader(BufReader::new(File::open(&path).unwrap())).unwrap();
+        assert_eq!(project.version, PROJECT_VERSION);
+        assert_eq!(project.tracks[0].clips[0].path, Path::new("audio/tone.wav"));
+
+        let (loaded, loaded_buses) = load(&path, &state).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        let first = loaded[0].read().unwrap();
+        let original = tracks[0].read().unwrap();
+        assert_eq!(first.name, "First");
+        assert_eq!(first.levels, original.levels);
+
+        assert_eq!(first.clips.len(), 2);
+        for (clip, original) in first.clips.iter().zip(&original.clips) {
+            assert_eq!(clip.sample.path, original.sample.path);
+            assert_eq!(clip.sample.data, original.sample.data);
+            assert_eq!(clip.start, original.start);
+            assert_eq!(clip.offset, original.offset);
+            assert_eq!(clip.length, original.length);
+            assert_eq!(clip.gain, original.gain);
+        }
+        // Clips of the same file share the sample
+        assert!(Arc::ptr_eq(&first.clips[0].sample, &first.clips[1].sample));
+
+        assert_eq!(first.inserts.len(), 1);
+        assert_eq!(first.inserts[0].processor().kind(), ProcessorKind::Limiter);
+        assert_eq!(first.inserts[0].processor().parameter(0), 9.0);
+        assert_eq!(
+            first.inserts[0].sidechain,
+            Some(loaded[1].read().unwrap().id)
+        );
+
+        let [reverb, group] = [0, 1].map(|index| loaded_buses.buses()[index].id);
+        assert_eq!(loaded_buses.buses()[0].name, "Reverb");
+        assert_eq!(
+            loaded_buses.bus(reverb).unwrap().output(),
+            Output::Bus(group)
+        );
+        assert_eq!(first.output, Output::Bus(group));
+        assert_eq!(first.sends, [AuxSend::new(reverb)]);
+        assert_eq!(loaded_buses.master.volume, -3.0);
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_project() {
+        let dir = test_dir("relative");
+        let audio = dir.join("audio").join("tone.wav");
+        write_tone(&audio);
+        let sibling = dir.join("sibling");
+        std::fs::create_dir(&sibling).unwrap();
+
+        let inside = relative_path(&dir, &audio);
+        let outside = relative_path(&sibling, &audio);
+        let missing = relative_path(&dir, &dir.join("missing.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(inside, Path::new("audio/tone.wav"));
+        assert_eq!(outside, Path::new("../audio/tone.wav"));
+        // Paths that can't be resolved are kept as they are
+        assert_eq!(missing, dir.join("missing.wav"));
+    }
+
+    #[test]
+    fn old_versions_are_migrated() {
+        let dir = test_dir("migration");
+        write_tone(&dir.join("audio").join("tone.wav"));
+
+        // Version 1 laid clips back to back, version 2 mapped each input to speakers
+        let clip = r#"{ "name": "tone.wav", "path": "audio/tone.wav" }"#;
+        let project = format!(
+            r#"{{
+                "version": 1,
+                "tracks": [{{
+                    "name": "Old",
+                    "view_range": {{ "start": 0, "end": 1000000 }},
+                    "channel_mapping": [{right}, {left}],
+                    "clips": [{clip}, {clip}, {clip}]
+                }}]
+            }}"#,
+            left = Speakers::FrontLeft.bits(),
+            right = Speakers::FrontRight.bits(),
+        );
+
+        let project: ProjectFile = serde_json::from_str(&project).unwrap();
+        let (tracks, buses) = project.into_session(&dir, &state()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let track = tracks[0].read().unwrap();
+        let starts: Vec<_> = track.clips.iter().map(|clip| clip.start).collect();
+        assert_eq!(starts, [0, 100_000, 200_000]);
+
+        let routing = track.routing.as_ref().unwrap();
+        assert_eq!(routing.gain(0, 0), 0.0);
+        assert_eq!(routing.gain(0, 1), 1.0);
+        assert_eq!(routing.gain(1, 0), 1.0);
+        assert_eq!(routing.gain(1, 1), 0.0);
+
+        assert_eq!(track.output, Output::Master);
+        assert!(buses.buses().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use tracing::info;
+
+use crate::{
+    bus::Buses,
+    channel::Layout,
+    loudness::{LoudnessAnalyzer, LoudnessReport},
+    mixer::Mixer,
+    playback::{create_resamplers, mix_tracks, prepare_inserts},
+    processor::{Format, Insert},
+    track::Track,
+    wave_file::{self, BitDepth},
+};
+
+/// The number of frames rendered per block. This mimics the buffer of an audio device
+const RENDER_BLOCK_SIZE: usize = 1024;
+
+/// Settings for rendering a session offline
+///
+/// `sample_rate` and `channels` are the format of the rendered mix
+/// `bit_depth` is the format the mix is written to disk with
+/// `dither` if `true`, dither is applied when writing integer bit depths
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_depth: BitDepth,
+    pub dither: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            sample_rate: 48000,
+            channels: 2,
+            bit_depth: BitDepth::TwentyFour,
+            dither: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The default settings with the bit depth of the most precise sample in `tracks`,
+    /// so a bounce doesn't lose any precision of its sources
+    pub fn for_tracks(tracks: &[Arc<RwLock<Track>>]) -> RenderSettings {
+        let default = RenderSettings::default();
+        let bit_depth = tracks
+            .iter()
+            .flat_map(|track| {
+                let track = track.read().unwrap();
+                track
+                    .clips
+                    .iter()
+                    .map(|clip| clip.sample.bit_depth)
+                    .collect::<Vec<_>>()
+            })
+            .max()
+            .unwrap_or(default.bit_depth);
+
+        RenderSettings {
+            bit_depth,
+            ..default
+        }
+    }
+}
+
+/// A copy of the tracks and buses of a session to render.
+///
+/// The copy has processors of its own, so a render neither disturbs playback nor depends on what was played
+pub struct RenderSession {
+    tracks: Vec<Arc<RwLock<Track>>>,
+    buses: Buses,
+}
+
+impl RenderSession {
+    pub fn new(tracks: &[Arc<RwLock<Track>>], buses: &Buses) -> RenderSession {
+        RenderSession {
+            tracks: tracks
+                .iter()
+                .map(|track| Arc::new(RwLock::new(track.read().unwrap().render_copy())))
+                .collect(),
+            buses: buses.render_copy(),
+        }
+    }
+}
+
+/// Render every track of `session` through its buses into a single packed audio signal.
+///
+/// This goes through the same resampling and channel routing as real time playback,
+/// except the resampling is done up front so the mix can be rendered faster than real time.
+pub fn render(session: &RenderSession, settings: &RenderSettings) -> Vec<f32> {
+    let channels = settings.channels as usize;
+    let (tracks, buses) = (&session.tracks, &session.buses);
+
+    let format = Format {
+        sample_rate: settings.sample_rate,
+        channels: settings.channels,
+        max_block_size: RENDER_BLOCK_SIZE,
+    };
+    prepare_inserts(tracks, buses, format);
+
+    // Start the inserts without anything left over from an earlier render of the session
+    let reset = |inserts: &[Insert]| {
+        for insert in inserts {
+            insert.processor().reset();
+        }
+    };
+    for track in tracks {
+        reset(&track.read().unwrap().inserts);
+    }
+    for bus in buses.buses() {
+        reset(&bus.inserts);
+    }
+    reset(&buses.master.inserts);
+
+    let resamplers = create_resamplers(tracks, settings.sample_rate);
+    resamplers
+        .iter()
+        .for_each(|resampler| resampler.resample_all());
+
+    let len = tracks
+        .iter()
+        .map(|track| {
+            track
+                .read()
+                .unwrap()
+                .len_samples(settings.sample_rate as f64)
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut mixer = Mixer::new(settings.sample_rate);
+    let mut position = 0;
+    let mut output = vec![0.0f32; len * channels];
+
+    for block in output.chunks_mut(RENDER_BLOCK_SIZE * channels) {
+        mix_tracks(
+            tracks,
+            buses,
+            &mut position,
+            &resamplers,
+            &mut mixer,
+            settings.channels,
+            block,
+        );
+    }
+
+    output
+}
+
+/// Render `session` and measure the loudness of the mix
+pub fn loudness(session: &RenderSession, settings: &RenderSettings) -> LoudnessReport {
+    let output = render(session, settings);
+
+    LoudnessAnalyzer::analyze(
+        &output,
+        settings.sample_rate,
+        settings.channels,
+        Layout::from_channel_count(settings.channels),
+    )
+}
+
+/// Render `session` and write the mix to a wave file at `path`
+pub fn bounce(
+    session: &RenderSession,
+    path: impl AsRef<Path>,
+    settings: &RenderSettings,
+) -> io::Result<()> {
+    let output = render(session, settings);
+
+    info!(
+        "Writing {} frames to {}",
+        output.len() / settings.channels as usize,
+        path.as_ref().display()
+    );
+
+    let mut file = BufWriter::new(File::create(path)?);
+    wave_file::write(
+        &mut file,
+        settings.sample_rate,
+        settings.channels,
+        Layout::from_channel_count(settings.channels),
+        settings.bit_depth,
+        &output,
+        settings.dither,
+    )
+}
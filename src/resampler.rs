@@ -4,8 +4,9 @@ use std::sync::{
 };
 
 use rubato::SincFixedOut;
+use tracing::info;
 
-use crate::{sample::Sample, util::strip_samples};
+use crate::{id::Id, sample::Sample, util::strip_samples};
 
 pub struct ResamplerInner {
     sample: Arc<Sample>,
@@ -24,14 +25,15 @@ impl ResamplerInner {
     }
 
     fn impl_resample(&self, data: &[f32], input_index: usize, resampling_buffer: &mut [Vec<f32>]) {
+        let channels = self.sample.header.channel_count as usize;
         let len = {
             let mut resampler_guard = self.resampler.lock().unwrap();
             let resampler: &mut SincFixedOut<f32> = &mut resampler_guard;
 
             let len = rubato::Resampler::input_frames_next(resampler);
             let sample_channels = strip_samples(
-                &data[input_index..input_index + len],
-                self.sample.header.channel_count as usize,
+                &data[input_index * channels..(input_index + len) * channels],
+                channels,
             );
 
             rubato::Resampler::process_into_buffer(
@@ -56,19 +58,18 @@ impl ResamplerInner {
     }
 
     fn impl_resample_self(&self, resampling_buffer: &mut [Vec<f32>]) {
+        let channels = self.sample.header.channel_count as usize;
         let len = {
             let mut resampler_guard = self.resampler.lock().unwrap();
             let resampler: &mut SincFixedOut<f32> = &mut resampler_guard;
 
-            let data = self.sample.data.as_thirty_two_float().unwrap();
+            let data = &self.sample.data;
 
             let len = rubato::Resampler::input_frames_next(resampler);
             let index = self.index.load(Ordering::SeqCst);
 
-            let sample_channels = strip_samples(
-                &data[index..index + len],
-                self.sample.header.channel_count as usize,
-            );
+            let sample_channels =
+                strip_samples(&data[index * channels..(index + len) * channels], channels);
 
             rubato::Resampler::process_into_buffer(
                 resampler,
@@ -91,7 +92,14 @@ impl ResamplerInner {
         self.index.fetch_add(len, Ordering::SeqCst);
     }
 
-    pub fn resample(&self, resampling_buffer: &mut [Vec<f32>]) -> bool {
+    /// Resample the next chunk of the sample into the output buffer
+    ///
+    /// `resampling_buffer` is scratch space and will be resized to the channel count of the sample
+    ///
+    /// Returns `true` once the whole sample has been resampled
+    pub fn resample(&self, resampling_buffer: &mut Vec<Vec<f32>>) -> bool {
+        resampling_buffer.resize(self.sample.header.channel_count as usize, Vec::new());
+
         let next_len = {
             let resampler_guard = self.resampler.lock().unwrap();
             let resampler: &SincFixedOut<f32> = &resampler_guard;
@@ -99,16 +107,18 @@ impl ResamplerInner {
         };
 
         let index = self.index.load(Ordering::SeqCst);
-        if index + next_len >= self.sample.len() {
-            let data = self.sample.data.as_thirty_two_float().unwrap();
-            let data = vec![data[index..].to_vec(), vec![0.0f32; data.len() - index]].concat();
+        if index + next_len >= self.sample.frames() {
+            // Pad the end of the sample with silence so a full chunk can be processed
+            let channels = self.sample.header.channel_count as usize;
+            let data = &self.sample.data[index * channels..];
+            let data = [data, &vec![0.0f32; next_len * channels]].concat();
 
             self.impl_resample(&data, 0, resampling_buffer);
         } else {
             self.impl_resample_self(resampling_buffer);
         }
 
-        self.index.load(Ordering::SeqCst) >= self.sample.data.as_thirty_two_float().unwrap().len()
+        self.index.load(Ordering::SeqCst) >= self.sample.frames()
     }
 }
 
@@ -116,16 +126,37 @@ impl ResamplerInner {
 pub struct Resampler(Arc<Vec<Option<(ResamplerInner, AtomicBool)>>>);
 
 impl Resampler {
-    pub fn iter_all(&self) -> impl Iterator<Item = &Option<(ResamplerInner, AtomicBool)>> + '_ {
-        self.0.iter()
-    }
-
     pub fn iter(&self) -> impl Iterator<Item = &(ResamplerInner, AtomicBool)> + '_ {
         self.0.iter().filter_map(|v| v.as_ref())
     }
 
-    pub fn len(&self) -> usize {
-        self.0.len()
+    /// Get the resampler for the sample with `id`
+    pub fn get(&self, id: Id) -> Option<&ResamplerInner> {
+        self.iter()
+            .map(|(resampler, _)| resampler)
+            .find(|resampler| resampler.sample.id == id)
+    }
+
+    /// Resample every sample that needs it to completion, blocking until done
+    pub fn resample_all(&self) {
+        let mut resampling_buffer = Vec::new();
+
+        for (resampler, complete) in self.iter() {
+            if complete.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            while !resampler.resample(&mut resampling_buffer) {}
+
+            info!(
+                "Sample: `{}` resampled - len: {}",
+                resampler.sample().name,
+                resampler.buffer().read().unwrap()[0].len(),
+            );
+
+            // This sample has been fully resampled
+            complete.store(true, Ordering::SeqCst);
+        }
     }
 }
 
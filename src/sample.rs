@@ -1,10 +1,10 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
     fs::File,
-    io,
+    io::{self, BufWriter, Write},
+    ops::Range,
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
-    time::Duration,
 };
 
 use bytemuck::Zeroable;
@@ -14,42 +14,130 @@ use tracing::info;
 use wgpu::util::DeviceExt;
 
 use crate::{
+    channel::Layout,
+    clip::Clip,
     id::{get_id_mgr, Id},
-    state::State,
+    loudness::{LoudnessAnalyzer, LoudnessReport},
+    processor::{AudioProcessor, Insert},
+    state::{Graphics, State},
     track::Track,
+    wave_file::{self, BitDepth},
     wave_view::{WaveComputeUniform, WaveUniform, WaveViewState},
 };
 
+/// Samples are processed offline in blocks of this many frames
+const PROCESS_BLOCK_FRAMES: usize = 4096;
+
+/// The GPU state shared by every view of a sample (the audio data itself)
 pub struct WaveViewSampleState {
+    _audio_buffer: wgpu::Buffer,
+    audio_bind_group: wgpu::BindGroup,
+
+    audio_len: usize,
+
+    device: Arc<wgpu::Device>,
+    wave_state: Arc<WaveViewState>,
+}
+
+impl WaveViewSampleState {
+    pub fn audio_bind_group(&self) -> &wgpu::BindGroup {
+        &self.audio_bind_group
+    }
+}
+
+/// The GPU state for a single view into a sample. Every clip has its own so that
+/// clips referencing the same sample can show different ranges of it.
+pub struct WaveViewClipState {
     compute_uniform_buffer: wgpu::Buffer,
     compute_uniform_bind_group: wgpu::BindGroup,
 
     draw_uniform_buffer: wgpu::Buffer,
     draw_uniform_bind_group: wgpu::BindGroup,
 
-    _audio_buffer: wgpu::Buffer,
-    audio_bind_group: wgpu::BindGroup,
-
     _compute_output_buffer: wgpu::Buffer,
     compute_output_bind_group: wgpu::BindGroup,
 
-    wave_state: Arc<WaveViewState>,
+    audio: Arc<WaveViewSampleState>,
 }
 
-impl WaveViewSampleState {
-    pub fn uniform_bind_group(&self) -> &wgpu::BindGroup {
-        &self.draw_uniform_bind_group
+impl WaveViewClipState {
+    /// Create the uniforms and compute output for a new view of `sample`
+    ///
+    /// Returns `None` if the sample isn't on the GPU
+    pub fn new(sample: &Sample) -> Option<WaveViewClipState> {
+        let audio = sample.wgpu_state.clone()?;
+        let device = &audio.device;
+        let wave_state = &audio.wave_state;
+
+        let draw_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("wave_form_uniform_buffer"),
+            contents: bytemuck::cast_slice(&[WaveUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let draw_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("wave_view_uniform_buffer_bind_group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: draw_uniform_buffer.as_entire_binding(),
+            }],
+            layout: &wave_state.draw_uniform_layout,
+        });
+
+        let compute_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("wave_form_compute_uniform_buffer"),
+            contents: bytemuck::cast_slice(&[WaveUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let compute_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("wave_view_compute_uniform_buffer_bind_group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: compute_uniform_buffer.as_entire_binding(),
+            }],
+            layout: &wave_state.compute_uniform_layout,
+        });
+
+        let compute_output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("wave_form_compute_output_buffer"),
+            mapped_at_creation: false,
+            size: audio.audio_len as u64 / 4 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let compute_output_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("wave_view_compute_output_bind_group"),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: compute_output_buffer.as_entire_binding(),
+            }],
+            layout: &wave_state.compute_output_buffer_layout,
+        });
+
+        Some(WaveViewClipState {
+            compute_uniform_buffer,
+            compute_uniform_bind_group,
+
+            draw_uniform_buffer,
+            draw_uniform_bind_group,
+
+            _compute_output_buffer: compute_output_buffer,
+            compute_output_bind_group,
+
+            audio,
+        })
     }
 
-    pub fn audio_bind_group(&self) -> &wgpu::BindGroup {
-        &self.audio_bind_group
+    pub fn uniform_bind_group(&self) -> &wgpu::BindGroup {
+        &self.draw_uniform_bind_group
     }
 
     pub fn paint<'rp>(&'rp self, rpass: &mut wgpu::RenderPass<'rp>) {
-        rpass.set_pipeline(&self.wave_state.draw_pipeline);
-        rpass.set_vertex_buffer(0, self.wave_state.vertex_buffer.slice(..));
+        rpass.set_pipeline(&self.audio.wave_state.draw_pipeline);
+        rpass.set_vertex_buffer(0, self.audio.wave_state.vertex_buffer.slice(..));
 
-        rpass.set_bind_group(0, self.audio_bind_group(), &[]);
+        rpass.set_bind_group(0, self.audio.audio_bind_group(), &[]);
         rpass.set_bind_group(1, self.uniform_bind_group(), &[]);
         rpass.set_bind_group(2, &self.compute_output_bind_group, &[]);
 
@@ -61,14 +149,20 @@ pub struct Sample {
     pub id: Id,
 
     pub name: String,
-    _path: PathBuf,
+    pub path: PathBuf,
 
     pub header: wav::Header,
-    pub data: wav::BitDepth,
+    /// Audio data normalized to -1.0..1.0 with channels packed after another
+    pub data: Vec<f32>,
+    /// The bit depth the audio was stored in on disk
+    pub bit_depth: BitDepth,
+    /// The speakers of the channels, from the channel mask of the file or guessed from the channel count
+    pub layout: Option<Layout>,
 
     pub sample_rate: f64,
 
-    wgpu_state: Arc<WaveViewSampleState>,
+    /// `None` if there were no graphics to upload the sample to
+    wgpu_state: Option<Arc<WaveViewSampleState>>,
 }
 
 impl Sample {
@@ -78,155 +172,213 @@ impl Sample {
         app_state: &Arc<RwLock<State>>,
     ) -> io::Result<Sample> {
         let mut file = File::open(&path)?;
-        let (header, data) = wav::read(&mut file)?;
+        let wave_file::WaveFile {
+            header,
+            bit_depth,
+            channel_mask,
+            data,
+        } = wave_file::read(&mut file)?;
+
+        // A mask that doesn't have a speaker for every channel is ignored
+        let layout = channel_mask
+            .and_then(Layout::from_channel_mask)
+            .filter(|layout| layout.speakers().len() == header.channel_count as usize)
+            .or_else(|| Layout::from_channel_count(header.channel_count));
+
+        let name = name.map(|n| n.to_string()).unwrap_or_else(|| {
+            path.as_ref()
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        });
 
-        let audio_len = data.as_thirty_two_float().unwrap().len();
         let app_state = app_state.read().unwrap();
-        let audio_buffer =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("wave_view_aduio_buffer"),
-                    contents: bytemuck::cast_slice(data.as_thirty_two_float().unwrap()),
-                    usage: wgpu::BufferUsages::STORAGE,
-                });
-
-        let audio_bind_group =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("wave_view_audio_buffer"),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: audio_buffer.as_entire_binding(),
-                    }],
-                    layout: &app_state.wave_view_state.audio_buffer_layout,
-                });
-
-        let draw_uniform_buffer =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("wave_form_uniform_buffer"),
-                    contents: bytemuck::cast_slice(&[WaveUniform::zeroed()]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
-
-        let draw_uniform_bind_group =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("wave_view_uniform_buffer_bind_group"),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: draw_uniform_buffer.as_entire_binding(),
-                    }],
-                    layout: &app_state.wave_view_state.draw_uniform_layout,
-                });
-
-        let compute_uniform_buffer =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("wave_form_compute_uniform_buffer"),
-                    contents: bytemuck::cast_slice(&[WaveUniform::zeroed()]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
-
-        let compute_uniform_bind_group =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("wave_view_compute_uniform_buffer_bind_group"),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: compute_uniform_buffer.as_entire_binding(),
-                    }],
-                    layout: &app_state.wave_view_state.compute_uniform_layout,
-                });
+        Ok(Sample::new(
+            name,
+            path.as_ref().to_path_buf(),
+            header,
+            data,
+            bit_depth,
+            layout,
+            app_state.graphics.as_ref(),
+        ))
+    }
 
-        let compute_output_buffer =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_buffer(&wgpu::BufferDescriptor {
-                    label: Some("wave_form_compute_output_buffer"),
-                    mapped_at_creation: false,
-                    size: audio_len as u64 / 4 * 4,
-                    usage: wgpu::BufferUsages::STORAGE,
-                });
+    /// Create a sample from decoded audio, uploading it to the GPU if there are `graphics`
+    pub fn new(
+        name: String,
+        path: PathBuf,
+        header: wav::Header,
+        data: Vec<f32>,
+        bit_depth: BitDepth,
+        layout: Option<Layout>,
+        graphics: Option<&Graphics>,
+    ) -> Sample {
+        let wgpu_state =
+            graphics.map(|graphics| {
+                let audio_buffer = graphics.wgpu_ctx.device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("wave_view_aduio_buffer"),
+                        contents: bytemuck::cast_slice(&data),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    },
+                );
 
-        let compute_output_bind_group =
-            app_state
-                .wgpu_ctx
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("wave_view_compute_output_bind_group"),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: compute_output_buffer.as_entire_binding(),
-                    }],
-                    layout: &app_state.wave_view_state.compute_output_buffer_layout,
-                });
+                let audio_bind_group =
+                    graphics
+                        .wgpu_ctx
+                        .device
+                        .create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("wave_view_audio_buffer"),
+                            entries: &[wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: audio_buffer.as_entire_binding(),
+                            }],
+                            layout: &graphics.wave_view_state.audio_buffer_layout,
+                        });
+
+                Arc::new(WaveViewSampleState {
+                    _audio_buffer: audio_buffer,
+                    audio_bind_group,
+
+                    audio_len: data.len(),
+
+                    device: graphics.wgpu_ctx.device.clone(),
+                    wave_state: graphics.wave_view_state.clone(),
+                })
+            });
 
-        Ok(Sample {
+        Sample {
             id: get_id_mgr().gen_id(),
-            name: name.map(|n| n.to_string()).unwrap_or_else(|| {
-                path.as_ref()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            }),
-            _path: path.as_ref().to_path_buf(),
+            name,
+            path,
+
+            sample_rate: header.sampling_rate as f64 / 1000.0 / 1000.0,
 
             header,
             data,
+            bit_depth,
+            layout,
 
-            sample_rate: header.sampling_rate as f64 / 1000.0 / 1000.0,
+            wgpu_state,
+        }
+    }
 
-            wgpu_state: Arc::new(WaveViewSampleState {
-                compute_uniform_buffer,
-                compute_uniform_bind_group,
+    /// The number of frames (a sample point for every channel) in the sample
+    pub fn frames(&self) -> usize {
+        self.data.len() / self.header.channel_count as usize
+    }
+
+    /// Measure the loudness of the whole sample
+    pub fn loudness(&self) -> LoudnessReport {
+        LoudnessAnalyzer::analyze(
+            &self.data,
+            self.header.sampling_rate,
+            self.header.channel_count,
+            self.layout,
+        )
+    }
 
-                draw_uniform_buffer,
-                draw_uniform_bind_group,
+    /// Run the whole sample through `processor` and return the processed audio.
+    ///
+    /// The processor is reset first. Its latency is compensated, so the result lines up with the sample
+    pub fn process(&self, processor: &mut dyn AudioProcessor) -> Vec<f32> {
+        let channels = self.header.channel_count as usize;
+        processor.prepare(
+            self.header.sampling_rate,
+            self.header.channel_count,
+            PROCESS_BLOCK_FRAMES,
+        );
+        processor.reset();
 
-                _audio_buffer: audio_buffer,
-                audio_bind_group,
+        let latency = processor.latency() * channels;
+        let mut data = Vec::with_capacity(self.data.len() + latency);
+        data.extend_from_slice(&self.data);
+        data.resize(self.data.len() + latency, 0.0);
 
-                _compute_output_buffer: compute_output_buffer,
-                compute_output_bind_group,
+        for block in data.chunks_mut(PROCESS_BLOCK_FRAMES * channels) {
+            processor.process(block);
+        }
 
-                wave_state: app_state.wave_view_state.clone(),
-            }),
-        })
+        data.drain(..latency);
+        data
     }
 
-    pub fn adjusted_len(&self, track: &Track) -> u64 {
-        let sample_data_len = track.view_range.end as usize;
-        let adjusted_len = sample_data_len.min(self.len());
+    /// Run the sample through `inserts` in order, like `process` with each of them, and save the result as a new file next to it.
+    ///
+    /// Bypassed inserts are skipped. Sidechains aren't followed, every processor is keyed from the sample itself.
+    /// The file is 32 bit float so nothing of the processed audio is lost
+    pub fn apply_inserts(
+        &self,
+        inserts: &[Insert],
+        graphics: Option<&Graphics>,
+    ) -> io::Result<Sample> {
+        let mut data: Option<Vec<f32>> = None;
+        for insert in inserts.iter().filter(|insert| !insert.bypass) {
+            let processed = match &data {
+                Some(data) => self
+                    .with_data(data)
+                    .process(insert.copy_processor().as_mut()),
+                None => self.process(insert.copy_processor().as_mut()),
+            };
+            data = Some(processed);
+        }
+        let data = data.unwrap_or_else(|| self.data.clone());
+
+        let path = processed_path(&self.path);
+        let mut file = BufWriter::new(File::create(&path)?);
+        wave_file::write(
+            &mut file,
+            self.header.sampling_rate,
+            self.header.channel_count,
+            self.layout,
+            BitDepth::ThirtyTwoFloat,
+            &data,
+            false,
+        )?;
+        file.flush()?;
+
+        info!("Wrote processed `{}` to {}", self.name, path.display());
+
+        let name = path.file_name().map_or_else(
+            || self.name.clone(),
+            |name| name.to_string_lossy().to_string(),
+        );
 
-        adjusted_len as u64
+        Ok(Sample::new(
+            name,
+            path,
+            self.float_header(),
+            data,
+            BitDepth::ThirtyTwoFloat,
+            self.layout,
+            graphics,
+        ))
     }
 
-    pub fn len(&self) -> usize {
-        match &self.data {
-            wav::BitDepth::ThirtyTwoFloat(data) => data.len(),
-            _ => 0,
-        }
+    /// A sample in the format of this one with other audio, that isn't drawn
+    fn with_data(&self, data: &[f32]) -> Sample {
+        Sample::new(
+            self.name.clone(),
+            self.path.clone(),
+            self.float_header(),
+            data.to_vec(),
+            BitDepth::ThirtyTwoFloat,
+            self.layout,
+            None,
+        )
     }
 
-    pub fn len_time(&self) -> Duration {
-        let secs = self.len() as f64 / self.header.sampling_rate as f64;
-        Duration::from_secs_f64(secs)
+    /// The header of the sample stored as 32 bit float
+    fn float_header(&self) -> wav::Header {
+        wav::Header::new(
+            wav::WAV_FORMAT_IEEE_FLOAT,
+            self.header.channel_count,
+            self.header.sampling_rate,
+            32,
+        )
     }
 
     pub fn view_updated(&self, ui: &mut egui::Ui, rect: egui::Rect, track: &Track, index: usize) {
@@ -236,9 +388,14 @@ impl Sample {
         };
 
         let state = track.app_state.read().unwrap();
+        let (Some(view), Some(audio), Some(graphics)) =
+            (&track.clips[index].view, &self.wgpu_state, &state.graphics)
+        else {
+            return;
+        };
 
-        state.wgpu_ctx.queue.write_buffer(
-            &self.wgpu_state.compute_uniform_buffer,
+        graphics.wgpu_ctx.queue.write_buffer(
+            &view.compute_uniform_buffer,
             0,
             bytemuck::cast_slice(&[WaveComputeUniform {
                 width: rect.width(),
@@ -253,7 +410,7 @@ impl Sample {
             }]),
         );
 
-        let mut encoder = state
+        let mut encoder = graphics
             .wgpu_ctx
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
@@ -261,15 +418,15 @@ impl Sample {
         {
             let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
 
-            cpass.set_pipeline(&self.wgpu_state.wave_state.compute_pipeline);
-            cpass.set_bind_group(0, &self.wgpu_state.audio_bind_group, &[]);
-            cpass.set_bind_group(1, &self.wgpu_state.compute_uniform_bind_group, &[]);
-            cpass.set_bind_group(2, &self.wgpu_state.compute_output_bind_group, &[]);
+            cpass.set_pipeline(&audio.wave_state.compute_pipeline);
+            cpass.set_bind_group(0, &audio.audio_bind_group, &[]);
+            cpass.set_bind_group(1, &view.compute_uniform_bind_group, &[]);
+            cpass.set_bind_group(2, &view.compute_output_bind_group, &[]);
 
             cpass.dispatch_workgroups(rect.width() as _, 1, 1);
         }
 
-        state.wgpu_ctx.queue.submit(Some(encoder.finish()));
+        graphics.wgpu_ctx.queue.submit(Some(encoder.finish()));
     }
 
     pub fn display(
@@ -289,7 +446,7 @@ impl Sample {
         // Width of viewport
         let width = rect.width();
         let height = rect.height();
-        let sample_data = self.data.as_thirty_two_float().unwrap();
+        let sample_data = &self.data;
 
         // Y-scale factor
         let scale = height / 2.0 - 10.0;
@@ -336,9 +493,8 @@ impl Sample {
                     main_color,
                 )
             }
-        } else {
-            let wave_state = self.wgpu_state.clone();
-            let id = self.id;
+        } else if let Some(wave_state) = track.clips[index].view.clone() {
+            let id = track.clips[index].id;
 
             // Render a shader to display larger zommed-out data
             let cb = egui_wgpu::CallbackFn::new()
@@ -366,7 +522,7 @@ impl Sample {
                         }]),
                     );
 
-                    let map: &mut HashMap<Id, Arc<WaveViewSampleState>> =
+                    let map: &mut HashMap<Id, Arc<WaveViewClipState>> =
                         paint_callback_resources.get_mut().unwrap();
 
                     match map.entry(id) {
@@ -379,13 +535,13 @@ impl Sample {
                     Vec::new()
                 })
                 .paint(move |_info, render_pass, paint_callback_resources| {
-                    let resources: &HashMap<Id, Arc<WaveViewSampleState>> =
+                    let resources: &HashMap<Id, Arc<WaveViewClipState>> =
                         paint_callback_resources.get().unwrap();
 
                     let id = id;
 
-                    if let Some(sample) = resources.get(&id) {
-                        sample.paint(render_pass);
+                    if let Some(clip) = resources.get(&id) {
+                        clip.paint(render_pass);
                     }
                 });
 
@@ -400,3 +556,164 @@ impl Sample {
         actual_len
     }
 }
+
+impl Track {
+    /// Replace the samples of the clips that overlap `range` (in microseconds), or of every clip without a range,
+    /// with copies run through the inserts of the track. See `Sample::apply_inserts`.
+    ///
+    /// Returns `true` if any clip changed
+    pub fn apply_inserts(&mut self, range: Option<&Range<u64>>) -> io::Result<bool> {
+        if self.inserts.iter().all(|insert| insert.bypass) {
+            return Ok(false);
+        }
+
+        let app_state = self.app_state.clone();
+        let graphics = &app_state.read().unwrap().graphics;
+
+        let overlaps = |clip: &Clip| {
+            range.is_none_or(|range| clip.start < range.end && clip.end() > range.start)
+        };
+
+        // Every sample is processed before any clip changes, so a failure leaves the track as it was.
+        // Samples used by several clips are only processed once
+        let mut processed: HashMap<Id, Arc<Sample>> = HashMap::new();
+        for clip in self.clips.iter().filter(|clip| overlaps(clip)) {
+            if let Entry::Vacant(entry) = processed.entry(clip.sample.id) {
+                let sample = clip
+                    .sample
+                    .apply_inserts(&self.inserts, graphics.as_ref())?;
+                entry.insert(Arc::new(sample));
+            }
+        }
+
+        let mut changed = false;
+        for clip in self.clips.iter_mut().filter(|clip| overlaps(clip)) {
+            let sample = processed[&clip.sample.id].clone();
+            clip.view = WaveViewClipState::new(&sample).map(Arc::new);
+            clip.sample = sample;
+            changed = true;
+        }
+
+        if changed {
+            self.invalidate_view();
+        }
+
+        Ok(changed)
+    }
+}
+
+/// A path next to `path` for a processed copy of it, that isn't taken yet
+fn processed_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map_or_else(
+        || "Sample".to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    );
+
+    (1..)
+        .map(|number| match number {
+            1 => path.with_file_name(format!("{stem} Processed.wav")),
+            number => path.with_file_name(format!("{stem} Processed {number}.wav")),
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+impl Sample {
+    /// A sample that only exists in memory, with `data` packed like it would be in a file
+    pub fn from_data(data: Vec<f32>, sample_rate: u32, channels: u16) -> Sample {
+        Sample::new(
+            "test".to_string(),
+            PathBuf::new(),
+            wav::Header::new(wav::WAV_FORMAT_IEEE_FLOAT, channels, sample_rate, 32),
+            data,
+            BitDepth::ThirtyTwoFloat,
+            Layout::from_channel_count(channels),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        processor::{process_inserts, Format, Insert, ProcessorKind},
+        util::sine,
+    };
+
+    #[test]
+    fn process_matches_insert() {
+        const SAMPLE_RATE: u32 = 44100;
+
+        let mut data = sine(200.0, 0.3, SAMPLE_RATE, 20000, 2);
+        data.extend(sine(3000.0, 0.9, SAMPLE_RATE, 20000, 2));
+        let sample = Sample::from_data(data, SAMPLE_RATE, 2);
+
+        // The equalizer has no latency, the limiter looks ahead
+        let settings = [
+            (ProcessorKind::Equalizer, 3, 6.0),
+            (ProcessorKind::Limiter, 0, 12.0),
+        ];
+
+        for (kind, index, value) in settings {
+            let mut processor = kind.create();
+            processor.set_parameter(index, value);
+            let offline = sample.process(processor.as_mut());
+
+            let insert = Insert::new(kind.create());
+            insert.processor().set_parameter(index, value);
+            insert.prepare(Format {
+                sample_rate: SAMPLE_RATE,
+                channels: 2,
+                max_block_size: PROCESS_BLOCK_FRAMES,
+            });
+            let latency = insert.processor().latency() * 2;
+
+            let mut played = sample.data.clone();
+            played.resize(sample.data.len() + latency, 0.0);
+            process_inserts(&[insert], &mut played, SAMPLE_RATE, 2, |_| None);
+
+            assert_eq!(offline.len(), sample.data.len(), "{kind:?}");
+            assert_eq!(offline, played[latency..], "{kind:?}");
+            assert_ne!(offline, sample.data, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn apply_inserts_writes_the_chain() {
+        const SAMPLE_RATE: u32 = 44100;
+
+        let mut sample =
+            Sample::from_data(sine(1000.0, 0.8, SAMPLE_RATE, 10000, 2), SAMPLE_RATE, 2);
+        sample.path = std::env::temp_dir().join(format!(
+            "audio_editor_apply_inserts_{}.wav",
+            std::process::id()
+        ));
+
+        let equalizer = Insert::new(ProcessorKind::Equalizer.create());
+        equalizer.processor().set_parameter(3, 6.0);
+        let limiter = Insert::new(ProcessorKind::Limiter.create());
+        limiter.processor().set_parameter(0, 12.0);
+        let mut bypassed = Insert::new(ProcessorKind::Gain.create());
+        bypassed.processor().set_parameter(0, -24.0);
+        bypassed.bypass = true;
+
+        let processed = sample
+            .apply_inserts(&[equalizer.clone(), bypassed, limiter.clone()], None)
+            .unwrap();
+
+        let file = wave_file::read(&mut File::open(&processed.path).unwrap()).unwrap();
+        std::fs::remove_file(&processed.path).unwrap();
+
+        let equalized = sample.process(equalizer.copy_processor().as_mut());
+        let expected = sample
+            .with_data(&equalized)
+            .process(limiter.copy_processor().as_mut());
+
+        assert_eq!(processed.data, expected);
+        assert_eq!(file.data, expected);
+        assert_eq!(file.bit_depth, BitDepth::ThirtyTwoFloat);
+        assert_ne!(processed.path, sample.path);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::backend::BackendKind;
+
+/// Sample rates offered in the settings when a device supports a range of them
+pub const COMMON_SAMPLE_RATES: [u32; 8] =
+    [22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000];
+
+/// The output device and stream configuration chosen by the user.
+///
+/// Every `None` uses what the device prefers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputSettings {
+    /// Where audio is sent. The remaining settings choose the format of the output
+    #[serde(default)]
+    pub backend: BackendKind,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// The buffer size in frames
+    pub buffer_size: Option<u32>,
+    /// Apply dither when the output device uses an integer sample format
+    pub dither: bool,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            backend: BackendKind::Device,
+            device: None,
+            sample_rate: None,
+            channels: None,
+            buffer_size: None,
+            dither: true,
+        }
+    }
+}
+
+impl OutputSettings {
+    /// Load the settings saved by a previous run. The defaults are used if there are none
+    pub fn load() -> OutputSettings {
+        let path = settings_path();
+
+        let settings = File::open(&path).and_then(|file| {
+            serde_json::from_reader(BufReader::new(file)).map_err(io::Error::from)
+        });
+
+        match settings {
+            Ok(settings) => settings,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => OutputSettings::default(),
+            Err(e) => {
+                warn!("Unable to read settings `{}`: {e}", path.display());
+                OutputSettings::default()
+            }
+        }
+    }
+
+    /// Save the settings so they are used by the next run
+    pub fn save(&self) -> io::Result<()> {
+        let path = settings_path();
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let file = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(file, self)?;
+
+        info!("Saved settings to {}", path.display());
+
+        Ok(())
+    }
+
+    /// Find the chosen output device, falling back to the default device if it isn't available
+    pub fn find_device(&self, host: &cpal::Host) -> Option<cpal::Device> {
+        let device = self.device.as_ref().and_then(|name| {
+            host.output_devices()
+                .ok()?
+                .find(|device| device.name().ok().as_ref() == Some(name))
+        });
+
+        if device.is_none() {
+            if let Some(name) = &self.device {
+                warn!("Output device `{name}` is not available, using the default device");
+            }
+        }
+
+        device.or_else(|| host.default_output_device())
+    }
+
+    /// Pick the configuration of `device` that best matches the settings.
+    ///
+    /// Falls back to the default configuration of the device if nothing matches
+    pub fn choose_config(&self, device: &cpal::Device) -> Option<cpal::SupportedStreamConfig> {
+        let matching = device
+            .supported_output_configs()
+            .ok()
+            .and_then(|mut configs| {
+                configs.find_map(|config| {
+                    if self
+                        .channels
+                        .is_some_and(|channels| channels != config.channels())
+                    {
+                        return None;
+                    }
+
+                    match self.sample_rate {
+                        Some(rate)
+                            if (config.min_sample_rate().0..=config.max_sample_rate().0)
+                                .contains(&rate) =>
+                        {
+                            Some(config.with_sample_rate(cpal::SampleRate(rate)))
+                        }
+                        Some(_) => None,
+                        None => Some(config.with_max_sample_rate()),
+                    }
+                })
+            });
+
+        matching.or_else(|| device.default_output_config().ok())
+    }
+
+    /// The buffer size to request for `config`. The chosen size is only used if the device supports it
+    pub fn buffer_size(&self, config: &cpal::SupportedStreamConfig) -> cpal::BufferSize {
+        match (self.buffer_size, config.buffer_size()) {
+            (Some(size), cpal::SupportedBufferSize::Range { min, max })
+                if (*min..=*max).contains(&size) =>
+            {
+                cpal::BufferSize::Fixed(size)
+            }
+            _ => cpal::BufferSize::Default,
+        }
+    }
+}
+
+/// An output device and the configurations it supports
+pub struct OutputDeviceInfo {
+    pub name: String,
+    pub configs: Vec<cpal::SupportedStreamConfigRange>,
+}
+
+impl OutputDeviceInfo {
+    /// List every output device of `host`. Devices that can't be queried are skipped
+    pub fn enumerate(host: &cpal::Host) -> Vec<OutputDeviceInfo> {
+        let Ok(devices) = host.output_devices() else {
+            return Vec::new();
+        };
+
+        devices
+            .filter_map(|device| {
+                Some(OutputDeviceInfo {
+                    name: device.name().ok()?,
+                    configs: device.supported_output_configs().ok()?.collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// The channel counts supported by the device
+    pub fn channel_counts(&self) -> Vec<u16> {
+        let mut channels: Vec<_> = self
+            .configs
+            .iter()
+            .map(|config| config.channels())
+            .collect();
+        channels.sort();
+        channels.dedup();
+
+        channels
+    }
+
+    /// The common sample rates supported with `channels` (or any channel count)
+    pub fn sample_rates(&self, channels: Option<u16>) -> Vec<u32> {
+        COMMON_SAMPLE_RATES
+            .into_iter()
+            .filter(|rate| {
+                self.configs(channels).any(|config| {
+                    (config.min_sample_rate().0..=config.max_sample_rate().0).contains(rate)
+                })
+            })
+            .collect()
+    }
+
+    /// The power of two buffer sizes supported with `channels` (or any channel count)
+    pub fn buffer_sizes(&self, channels: Option<u16>) -> Vec<u32> {
+        (5..=13)
+            .map(|power| 1 << power)
+            .filter(|size| {
+                self.configs(channels)
+                    .any(|config| match config.buffer_size() {
+                        cpal::SupportedBufferSize::Range { min, max } => {
+                            (*min..=*max).contains(size)
+                        }
+                        cpal::SupportedBufferSize::Unknown => false,
+                    })
+            })
+            .collect()
+    }
+
+    fn configs(
+        &self,
+        channels: Option<u16>,
+    ) -> impl Iterator<Item = &cpal::SupportedStreamConfigRange> {
+        self.configs
+            .iter()
+            .filter(move |config| channels.is_none_or(|channels| config.channels() == channels))
+    }
+}
+
+/// Where the settings are stored. This is the platform's config directory when it can be found
+fn settings_path() -> PathBuf {
+    let config_dir = std::env::var_os("APPDATA")
+        .or_else(|| std::env::var_os("XDG_CONFIG_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+
+    config_dir.join("audio_editor").join("settings.json")
+}
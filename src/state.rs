@@ -1,31 +1,112 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    ops::Range,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use crate::wave_view::WaveViewState;
+use crate::{
+    bus::Buses, history::History, id::Id, loudness::SharedLoudness, meter::Meter,
+    processor::Format, transport::Transport, wave_view::WaveViewState,
+};
 
 pub struct State {
     pub playing: bool,
-    pub play_time: Option<cpal::StreamInstant>,
-    pub current_time: Option<cpal::StreamInstant>,
+    /// The playhead. Splits and pastes happen here
+    pub transport: Arc<Transport>,
+    /// The region played over and over while `looping`, in frames at the output sample rate
+    pub loop_region: Option<Range<u64>>,
+    pub looping: bool,
+    /// The number of channels of the output. Zero until an output has been started
+    pub output_channels: u16,
+    /// The most frames the output asks for at once
+    pub max_block_size: usize,
+    /// The levels of the output, measured by the playback engine
+    pub master_meter: Arc<Meter>,
+    /// The loudness of the output, measured by the playback engine
+    pub master_loudness: Arc<SharedLoudness>,
+    /// The aux buses and the master bus the tracks are mixed through
+    pub buses: Arc<RwLock<Buses>>,
+
+    /// The selected range of the timeline in microseconds
+    pub selection: Option<Range<u64>>,
+    /// The tracks the selection applies to
+    pub selected_tracks: HashSet<Id>,
+    /// The track that was last clicked. Pastes start at this track
+    pub focused_track: Option<Id>,
+
+    /// Every edit made to the tracks, so they can be undone
+    pub history: History,
 
     pub egui_ctx: egui::Context,
-    pub wgpu_ctx: eframe::egui_wgpu::RenderState,
+    /// `None` without a window, samples can then be played and rendered but not drawn
+    pub graphics: Option<Graphics>,
+}
 
-    // WGPU state
+/// The GPU state waveforms are drawn with
+pub struct Graphics {
+    pub wgpu_ctx: eframe::egui_wgpu::RenderState,
     pub wave_view_state: Arc<WaveViewState>,
 }
 
 impl State {
-    pub fn duration_played(&self) -> Option<Duration> {
-        if !self.playing {
-            return None;
+    /// Create the state of an empty project
+    pub fn new(egui_ctx: egui::Context, graphics: Option<Graphics>) -> State {
+        State {
+            playing: false,
+            transport: Arc::new(Transport::default()),
+
+            loop_region: None,
+            looping: false,
+            output_channels: 0,
+            max_block_size: 0,
+            master_meter: Arc::default(),
+            master_loudness: Arc::default(),
+            buses: Arc::default(),
+
+            selection: None,
+            selected_tracks: HashSet::new(),
+            focused_track: None,
+            history: History::default(),
+
+            egui_ctx,
+            graphics,
         }
+    }
 
-        if let (Some(start_time), Some(current_time)) = (&self.play_time, &self.current_time) {
-            if let Some(duration) = current_time.duration_since(start_time) {
-                return Some(duration);
-            }
+    /// The loop region if looping is enabled and the region isn't empty
+    pub fn active_loop(&self) -> Option<Range<u64>> {
+        self.loop_region
+            .clone()
+            .filter(|region| self.looping && !region.is_empty())
+    }
+
+    /// Change the rate the playhead and loop region are counted in, keeping them at the same time
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let old_rate = self.transport.sample_rate();
+        self.transport.set_sample_rate(sample_rate);
+
+        if old_rate == 0 || old_rate == sample_rate {
+            return;
+        }
+
+        let rescale = |frame: u64| (frame as f64 * sample_rate as f64 / old_rate as f64) as u64;
+        if let Some(region) = &mut self.loop_region {
+            *region = rescale(region.start)..rescale(region.end);
         }
+    }
+
+    /// The format the playback engine runs inserts in. `None` until an output has been started
+    pub fn insert_format(&self) -> Option<Format> {
+        (self.output_channels > 0).then(|| Format {
+            sample_rate: self.transport.sample_rate(),
+            channels: self.output_channels,
+            max_block_size: self.max_block_size,
+        })
+    }
 
-        None
+    /// The position of the playhead on the timeline
+    pub fn duration_played(&self) -> Duration {
+        self.transport.duration()
     }
 }
@@ -7,151 +7,184 @@ use std::{
 use egui::Pos2;
 
 use crate::{
-    channel::{ChannelMapping, Speakers},
-    sample::Sample,
+    automation::{AutomationLane, AutomationMode, AutomationTarget, Breakpoint},
+    bus::{AuxSend, Output},
+    channel::{GainMatrix, Layout, RoutingPreset, Speakers},
+    clip::Clip,
+    fade::{Fade, FadeCurve, FadeEdge},
+    history::Swap,
+    id::{get_id_mgr, Id},
+    meter::{Meter, MeterDisplay},
+    mixer::{format_pan, format_volume, PanLaw, TrackLevels, MAX_VOLUME_DB, MIN_VOLUME_DB},
+    processor::{insert_chain_ui, ChainEdit, Insert},
     state::State,
     util::{PixelRange, SampleRange},
 };
 
 pub struct Track {
+    pub id: Id,
     pub name: String,
-    pub samples: Vec<Arc<Sample>>,
+    pub clips: Vec<Clip>,
     pub view_range: Range<u64>,
 
-    pub channel_mapping: Option<ChannelMapping>,
+    /// The gain from each input channel to each speaker. `None` uses the default for the channel counts
+    pub routing: Option<GainMatrix>,
+    pub levels: TrackLevels,
+    /// The processors the track goes through before its levels are applied, in order
+    pub inserts: Vec<Insert>,
+    /// Where the track is mixed into after its levels
+    pub output: Output,
+    pub sends: Vec<AuxSend>,
+    /// If the track is armed for recording
+    pub armed: bool,
+    /// The controls that change over time, drawn under the track
+    pub automation: Vec<AutomationLane>,
+    /// The levels the track is playing at, measured by the playback engine
+    pub meter: Arc<Meter>,
+    meter_display: MeterDisplay,
 
     frame_count: usize,
-    cached_times: Vec<u64>,
+    /// If the routing editor is open
+    show_routing: bool,
+    /// If the insert editor is open
+    show_inserts: bool,
+    /// If the automation lanes are shown
+    show_automation: bool,
+    /// The lanes from before they started being written, so the whole pass is undone at once
+    pub(crate) automation_before: Option<Vec<AutomationLane>>,
+    /// Where the current selection drag started in microseconds
+    selection_anchor: Option<u64>,
     pub app_state: Arc<RwLock<State>>,
 }
 
 const TRACK_HEIGHT: f32 = 200.0;
+/// The default width of the name and level controls on the left of the track
+const HEADER_WIDTH: f32 = 180.0;
+/// The width of the area on the edges of a clip that can be dragged to trim it
+const TRIM_HANDLE_WIDTH: f32 = 6.0;
+/// The height of the name above the waveform of a clip, including its margin
+const CLIP_HEADER_HEIGHT: f32 = 25.0;
+/// The size of the handles in the clip header that change the length of the fades
+const FADE_HANDLE_SIZE: f32 = 8.0;
+/// The number of points the fade curves are drawn with
+const FADE_CURVE_POINTS: usize = 16;
 
-impl Track {
-    pub fn new(
-        name: impl Into<String>,
-        samples: Vec<Arc<Sample>>,
-        map_to: Speakers,
-        app_state: Arc<RwLock<State>>,
-    ) -> Track {
-        let sample_times = samples
-            .iter()
-            .scan(0, |state, sample| {
-                let micros = sample.len_time().as_micros() as u64;
-                let old_state = *state;
-                *state += micros;
+/// The size of the handles at the top of the loop markers
+const LOOP_HANDLE_SIZE: f32 = 10.0;
 
-                Some(old_state)
-            })
-            .collect();
+const AUTOMATION_LANE_HEIGHT: f32 = 60.0;
+/// The size of the breakpoints of automation lanes
+const AUTOMATION_POINT_SIZE: f32 = 8.0;
+
+/// The edge of a clip that is being trimmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TrimEdge {
+    Start,
+    End,
+}
+
+/// A marker of the loop region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum LoopMarker {
+    Start,
+    End,
+}
 
+impl Track {
+    /// Create a track that routes each clip by the layout of its sample
+    pub fn new(name: impl Into<String>, clips: Vec<Clip>, app_state: Arc<RwLock<State>>) -> Track {
         Track {
+            id: get_id_mgr().gen_id(),
             name: name.into(),
-            samples,
+            clips,
             app_state,
 
-            channel_mapping: Some(ChannelMapping::from_array_mapping(
-                [(Speakers::FrontLeft, map_to)],
-                1,
-                false,
-            )),
+            routing: None,
+            levels: TrackLevels::default(),
+            inserts: Vec::new(),
+            output: Output::Master,
+            sends: Vec::new(),
+            armed: false,
+            automation: Vec::new(),
+            meter: Arc::default(),
+            meter_display: MeterDisplay::default(),
             frame_count: 0,
-            // channel_mapping: ChannelMapping::default(1),
-            cached_times: sample_times,
+            show_routing: false,
+            show_inserts: false,
+            show_automation: false,
+            automation_before: None,
+            selection_anchor: None,
             view_range: Duration::from_secs(0).as_micros() as u64
                 ..Duration::from_secs(20).as_micros() as u64,
         }
     }
 
-    pub fn sample_at_time(&self, duration: &Duration) -> Option<&Arc<Sample>> {
-        let mut offset = 0;
-        self.samples.iter().find(|&x| {
-            if duration.as_millis() > offset
-                && duration.as_millis() < offset + x.len_time().as_millis()
-            {
-                true
-            } else {
-                offset += x.len_time().as_millis();
-                false
-            }
-        })
-    }
+    /// A copy of what the track plays, to render offline. The inserts get processors of their own
+    pub fn render_copy(&self) -> Track {
+        let clips = self.clips.iter().map(Clip::snapshot).collect();
+        let mut track = Track::new(self.name.clone(), clips, self.app_state.clone());
+
+        track.id = self.id;
+        track.routing = self.routing;
+        track.levels = self.levels;
+        track.inserts = self.inserts.iter().map(Insert::duplicate).collect();
+        track.output = self.output;
+        track.sends = self.sends.clone();
+        track.automation = self.automation.clone();
 
-    pub fn sample_at(&self, index: usize) -> &Arc<Sample> {
-        &self.samples[index]
+        track
     }
 
-    pub fn sample_at_sample_index(
-        &self,
-        index: usize,
-        target_rate: f64,
-    ) -> Option<(&Arc<Sample>, usize)> {
-        let mut offset = 0;
-        let ind = self.samples.iter().position(|x| {
-            let factor = target_rate / x.header.sampling_rate as f64;
-            if index >= offset && index < offset + (x.len() as f64 * factor) as usize {
-                true
-            } else {
-                offset += (x.len() as f64 * factor) as usize;
-                false
-            }
-        });
+    pub fn clip_at(&self, index: usize) -> &Clip {
+        &self.clips[index]
+    }
 
-        ind.map(|i| (&self.samples[i], i))
+    /// Get the length of the track in frames at the given sample rate
+    pub fn len_samples(&self, target_rate: f64) -> usize {
+        self.clips
+            .iter()
+            .map(|clip| clip.frame_range(target_rate).end)
+            .max()
+            .unwrap_or(0)
     }
 
-    pub fn time_rel_left(&self, absolute_time: u64) -> Option<u64> {
-        absolute_time.checked_sub(self.view_range.start)
+    /// Redraw the waveforms of every clip on the next frame. Should be called whenever the clips change
+    pub fn invalidate_view(&mut self) {
+        self.frame_count = 0;
     }
 
-    pub fn calculate_times(&mut self) {
-        self.cached_times.clear();
-        self.cached_times
-            .extend(self.samples.iter().scan(0, |state, sample| {
-                let micros = sample.len_time().as_micros() as u64;
-                let old_state = *state;
-                *state += micros;
+    /// Get the time on the timeline (in microseconds) at a horizontal pixel position in the given width
+    pub fn time_at_pixel(&self, pixel: f32, width: f32) -> u64 {
+        let view_len = (self.view_range.end - self.view_range.start) as f64;
 
-                Some(old_state)
-            }));
+        (self.view_range.start as f64 + (pixel / width) as f64 * view_len)
+            .round()
+            .max(0.0) as u64
     }
 
-    /// Get the bounds of the sample in number of sample points while respecting the view boundries
+    /// Get the bounds of the clip in frames of its sample while respecting the view boundries
     ///
-    /// `sample_index` should be the index of a sample that this track contains
+    /// `clip_index` should be the index of a clip that this track contains
     ///
-    /// If the sample is outside of the view range, `None` is returned
-    pub fn get_clip_sample_width(&self, sample_index: usize) -> Option<SampleRange> {
-        let sample = self.sample_at(sample_index);
-        let start_time = self.cached_times[sample_index];
-        let end_time = start_time + sample.len_time().as_micros() as u64;
+    /// If the clip is outside of the view range, `None` is returned
+    pub fn get_clip_sample_width(&self, clip_index: usize) -> Option<SampleRange> {
+        let clip = self.clip_at(clip_index);
+        let start_time = clip.start;
+        let end_time = clip.end();
 
         if end_time < self.view_range.start || start_time > self.view_range.end {
             return None;
         }
 
-        match (
-            self.view_range.contains(&start_time),
-            self.view_range.contains(&end_time),
-        ) {
-            (false, false) => Some(SampleRange {
-                min: ((self.view_range.start - start_time) as f64 * sample.sample_rate).floor()
-                    as u64,
-                max: ((self.view_range.end - start_time) as f64 * sample.sample_rate).ceil() as u64,
-            }),
-            (true, false) => Some(SampleRange {
-                min: 0,
-                max: ((self.view_range.end - start_time) as f64 * sample.sample_rate) as u64,
-            }),
-            (false, true) => Some(SampleRange {
-                min: ((self.view_range.start - start_time) as f64 * sample.sample_rate) as u64,
-                max: sample.len() as u64,
-            }),
-            (true, true) => Some(SampleRange {
-                min: 0,
-                max: sample.len() as u64,
-            }),
-        }
+        // Time into the clip that is visible
+        let visible_start = self.view_range.start.max(start_time) - start_time;
+        let visible_end = self.view_range.end.min(end_time) - start_time;
+
+        Some(SampleRange {
+            min: clip.offset + (visible_start as f64 * clip.sample.sample_rate).floor() as u64,
+            max: clip.offset
+                + ((visible_end as f64 * clip.sample.sample_rate).ceil() as u64).min(clip.length),
+        })
     }
 
     /// Get the pixel position (horizontally) in the given width and view range of a duration
@@ -164,71 +197,24 @@ impl Track {
         let micros = duration.as_micros() as u64;
 
         if self.view_range.contains(&micros) {
-            Some(width / (self.view_range.end - self.view_range.start) as f32 * micros as f32)
+            Some(
+                width / (self.view_range.end - self.view_range.start) as f32
+                    * (micros - self.view_range.start) as f32,
+            )
         } else {
             None
         }
     }
 
-    /// Get the pixel position (horizontally) in the given width and view range of a duration
-    ///
-    /// `duration` is the time relative to the beginning of the sample
-    /// `width` is the width of the timeline
-    ///
-    /// If the duration does not fall inside the sample, `None` is return
-    pub fn get_pixel_from_duration_sample(
-        &self,
-        sample_index: usize,
-        duration: &Duration,
-        width: f32,
-    ) -> Option<f32> {
-        let sample = self.sample_at(sample_index);
-        let start_time = self.cached_times[sample_index];
-        let end_time = start_time + sample.len_time().as_micros() as u64;
-
-        let micros = duration.as_micros() as u64;
-
-        let pixels_per_micro = width / (self.view_range.end - self.view_range.start) as f32;
-
-        if micros < self.view_range.start
-            || micros > self.view_range.end
-            || micros < start_time
-            || micros > end_time
-        {
-            return None;
-        }
-
-        match (
-            self.view_range.contains(&start_time),
-            self.view_range.contains(&end_time),
-        ) {
-            (false, false) => Some(
-                pixels_per_micro * micros as f32
-                    - pixels_per_micro * (self.view_range.start - start_time) as f32,
-            ),
-            (true, false) => Some(
-                pixels_per_micro * micros as f32
-                    - pixels_per_micro * (start_time - self.view_range.start) as f32,
-            ),
-            (false, true) => Some(
-                pixels_per_micro * micros as f32
-                    - pixels_per_micro * (self.view_range.start - start_time) as f32,
-            ),
-            (true, true) => {
-                Some(pixels_per_micro * micros as f32 - pixels_per_micro * (start_time) as f32)
-            }
-        }
-    }
-
-    /// Get the pixel range the provided sample occupies on the timeline
+    /// Get the pixel range the provided clip occupies on the timeline
     ///
-    /// `sample_index` should be the index of a sample that this track contains
+    /// `clip_index` should be the index of a clip that this track contains
     ///
-    /// If the sample is outside of the view range, `None` is returned
-    pub fn get_clip_pixel_width(&self, width: f32, sample_index: usize) -> Option<PixelRange> {
-        let sample = self.sample_at(sample_index);
-        let start_time = self.cached_times[sample_index];
-        let end_time = start_time + sample.len_time().as_micros() as u64;
+    /// If the clip is outside of the view range, `None` is returned
+    pub fn get_clip_pixel_width(&self, width: f32, clip_index: usize) -> Option<PixelRange> {
+        let clip = self.clip_at(clip_index);
+        let start_time = clip.start;
+        let end_time = clip.end();
 
         let pixels_per_micro = width / (self.view_range.end - self.view_range.start) as f32;
 
@@ -236,31 +222,16 @@ impl Track {
             return None;
         }
 
-        match (
-            self.view_range.contains(&start_time),
-            self.view_range.contains(&end_time),
-        ) {
-            (false, false) => Some(PixelRange {
-                min: 0.0,
-                max: width,
-            }),
-            (true, false) => Some(PixelRange {
-                min: pixels_per_micro * (start_time - self.view_range.start) as f32,
-                max: width,
-            }),
-            (false, true) => Some(PixelRange {
-                min: 0.0,
-                max: pixels_per_micro * (end_time - self.view_range.start) as f32,
-            }),
-            (true, true) => Some(PixelRange {
-                min: pixels_per_micro * start_time as f32,
-                max: pixels_per_micro * end_time as f32,
-            }),
-        }
+        Some(PixelRange {
+            min: pixels_per_micro * (start_time.max(self.view_range.start) - self.view_range.start) as f32,
+            max: pixels_per_micro * (end_time.min(self.view_range.end) - self.view_range.start) as f32,
+        })
     }
 
     /// The main drawing code for the track
-    pub fn ui(&mut self, ui: &mut egui::Ui) -> egui::Response {
+    ///
+    /// `tracks` are the ids and names of every track, to pick a sidechain from
+    pub fn ui(&mut self, ui: &mut egui::Ui, tracks: &[(Id, String)]) -> egui::Response {
         let frame = egui::containers::Frame {
             shadow: eframe::epaint::Shadow {
                 extrusion: 4.0,
@@ -273,7 +244,7 @@ impl Track {
         frame.begin(ui);
         let res = frame
             .show(ui, |ui| {
-                ui.horizontal(|ui| {
+                let row = ui.horizontal(|ui| {
                     egui::containers::Frame::none()
                         .inner_margin(egui::Margin {
                             left: 10.0,
@@ -282,20 +253,22 @@ impl Track {
                         .show(ui, |ui| {
                             egui::Resize::default()
                                 .id_source(&self.name)
-                                .default_width(100.0)
+                                .default_width(HEADER_WIDTH)
                                 .min_height(TRACK_HEIGHT)
                                 .max_size(egui::vec2(f32::INFINITY, TRACK_HEIGHT))
                                 .with_stroke(false)
-                                .show(ui, |ui| {
-                                    ui.label(&self.name);
-                                });
+                                .show(ui, |ui| self.header_ui(ui));
                         });
 
+                    let channels = self.app_state.read().unwrap().output_channels;
+                    self.meter_display
+                        .ui(ui, &self.meter, channels, TRACK_HEIGHT, true);
+
                     ui.separator();
 
-                    let (rect, _bg_response) = ui.allocate_exact_size(
+                    let (rect, _) = ui.allocate_exact_size(
                         egui::vec2(ui.available_width(), ui.available_height()),
-                        egui::Sense::drag(),
+                        egui::Sense::hover(),
                     );
                     // Change the preview zoom on scroll
                     let (scoll_delta, bg_pos) = ui
@@ -329,18 +302,38 @@ impl Track {
                     };
                     let redraw = redraw.is_none() || self.frame_count == 0;
                     let width = rect.width();
+                    let timeline_rect = rect;
 
-                    ui.allocate_ui_at_rect(rect, |ui| {
-                        let mut offset = 0;
+                    // The clip being trimmed, which edge, the time it's being dragged to and the drag's id
+                    let mut trim = None;
+                    let mut trim_released = false;
+                    // The clip whose fade changed, which fade, the new fade and the drag's id
+                    let mut fade_edit = None;
+                    let mut fade_released = false;
 
+                    // The position of `time` (in microseconds) on the timeline, even outside the view
+                    let view_range = self.view_range.clone();
+                    let pixel_at = |time: u64| {
+                        timeline_rect.left()
+                            + (time as f32 - view_range.start as f32) * width
+                                / (view_range.end - view_range.start) as f32
+                    };
+
+                    ui.allocate_ui_at_rect(rect, |ui| {
                         ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
 
-                        for (index, sample) in self.samples.iter().enumerate() {
+                        for (index, clip) in self.clips.iter().enumerate() {
                             let Some(pixel_range) = self.get_clip_pixel_width(width, index) else {
                                 continue;
                             };
 
-                            let sample_response = ui.vertical(|ui| {
+                            // Clips are placed at their position on the timeline, leaving gaps between them
+                            let clip_rect = egui::Rect::from_min_max(
+                                Pos2::new(rect.left() + pixel_range.min.round(), rect.top()),
+                                Pos2::new(rect.left() + pixel_range.max.round(), rect.bottom()),
+                            );
+
+                            let sample_response = ui.allocate_ui_at_rect(clip_rect, |ui| ui.vertical(|ui| {
                                 ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
 
                                 let res = ui.allocate_ui_with_layout(
@@ -364,7 +357,13 @@ impl Track {
                                             })
                                             .show(ui, |ui| {
                                                 ui.set_min_width(ui.available_width());
-                                                ui.label(&sample.name);
+                                                match clip.gain_label() {
+                                                    Some(gain) => ui.label(format!(
+                                                        "{} ({gain})",
+                                                        clip.name()
+                                                    )),
+                                                    None => ui.label(clip.name()),
+                                                };
                                             });
                                     },
                                 );
@@ -389,46 +388,168 @@ impl Track {
                                         new_rect.set_width(
                                             pixel_range.len().max(res.response.rect.width()),
                                         );
-                                        ui.allocate_rect(new_rect, egui::Sense::drag());
+                                        ui.allocate_rect(new_rect, egui::Sense::hover());
 
                                         if redraw {
-                                            sample.view_updated(ui, new_rect, self, index);
+                                            clip.sample.view_updated(ui, new_rect, self, index);
                                         }
 
-                                        sample.display(ui, new_rect, self, index);
-                                        offset += sample.len();
+                                        clip.sample.display(ui, new_rect, self, index);
 
                                         new_rect
                                     })
-                            });
+                            }).inner);
 
                             let rect = sample_response.inner.inner;
                             let response = sample_response.inner.response;
 
-                            // Display playback cursor
-                            let state = self.app_state.read().unwrap();
-                            if state.playing {
-                                if let Some(duration) = state.duration_played() {
-                                    if self.sample_at_time(&duration).is_some() {
-                                        if let Some(pixel) = self
-                                            .get_pixel_from_duration_sample(index, &duration, width)
+                            // Draw the fades, including the crossfades with overlapping clips
+                            let painter = ui.painter_at(clip_rect);
+                            let (fade_in, fade_out) = self.clip_fades(index);
+                            let body_top = clip_rect.top() + CLIP_HEADER_HEIGHT;
+                            let body_bottom = clip_rect.bottom() - 5.0;
+
+                            for (edge, fade) in [(FadeEdge::In, fade_in), (FadeEdge::Out, fade_out)]
+                            {
+                                if fade.length == 0 {
+                                    continue;
+                                }
+
+                                let points = (0..=FADE_CURVE_POINTS)
+                                    .map(|point| {
+                                        let position = point as f32 / FADE_CURVE_POINTS as f32;
+                                        let offset = (position * fade.length as f32) as u64;
+                                        let (time, gain) = match edge {
+                                            FadeEdge::In => {
+                                                (clip.start + offset, fade.curve.gain(position))
+                                            }
+                                            FadeEdge::Out => (
+                                                clip.end() - fade.length + offset,
+                                                fade.curve.gain(1.0 - position),
+                                            ),
+                                        };
+
+                                        Pos2::new(
+                                            pixel_at(time),
+                                            body_bottom - gain * (body_bottom - body_top),
+                                        )
+                                    })
+                                    .collect();
+
+                                painter.add(egui::Shape::line(
+                                    points,
+                                    egui::Stroke::new(1.0, egui::Color32::from_white_alpha(180)),
+                                ));
+                            }
+
+                            // Drag the handles in the header to change the fades, right click one to choose
+                            // its curve. They are checked before the trim handles to take the drag in the corners
+                            for (edge, fade) in
+                                [(FadeEdge::In, clip.fade_in), (FadeEdge::Out, clip.fade_out)]
+                            {
+                                let left = match edge {
+                                    FadeEdge::In => pixel_at(clip.start + fade.length),
+                                    FadeEdge::Out => {
+                                        pixel_at(clip.end() - fade.length) - FADE_HANDLE_SIZE
+                                    }
+                                };
+                                let handle_rect = egui::Rect::from_min_size(
+                                    Pos2::new(left, clip_rect.top() + 5.0),
+                                    egui::vec2(FADE_HANDLE_SIZE, FADE_HANDLE_SIZE),
+                                );
+                                if !timeline_rect.intersects(handle_rect) {
+                                    continue;
+                                }
+
+                                let handle_id = egui::Id::new((clip.id, edge));
+                                let handle = ui
+                                    .interact(handle_rect, handle_id, egui::Sense::click_and_drag())
+                                    .on_hover_cursor(egui::CursorIcon::ResizeHorizontal)
+                                    .on_hover_text(format!(
+                                        "{} {} ms",
+                                        fade.curve.name(),
+                                        fade.length / 1000
+                                    ));
+
+                                if handle.dragged() && handle.drag_delta().x != 0.0 {
+                                    if let Some(pos) = handle.interact_pointer_pos() {
+                                        let time =
+                                            self.time_at_pixel(pos.x - timeline_rect.left(), width);
+                                        let length = match edge {
+                                            FadeEdge::In => time.saturating_sub(clip.start),
+                                            FadeEdge::Out => clip.end().saturating_sub(time),
+                                        };
+
+                                        fade_edit = Some((
+                                            index,
+                                            edge,
+                                            Fade { length, ..fade },
+                                            Some(handle_id),
+                                        ));
+                                    }
+                                }
+                                fade_released |= handle.drag_released();
+
+                                let color = if handle.hovered() || handle.dragged() {
+                                    egui::Color32::WHITE
+                                } else {
+                                    egui::Color32::from_white_alpha(150)
+                                };
+                                ui.painter().rect_filled(handle_rect, 1.0, color);
+
+                                handle.context_menu(|ui| {
+                                    for curve in FadeCurve::ALL {
+                                        if ui
+                                            .selectable_label(fade.curve == curve, curve.name())
+                                            .clicked()
                                         {
-                                            ui.painter().line_segment(
-                                                [
-                                                    Pos2::new(
-                                                        pixel.round() + rect.left() + 0.5,
-                                                        rect.bottom(),
-                                                    ),
-                                                    Pos2::new(
-                                                        pixel.round() + rect.left() + 0.5,
-                                                        rect.top(),
-                                                    ),
-                                                ],
-                                                egui::Stroke::new(1.0, egui::Color32::GREEN),
-                                            );
+                                            fade_edit =
+                                                Some((index, edge, Fade { curve, ..fade }, None));
+                                            ui.close_menu();
                                         }
                                     }
+                                });
+                            }
+
+                            // Drag the edges of the clip to trim it. These are checked before the
+                            // timeline so they take the drag when the pointer is over them
+                            let edges = [
+                                (
+                                    TrimEdge::Start,
+                                    clip.start >= self.view_range.start,
+                                    clip_rect.left(),
+                                ),
+                                (
+                                    TrimEdge::End,
+                                    clip.end() <= self.view_range.end,
+                                    clip_rect.right() - TRIM_HANDLE_WIDTH,
+                                ),
+                            ];
+
+                            for (edge, visible, left) in edges {
+                                if !visible {
+                                    continue;
                                 }
+
+                                let handle_rect = egui::Rect::from_min_size(
+                                    Pos2::new(left, clip_rect.top()),
+                                    egui::vec2(TRIM_HANDLE_WIDTH, clip_rect.height()),
+                                );
+
+                                let handle_id = egui::Id::new((clip.id, edge));
+                                let handle = ui
+                                    .interact(handle_rect, handle_id, egui::Sense::drag())
+                                    .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+
+                                if handle.dragged() && handle.drag_delta().x != 0.0 {
+                                    if let Some(pos) = handle.interact_pointer_pos() {
+                                        let time =
+                                            self.time_at_pixel(pos.x - timeline_rect.left(), width);
+                                        trim = Some((index, edge, time, handle_id));
+                                    }
+                                }
+
+                                trim_released |= handle.drag_released();
                             }
 
                             // Display mouse cursor
@@ -442,14 +563,858 @@ impl Track {
                             }
                         }
                     });
-                })
+
+                    if let Some((index, edge, time, handle_id)) = trim {
+                        let (command, ()) = Swap::record(
+                            "Trim",
+                            self,
+                            |track| &mut track.clips,
+                            |track| match edge {
+                                TrimEdge::Start => track.trim_clip_start(index, time),
+                                TrimEdge::End => track.trim_clip_end(index, time),
+                            },
+                        );
+
+                        // The whole drag is undone in one step
+                        let mut state = self.app_state.write().unwrap();
+                        state.history.push(command.with_gesture(handle_id));
+                    }
+
+                    if trim_released {
+                        self.app_state.write().unwrap().history.end_gesture();
+                    }
+
+                    if let Some((index, edge, fade, gesture)) = fade_edit {
+                        let name = match edge {
+                            FadeEdge::In => "Fade In",
+                            FadeEdge::Out => "Fade Out",
+                        };
+                        let (mut command, ()) = Swap::record(
+                            name,
+                            self,
+                            |track| &mut track.clips,
+                            |track| track.set_fade(index, edge, fade),
+                        );
+
+                        if let Some(gesture) = gesture {
+                            command = command.with_gesture(gesture);
+                        }
+
+                        self.app_state.write().unwrap().history.push(command);
+                    }
+
+                    if fade_released {
+                        self.app_state.write().unwrap().history.end_gesture();
+                    }
+
+                    self.loop_ui(ui, timeline_rect);
+                    self.timeline_ui(ui, timeline_rect);
+                    timeline_rect
+                });
+
+                self.automation_ui(ui, row.inner);
             })
             .response;
 
+        self.windows_ui(ui.ctx(), tracks);
+
         self.frame_count += 1;
 
         res
     }
+
+    /// Draw the routing and insert editors of the track if they are open.
+    ///
+    /// `tracks` are the ids and names of every track, for picking sidechains
+    pub fn windows_ui(&mut self, ctx: &egui::Context, tracks: &[(Id, String)]) {
+        self.routing_ui(ctx);
+        self.inserts_ui(ctx, tracks);
+    }
+
+    /// Open the editor of the insert chain
+    pub fn open_inserts(&mut self) {
+        self.show_inserts = true;
+    }
+
+    /// Draw the name and level controls of the track.
+    ///
+    /// Changes are recorded in the history. Dragging a slider is undone in one step
+    fn header_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label(&self.name);
+
+        let mut levels = self.levels;
+
+        let volume = ui.add(
+            egui::Slider::new(&mut levels.volume, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                .text("Volume")
+                .suffix(" dB")
+                .custom_formatter(|volume, _| format_volume(volume)),
+        );
+
+        let pan = ui.add(
+            egui::Slider::new(&mut levels.pan, -1.0..=1.0)
+                .text("Pan")
+                .custom_formatter(|pan, _| format_pan(pan)),
+        );
+
+        egui::ComboBox::from_id_source((self.id, "pan-law"))
+            .selected_text(levels.pan_law.name())
+            .show_ui(ui, |ui| {
+                for law in PanLaw::ALL {
+                    ui.selectable_value(&mut levels.pan_law, law, law.name());
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.toggle_value(&mut levels.mute, "M").on_hover_text("Mute");
+            ui.toggle_value(&mut levels.solo, "S").on_hover_text("Solo");
+            ui.toggle_value(&mut self.armed, "R")
+                .on_hover_text("Arm for recording");
+            ui.toggle_value(&mut self.show_routing, "Routing");
+            ui.toggle_value(&mut self.show_inserts, "Inserts");
+            ui.toggle_value(&mut self.show_automation, "A")
+                .on_hover_text("Automation");
+        });
+
+        if levels.volume != self.levels.volume {
+            self.touch_automation(AutomationTarget::Volume);
+        }
+        if levels.pan != self.levels.pan {
+            self.touch_automation(AutomationTarget::Pan);
+        }
+
+        if levels != self.levels {
+            let (name, slider) = if levels.volume != self.levels.volume {
+                ("Volume", Some(&volume))
+            } else if levels.pan != self.levels.pan {
+                ("Pan", Some(&pan))
+            } else if levels.pan_law != self.levels.pan_law {
+                ("Pan Law", None)
+            } else if levels.mute != self.levels.mute {
+                ("Mute", None)
+            } else {
+                ("Solo", None)
+            };
+
+            let (mut command, ()) = Swap::record(
+                name,
+                self,
+                |track| &mut track.levels,
+                |track| track.levels = levels,
+            );
+
+            if let Some(slider) = slider.filter(|slider| slider.dragged()) {
+                command = command.with_gesture(slider.id);
+            }
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if volume.drag_released() || pan.drag_released() {
+            self.app_state.write().unwrap().history.end_gesture();
+        }
+    }
+
+    /// Draw the window for editing the gain from each input channel to each speaker, the output and the sends.
+    ///
+    /// Changes are recorded in the history. Dragging a gain or a send level is undone in one step
+    fn routing_ui(&mut self, ctx: &egui::Context) {
+        if !self.show_routing {
+            return;
+        }
+
+        // The sample with the most channels decides the inputs
+        let (inputs, input_layout) = self
+            .clips
+            .iter()
+            .map(|clip| (clip.sample.header.channel_count, clip.sample.layout))
+            .max_by_key(|(channels, _)| *channels)
+            .unwrap_or((1, Some(Layout::Mono)));
+        let outputs = match self.app_state.read().unwrap().output_channels {
+            0 => 2,
+            channels => channels,
+        };
+        let output_layout = Layout::from_channel_count(outputs);
+
+        let mut routing = self
+            .routing
+            .unwrap_or_else(|| GainMatrix::between(input_layout, inputs, output_layout, outputs));
+        // The name of the change, the new routing and the drag it's part of
+        let mut change = None;
+        let mut released = false;
+
+        let buses = self.app_state.read().unwrap().buses.read().unwrap().names();
+        let mut output = self.output;
+        let mut sends = self.sends.clone();
+        // The name of the change to the sends and the drag it's part of
+        let mut sends_change = None;
+
+        let id = self.id;
+        let mut open = true;
+
+        egui::Window::new(format!("Routing: {}", self.name))
+            .id(egui::Id::new((id, "routing")))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source((id, "routing-preset"))
+                        .selected_text("Presets")
+                        .show_ui(ui, |ui| {
+                            for preset in RoutingPreset::ALL {
+                                if ui.selectable_label(false, preset.name()).clicked() {
+                                    change = Some(("Routing Preset", Some(preset.matrix()), None));
+                                }
+                            }
+
+                            ui.separator();
+
+                            // Fold the input into the speakers of a layout
+                            for layout in Layout::ALL {
+                                if ui
+                                    .selectable_label(false, format!("To {}", layout.name()))
+                                    .clicked()
+                                {
+                                    let routing = GainMatrix::between(
+                                        input_layout,
+                                        inputs,
+                                        Some(layout),
+                                        layout.channel_count(),
+                                    );
+                                    change = Some(("Routing Preset", Some(routing), None));
+                                }
+                            }
+                        });
+
+                    if ui.button("Reset").clicked() {
+                        change = Some(("Reset Routing", None, None));
+                    }
+                });
+
+                egui::Grid::new((id, "routing-grid")).show(ui, |ui| {
+                    ui.label("");
+                    for output in 0..outputs.min(Speakers::MAX_COUNT as u16) {
+                        let speaker = Layout::channel_speaker(output_layout, output as usize);
+                        ui.label(speaker.map_or("?", |speaker| speaker.short_name()));
+                    }
+                    ui.end_row();
+
+                    for input in 0..inputs as usize {
+                        match Layout::channel_speaker(input_layout, input) {
+                            Some(speaker) => {
+                                ui.label(format!("In {} ({})", input + 1, speaker.short_name()))
+                            }
+                            None => ui.label(format!("In {}", input + 1)),
+                        };
+
+                        for output in 0..outputs as usize {
+                            let Some(gain) = routing.gain_mut(input, output) else {
+                                continue;
+                            };
+
+                            let response = ui.add(
+                                egui::DragValue::new(gain)
+                                    .clamp_range(0.0..=2.0)
+                                    .speed(0.01)
+                                    .fixed_decimals(2),
+                            );
+
+                            if response.changed() {
+                                let gesture = response.dragged().then_some(response.id);
+                                change = Some(("Routing", Some(routing), gesture));
+                            }
+                            released |= response.drag_released();
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+
+                let bus_name = |output: Output| match output {
+                    Output::Master => "Master",
+                    Output::Bus(bus) => buses
+                        .iter()
+                        .find(|(id, _)| *id == bus)
+                        .map_or("Missing", |(_, name)| name.as_str()),
+                };
+
+                egui::ComboBox::from_label("Output")
+                    .selected_text(bus_name(output))
+                    .show_ui(ui, |ui| {
+                        let buses = buses.iter().map(|(bus, _)| Output::Bus(*bus));
+
+                        for target in std::iter::once(Output::Master).chain(buses) {
+                            if ui
+                                .selectable_label(output == target, bus_name(target))
+                                .clicked()
+                            {
+                                output = target;
+                            }
+                        }
+                    });
+
+                ui.label("Sends");
+
+                // The send that was removed
+                let mut removed = None;
+
+                for (index, send) in sends.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        let level = ui.add(
+                            egui::Slider::new(&mut send.level, MIN_VOLUME_DB..=MAX_VOLUME_DB)
+                                .text(bus_name(Output::Bus(send.bus)))
+                                .suffix(" dB")
+                                .custom_formatter(|level, _| format_volume(level)),
+                        );
+
+                        if level.changed() {
+                            let gesture = level.dragged().then_some(level.id);
+                            sends_change = Some(("Send Level", gesture));
+                        }
+                        released |= level.drag_released();
+
+                        if ui
+                            .checkbox(&mut send.pre_fader, "Pre")
+                            .on_hover_text("Send before the volume and pan")
+                            .changed()
+                        {
+                            sends_change = Some(("Pre Fader Send", None));
+                        }
+
+                        if ui.button("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+
+                if let Some(index) = removed {
+                    sends.remove(index);
+                    sends_change = Some(("Remove Send", None));
+                }
+
+                egui::ComboBox::from_id_source((id, "add-send"))
+                    .selected_text("Add Send")
+                    .show_ui(ui, |ui| {
+                        for (bus, name) in &buses {
+                            if ui.selectable_label(false, name).clicked() {
+                                sends.push(AuxSend::new(*bus));
+                                sends_change = Some(("Add Send", None));
+                            }
+                        }
+                    });
+            });
+
+        if output != self.output {
+            let (command, ()) = Swap::record(
+                "Track Output",
+                self,
+                |track| &mut track.output,
+                |track| track.output = output,
+            );
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if let Some((name, gesture)) = sends_change {
+            let (mut command, ()) = Swap::record(
+                name,
+                self,
+                |track| &mut track.sends,
+                |track| track.sends = sends,
+            );
+
+            if let Some(gesture) = gesture {
+                command = command.with_gesture(gesture);
+            }
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if let Some((name, routing, gesture)) = change {
+            let (mut command, ()) = Swap::record(
+                name,
+                self,
+                |track| &mut track.routing,
+                |track| track.routing = routing,
+            );
+
+            if let Some(gesture) = gesture {
+                command = command.with_gesture(gesture);
+            }
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if released {
+            self.app_state.write().unwrap().history.end_gesture();
+        }
+
+        self.show_routing &= open;
+    }
+
+    /// Draw the editor of the insert chain.
+    ///
+    /// Adding, removing, moving and bypassing inserts and changing their parameters is recorded in the history.
+    /// Dragging a parameter is undone in one step
+    fn inserts_ui(&mut self, ctx: &egui::Context, tracks: &[(Id, String)]) {
+        if !self.show_inserts {
+            return;
+        }
+
+        let mut inserts = self.inserts.clone();
+        let mut edit = ChainEdit::default();
+
+        let id = self.id;
+        // A track can't be keyed from itself
+        let sources: Vec<_> = tracks
+            .iter()
+            .filter(|(track, _)| *track != id)
+            .cloned()
+            .collect();
+        let mut open = true;
+
+        egui::Window::new(format!("Inserts: {}", self.name))
+            .id(egui::Id::new((id, "inserts")))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                edit = insert_chain_ui(ui, ui.id(), &mut inserts, &sources);
+            });
+
+        for parameter in edit.parameters {
+            self.app_state.write().unwrap().history.push(parameter);
+        }
+
+        if let Some(name) = edit.change {
+            // New inserts are ready before the audio thread gets them
+            if let Some(format) = self.app_state.read().unwrap().insert_format() {
+                for insert in &inserts {
+                    insert.prepare(format);
+                }
+            }
+
+            let (command, ()) = Swap::record(
+                name,
+                self,
+                |track| &mut track.inserts,
+                |track| track.inserts = inserts,
+            );
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if ctx.input(|input| input.pointer.any_released()) {
+            self.app_state.write().unwrap().history.end_gesture();
+        }
+
+        self.show_inserts &= open;
+    }
+
+    /// Draw the automation lanes under the timeline and handle editing their points.
+    ///
+    /// Double clicking a lane adds a point. Points are dragged to move them, and right clicking one deletes it
+    /// or changes the shape of the segment after it. Changes are recorded in the history
+    fn automation_ui(&mut self, ui: &mut egui::Ui, timeline_rect: egui::Rect) {
+        if !self.show_automation {
+            return;
+        }
+
+        let targets = self.automation_targets();
+        let target_name = |target: AutomationTarget| {
+            targets
+                .iter()
+                .find(|(other, _)| *other == target)
+                .map_or("Missing", |(_, name)| name.as_str())
+        };
+
+        let width = timeline_rect.width();
+        let view_range = self.view_range.clone();
+        let pixel_at = |time: u64| {
+            timeline_rect.left()
+                + (time as f32 - view_range.start as f32) * width
+                    / (view_range.end - view_range.start) as f32
+        };
+        let playhead = pixel_at(self.app_state.read().unwrap().transport.time());
+
+        let mut lanes = self.automation.clone();
+        // The name of the change to the lanes and the drag's id
+        let mut change = None;
+        let mut released = false;
+        let mut removed = None;
+
+        for (lane_index, lane) in lanes.iter_mut().enumerate() {
+            let lane_id = egui::Id::new((self.id, "automation", lane_index));
+            let (row, _) = ui.allocate_exact_size(
+                egui::vec2(ui.available_width(), AUTOMATION_LANE_HEIGHT),
+                egui::Sense::hover(),
+            );
+            let header_rect = egui::Rect::from_x_y_ranges(
+                row.left() + 10.0..=timeline_rect.left() - 10.0,
+                row.y_range(),
+            );
+            let rect = egui::Rect::from_x_y_ranges(timeline_rect.x_range(), row.y_range());
+
+            ui.allocate_ui_at_rect(header_rect, |ui| {
+                ui.push_id(lane_id, |ui| {
+                    egui::ComboBox::from_id_source("target")
+                        .selected_text(target_name(lane.target))
+                        .show_ui(ui, |ui| {
+                            for (target, name) in &targets {
+                                if ui.selectable_label(lane.target == *target, name).clicked() {
+                                    lane.target = *target;
+                                    change = Some(("Automation Target", None));
+                                }
+                            }
+                        });
+
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_source("mode")
+                            .selected_text(lane.mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in AutomationMode::ALL {
+                                    if ui
+                                        .selectable_label(lane.mode == mode, mode.name())
+                                        .clicked()
+                                    {
+                                        lane.mode = mode;
+                                        change = Some(("Automation Mode", None));
+                                    }
+                                }
+                            });
+
+                        if ui.button("Remove").clicked() {
+                            removed = Some(lane_index);
+                        }
+
+                        if lane.writing() {
+                            ui.colored_label(egui::Color32::RED, "Writing");
+                        }
+                    });
+                });
+            });
+
+            let parameter = self.automation_parameter(lane.target);
+            let y_at = |value: f32| rect.bottom() - value * rect.height();
+            let value_at = |y: f32| ((rect.bottom() - y) / rect.height()).clamp(0.0, 1.0);
+
+            // Points are checked before the lane so they take the drag
+            let mut point_hovered = false;
+            let mut index = 0;
+            while let Some(point) = lane.points().get(index).copied() {
+                let center = Pos2::new(pixel_at(point.time), y_at(point.value));
+                let point_id = lane_id.with(index);
+                index += 1;
+
+                if !rect.x_range().contains(&center.x) {
+                    continue;
+                }
+
+                let mut response = ui
+                    .interact(
+                        egui::Rect::from_center_size(
+                            center,
+                            egui::Vec2::splat(AUTOMATION_POINT_SIZE),
+                        ),
+                        point_id,
+                        egui::Sense::click_and_drag(),
+                    )
+                    .on_hover_cursor(egui::CursorIcon::Grab);
+                point_hovered |= response.hovered();
+
+                if response.dragged() {
+                    if let Some(pos) = response.interact_pointer_pos() {
+                        let time = self.time_at_pixel(pos.x - rect.left(), width);
+                        lane.move_point(index - 1, time, value_at(pos.y));
+                        change = Some(("Move Automation Point", Some(point_id)));
+                    }
+                }
+                released |= response.drag_released();
+
+                if let Some(parameter) = &parameter {
+                    let value = parameter.denormalize(point.value);
+                    response = response.on_hover_text(format!("{value:.2} {}", parameter.unit));
+                }
+
+                response.context_menu(|ui| {
+                    if ui.button("Delete").clicked() {
+                        lane.remove_point(index - 1);
+                        change = Some(("Delete Automation Point", None));
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+                    ui.label("Segment");
+                    for curve in FadeCurve::ALL {
+                        if ui
+                            .selectable_label(point.curve == curve, curve.name())
+                            .clicked()
+                        {
+                            lane.set_curve(index - 1, curve);
+                            change = Some(("Automation Curve", None));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+
+            let background = ui.interact(rect, lane_id, egui::Sense::click());
+            if background.double_clicked() && !point_hovered {
+                if let Some(pos) = background.interact_pointer_pos() {
+                    lane.add_point(Breakpoint {
+                        time: self.time_at_pixel(pos.x - rect.left(), width),
+                        value: value_at(pos.y),
+                        curve: FadeCurve::Linear,
+                    });
+                    change = Some(("Add Automation Point", None));
+                }
+            }
+
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 0.0, egui::Color32::from_gray(35));
+
+            let color = if lane.playing() {
+                egui::Color32::from_rgb(230, 160, 40)
+            } else {
+                egui::Color32::GRAY
+            };
+
+            if lane.points().is_empty() {
+                // Without points the control keeps its own value
+                if let Some(value) = self.automation_control(lane.target) {
+                    painter.hline(
+                        rect.x_range(),
+                        y_at(value),
+                        egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+                    );
+                }
+            } else {
+                let line = (0..=(width / 2.0) as usize)
+                    .map(|step| {
+                        let x = rect.left() + step as f32 * 2.0;
+                        let time = self.time_at_pixel(x - rect.left(), width);
+                        Pos2::new(x, y_at(lane.value_at(time as f64).unwrap_or(0.0)))
+                    })
+                    .collect();
+                painter.add(egui::Shape::line(line, egui::Stroke::new(1.5, color)));
+
+                for point in lane.points() {
+                    let center = Pos2::new(pixel_at(point.time), y_at(point.value));
+                    painter.circle_filled(center, AUTOMATION_POINT_SIZE / 2.0, color);
+                }
+            }
+
+            painter.vline(
+                playhead,
+                rect.y_range(),
+                egui::Stroke::new(1.0, egui::Color32::RED),
+            );
+        }
+
+        if let Some(index) = removed {
+            lanes.remove(index);
+            change = Some(("Remove Automation Lane", None));
+        }
+
+        ui.horizontal(|ui| {
+            ui.add_space(10.0);
+
+            if ui.button("Add Lane").clicked() {
+                // Start with a control that doesn't have a lane yet
+                let target = targets
+                    .iter()
+                    .map(|(target, _)| *target)
+                    .find(|target| lanes.iter().all(|lane| lane.target != *target))
+                    .unwrap_or(AutomationTarget::Volume);
+
+                lanes.push(AutomationLane::new(target));
+                change = Some(("Add Automation Lane", None));
+            }
+        });
+
+        if let Some((name, gesture)) = change {
+            let (mut command, ()) = Swap::record(
+                name,
+                self,
+                |track| &mut track.automation,
+                |track| track.automation = lanes,
+            );
+
+            if let Some(gesture) = gesture {
+                command = command.with_gesture(gesture);
+            }
+
+            self.app_state.write().unwrap().history.push(command);
+        }
+
+        if released {
+            self.app_state.write().unwrap().history.end_gesture();
+        }
+    }
+
+    /// Draw the loop region and handle dragging its markers
+    fn loop_ui(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
+        let width = rect.width();
+        let mut state = self.app_state.write().unwrap();
+
+        let Some(region) = state.loop_region.clone() else {
+            return;
+        };
+
+        let transport = state.transport.clone();
+        let color = if state.looping {
+            egui::Color32::from_rgb(230, 160, 40)
+        } else {
+            egui::Color32::GRAY
+        };
+
+        let mut pixels = [None; 2];
+
+        for (marker, frame) in [
+            (LoopMarker::Start, region.start),
+            (LoopMarker::End, region.end),
+        ] {
+            let duration = Duration::from_micros(transport.time_at(frame));
+            let Some(pixel) = self.get_pixel_from_duration(&duration, width) else {
+                continue;
+            };
+
+            let x = rect.left() + pixel.round() + 0.5;
+            pixels[marker as usize] = Some(x);
+
+            let handle_rect = egui::Rect::from_min_size(
+                Pos2::new(x - LOOP_HANDLE_SIZE / 2.0, rect.top()),
+                egui::vec2(LOOP_HANDLE_SIZE, LOOP_HANDLE_SIZE),
+            );
+
+            let handle = ui
+                .interact(
+                    handle_rect,
+                    egui::Id::new((self.id, marker)),
+                    egui::Sense::drag(),
+                )
+                .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+
+            if handle.dragged() {
+                if let Some(pos) = handle.interact_pointer_pos() {
+                    let frame = transport.frames_at(self.time_at_pixel(pos.x - rect.left(), width));
+
+                    // Keep the region at least one frame long
+                    if let Some(region) = &mut state.loop_region {
+                        match marker {
+                            LoopMarker::Start => region.start = frame.min(region.end - 1),
+                            LoopMarker::End => region.end = frame.max(region.start + 1),
+                        }
+                    }
+                }
+            }
+
+            ui.painter().add(egui::Shape::convex_polygon(
+                vec![
+                    Pos2::new(x - LOOP_HANDLE_SIZE / 2.0, rect.top()),
+                    Pos2::new(x + LOOP_HANDLE_SIZE / 2.0, rect.top()),
+                    Pos2::new(x, rect.top() + LOOP_HANDLE_SIZE),
+                ],
+                color,
+                egui::Stroke::NONE,
+            ));
+
+            ui.painter().line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                egui::Stroke::new(1.0, color),
+            );
+        }
+
+        // Shade the top of the region, even if one of the markers is out of view
+        let left = pixels[0].unwrap_or(rect.left());
+        let right = pixels[1].unwrap_or(rect.right());
+        let in_view = transport.time_at(region.end) > self.view_range.start
+            && transport.time_at(region.start) < self.view_range.end;
+
+        if in_view && left < right {
+            ui.painter().rect_filled(
+                egui::Rect::from_x_y_ranges(left..=right, rect.top()..=rect.top() + 3.0),
+                0.0,
+                color.linear_multiply(0.5),
+            );
+        }
+    }
+
+    /// Handle selecting and seeking on the timeline and draw the selection and playhead
+    fn timeline_ui(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
+        let response = ui.interact(
+            rect,
+            egui::Id::new((self.id, "timeline")),
+            egui::Sense::click_and_drag(),
+        );
+        let shift = ui.input(|input| input.modifiers.shift);
+        let width = rect.width();
+
+        let mut state = self.app_state.write().unwrap();
+
+        if let Some(pos) = response.interact_pointer_pos() {
+            let time = self.time_at_pixel(pos.x - rect.left(), width);
+
+            if response.drag_started() {
+                state.focused_track = Some(self.id);
+
+                // Holding shift adds the track to the selection without changing the range
+                if !shift {
+                    state.selected_tracks.clear();
+                    state.selection = None;
+                    state.transport.seek_time(time);
+                    self.selection_anchor = Some(time);
+                }
+
+                state.selected_tracks.insert(self.id);
+            } else if response.dragged() {
+                if let Some(anchor) = self.selection_anchor {
+                    state.selection = Some(anchor.min(time)..anchor.max(time))
+                        .filter(|selection| !selection.is_empty());
+                }
+            }
+        }
+
+        if response.drag_released() {
+            self.selection_anchor = None;
+        }
+
+        // Display the selection
+        if let Some(selection) = &state.selection {
+            if state.selected_tracks.contains(&self.id)
+                && selection.end > self.view_range.start
+                && selection.start < self.view_range.end
+            {
+                let view_len = (self.view_range.end - self.view_range.start) as f32;
+                let to_pixel = |time: u64| {
+                    rect.left()
+                        + width
+                            * (time.clamp(self.view_range.start, self.view_range.end)
+                                - self.view_range.start) as f32
+                            / view_len
+                };
+
+                ui.painter().rect_filled(
+                    egui::Rect::from_x_y_ranges(
+                        to_pixel(selection.start)..=to_pixel(selection.end),
+                        rect.y_range(),
+                    ),
+                    0.0,
+                    egui::Color32::from_white_alpha(30),
+                );
+            }
+        }
+
+        // Display playhead
+        if let Some(pixel) = self.get_pixel_from_duration(&state.duration_played(), width) {
+            let x = rect.left() + pixel.round() + 0.5;
+
+            ui.painter().line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                egui::Stroke::new(1.0, egui::Color32::GREEN),
+            );
+        }
+    }
 }
 
 use std::any::Any;
@@ -0,0 +1,106 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The playhead shared between the UI and the audio callback.
+///
+/// Positions are frames on the timeline at the output sample rate.
+/// The sample rate is zero until an output stream has been started
+#[derive(Default)]
+pub struct Transport {
+    position: AtomicU64,
+    /// Where playback was last started from. Stopping returns here
+    play_start: AtomicU64,
+    sample_rate: AtomicU32,
+}
+
+impl Transport {
+    /// The frame that will be played next
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::Acquire)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Acquire)
+    }
+
+    /// Change the rate positions are counted in, keeping the playhead at the same time
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        let old_rate = self.sample_rate.swap(sample_rate, Ordering::AcqRel);
+        if old_rate == sample_rate || old_rate == 0 {
+            return;
+        }
+
+        let rescale = |frames: u64| (frames as f64 * sample_rate as f64 / old_rate as f64) as u64;
+
+        self.position
+            .store(rescale(self.position()), Ordering::Release);
+        self.play_start.store(
+            rescale(self.play_start.load(Ordering::Acquire)),
+            Ordering::Release,
+        );
+    }
+
+    /// Move the playhead to `frame`
+    pub fn seek(&self, frame: u64) {
+        self.position.store(frame, Ordering::Release);
+    }
+
+    /// Move the playhead to `time` (in microseconds)
+    pub fn seek_time(&self, time: u64) {
+        self.seek(self.frames_at(time));
+    }
+
+    /// Convert a time on the timeline (in microseconds) to a frame at the output sample rate
+    pub fn frames_at(&self, time: u64) -> u64 {
+        (time as f64 * self.sample_rate() as f64 / 1_000_000.0).round() as u64
+    }
+
+    /// Convert a frame at the output sample rate to a time on the timeline in microseconds
+    pub fn time_at(&self, frame: u64) -> u64 {
+        match self.sample_rate() {
+            0 => 0,
+            rate => (frame as f64 * 1_000_000.0 / rate as f64).round() as u64,
+        }
+    }
+
+    /// The time of the playhead on the timeline in microseconds
+    pub fn time(&self) -> u64 {
+        self.duration().as_micros() as u64
+    }
+
+    /// The time of the playhead on the timeline
+    pub fn duration(&self) -> Duration {
+        match self.sample_rate() {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(self.position() as f64 / rate as f64),
+        }
+    }
+
+    /// Remember the current position as the place to return to when stopping
+    pub fn mark_play_start(&self) {
+        self.play_start.store(self.position(), Ordering::Release);
+    }
+
+    /// Return to where playback was last started from, or to the beginning if already there
+    pub fn return_to_start(&self) {
+        let play_start = self.play_start.load(Ordering::Acquire);
+
+        if self.position() == play_start {
+            self.seek(0);
+            self.play_start.store(0, Ordering::Release);
+        } else {
+            self.seek(play_start);
+        }
+    }
+
+    /// Move the playhead from `from` to `to` after a buffer was played.
+    ///
+    /// Nothing happens if the playhead was moved somewhere else (seeked) while the buffer was being filled
+    pub fn advance(&self, from: u64, to: u64) {
+        let _ = self
+            .position
+            .compare_exchange(from, to, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
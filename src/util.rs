@@ -79,3 +79,21 @@ pub fn strip_samples(data: &[f32], channels: usize) -> Vec<Vec<f32>> {
         .map(|c| data.iter().copied().skip(c).step_by(channels).collect())
         .collect()
 }
+
+/// `frames` frames of a sine wave at `frequency` with the same signal on every channel
+#[cfg(test)]
+pub fn sine(
+    frequency: f32,
+    amplitude: f32,
+    sample_rate: u32,
+    frames: usize,
+    channels: usize,
+) -> Vec<f32> {
+    (0..frames)
+        .flat_map(|frame| {
+            let phase =
+                std::f64::consts::TAU * frequency as f64 * frame as f64 / sample_rate as f64;
+            std::iter::repeat_n(amplitude * phase.sin() as f32, channels)
+        })
+        .collect()
+}
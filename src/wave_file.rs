@@ -0,0 +1,391 @@
+use std::io::{self, Read, Seek, Write};
+
+use crate::channel::Layout;
+
+/// Format tag used by WAVE_FORMAT_EXTENSIBLE files. The real format is stored in the sub format GUID
+pub const WAV_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// The sub format GUID of extensible files, after the two bytes of the format tag
+const SUB_FORMAT_GUID: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// The sample format audio was stored in on disk.
+///
+/// Audio is always converted to normalized `f32` when it is loaded, this is
+/// kept around so the original depth can be respected when writing audio back out.
+/// Depths are ordered from the least to the most precise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BitDepth {
+    /// 8-bit unsigned integer PCM
+    Eight,
+    /// 16-bit signed integer PCM
+    Sixteen,
+    /// 24-bit signed integer PCM
+    TwentyFour,
+    /// 32-bit signed integer PCM
+    ThirtyTwo,
+    /// 32-bit IEEE float
+    ThirtyTwoFloat,
+    /// 64-bit IEEE float
+    SixtyFourFloat,
+}
+
+impl BitDepth {
+    /// Every supported bit depth
+    pub const ALL: [BitDepth; 6] = [
+        BitDepth::Eight,
+        BitDepth::Sixteen,
+        BitDepth::TwentyFour,
+        BitDepth::ThirtyTwo,
+        BitDepth::ThirtyTwoFloat,
+        BitDepth::SixtyFourFloat,
+    ];
+
+    /// A human readable name for the bit depth
+    pub fn name(&self) -> &'static str {
+        match self {
+            BitDepth::Eight => "8-bit",
+            BitDepth::Sixteen => "16-bit",
+            BitDepth::TwentyFour => "24-bit",
+            BitDepth::ThirtyTwo => "32-bit",
+            BitDepth::ThirtyTwoFloat => "32-bit float",
+            BitDepth::SixtyFourFloat => "64-bit float",
+        }
+    }
+
+    /// If the samples are stored as integers. Dither only applies to these
+    pub fn is_int(&self) -> bool {
+        !matches!(self, BitDepth::ThirtyTwoFloat | BitDepth::SixtyFourFloat)
+    }
+
+    /// Get the bit depth from a wave format tag and the bits per sample
+    pub fn from_format(audio_format: u16, bits_per_sample: u16) -> Option<BitDepth> {
+        match (audio_format, bits_per_sample) {
+            (wav::WAV_FORMAT_PCM, 8) => Some(BitDepth::Eight),
+            (wav::WAV_FORMAT_PCM, 16) => Some(BitDepth::Sixteen),
+            (wav::WAV_FORMAT_PCM, 24) => Some(BitDepth::TwentyFour),
+            (wav::WAV_FORMAT_PCM, 32) => Some(BitDepth::ThirtyTwo),
+            (wav::WAV_FORMAT_IEEE_FLOAT, 32) => Some(BitDepth::ThirtyTwoFloat),
+            (wav::WAV_FORMAT_IEEE_FLOAT, 64) => Some(BitDepth::SixtyFourFloat),
+            _ => None,
+        }
+    }
+
+    /// The number of bits each sample point takes up
+    pub fn bits(&self) -> u16 {
+        match self {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+            BitDepth::TwentyFour => 24,
+            BitDepth::ThirtyTwo | BitDepth::ThirtyTwoFloat => 32,
+            BitDepth::SixtyFourFloat => 64,
+        }
+    }
+
+    /// The wave format tag for this bit depth
+    pub fn audio_format(&self) -> u16 {
+        match self {
+            BitDepth::ThirtyTwoFloat | BitDepth::SixtyFourFloat => wav::WAV_FORMAT_IEEE_FLOAT,
+            _ => wav::WAV_FORMAT_PCM,
+        }
+    }
+
+    /// Decode a single little endian sample point into a normalized float
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            BitDepth::Eight => (bytes[0] as f32 - 128.0) / 128.0,
+            BitDepth::Sixteen => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            BitDepth::TwentyFour => {
+                // Shift into the top of an i32 and back down to sign extend
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.0
+            }
+            BitDepth::ThirtyTwo => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0
+            }
+            BitDepth::ThirtyTwoFloat => {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            BitDepth::SixtyFourFloat => f64::from_le_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+            ]) as f32,
+        }
+    }
+
+    /// Quantize a normalized float and append it as a little endian sample point
+    fn encode(&self, value: f32, dither: bool, bytes: &mut Vec<u8>) {
+        // Triangular dither in the range of -1..1 LSB
+        let noise = if dither {
+            rand::random::<f64>() - rand::random::<f64>()
+        } else {
+            0.0
+        };
+        let quantize = |scale: f64| {
+            (value.clamp(-1.0, 1.0) as f64 * scale + noise)
+                .round()
+                .clamp(-scale, scale - 1.0)
+        };
+
+        match self {
+            BitDepth::Eight => bytes.push((quantize(128.0) + 128.0) as u8),
+            BitDepth::Sixteen => bytes.extend_from_slice(&(quantize(32768.0) as i16).to_le_bytes()),
+            BitDepth::TwentyFour => {
+                bytes.extend_from_slice(&(quantize(8388608.0) as i32).to_le_bytes()[..3])
+            }
+            BitDepth::ThirtyTwo => {
+                bytes.extend_from_slice(&(quantize(2147483648.0) as i32).to_le_bytes())
+            }
+            BitDepth::ThirtyTwoFloat => bytes.extend_from_slice(&value.to_le_bytes()),
+            BitDepth::SixtyFourFloat => bytes.extend_from_slice(&(value as f64).to_le_bytes()),
+        }
+    }
+}
+
+/// A decoded wave file
+///
+/// `header` is the format chunk with `audio_format` resolved to PCM or IEEE float (even for extensible files)
+/// `bit_depth` is the format the samples were stored in
+/// `channel_mask` is the speaker mask from WAVE_FORMAT_EXTENSIBLE files
+/// `data` is the audio signal with channels packed after another, normalized to -1.0..1.0
+pub struct WaveFile {
+    pub header: wav::Header,
+    pub bit_depth: BitDepth,
+    pub channel_mask: Option<u32>,
+    pub data: Vec<f32>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Read a wave file and decode its samples into normalized floats
+///
+/// Supports 8-bit unsigned, 16/24/32-bit signed integer PCM and 32/64-bit float,
+/// both in plain and WAVE_FORMAT_EXTENSIBLE files.
+pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<WaveFile> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid_data("File is not a RIFF WAVE file"));
+    }
+
+    let mut format = None;
+    let mut data = None;
+
+    // Walk the chunks. Chunks are padded to an even number of bytes
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32_at(&bytes, offset + 4) as usize;
+        let start = offset + 8;
+        let end = (start + size).min(bytes.len());
+
+        match id {
+            b"fmt " => format = Some(&bytes[start..end]),
+            b"data" => data = Some(&bytes[start..end]),
+            _ => (),
+        }
+
+        offset = start + size + (size & 1);
+    }
+
+    let format = format.ok_or_else(|| invalid_data("Wave file is missing the \"fmt \" chunk"))?;
+    let data = data.ok_or_else(|| invalid_data("Wave file is missing the \"data\" chunk"))?;
+
+    if format.len() < 16 {
+        return Err(invalid_data("Wave format chunk is too small"));
+    }
+
+    let mut header = wav::Header {
+        audio_format: u16_at(format, 0),
+        channel_count: u16_at(format, 2),
+        sampling_rate: u32_at(format, 4),
+        bytes_per_second: u32_at(format, 8),
+        bytes_per_sample: u16_at(format, 12),
+        bits_per_sample: u16_at(format, 14),
+    };
+
+    // Extensible files store the actual format in the first two bytes of the sub format GUID
+    let mut channel_mask = None;
+    if header.audio_format == WAV_FORMAT_EXTENSIBLE {
+        if format.len() < 40 {
+            return Err(invalid_data("Extensible wave format chunk is too small"));
+        }
+
+        channel_mask = Some(u32_at(format, 20));
+        header.audio_format = u16_at(format, 24);
+    }
+
+    let bit_depth =
+        BitDepth::from_format(header.audio_format, header.bits_per_sample).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Unsupported wave format {:#x} with {} bits per sample",
+                    header.audio_format, header.bits_per_sample
+                ),
+            )
+        })?;
+
+    if header.channel_count == 0 {
+        return Err(invalid_data("Wave file has no channels"));
+    }
+
+    let mut data = data
+        .chunks_exact(bit_depth.bits() as usize / 8)
+        .map(|point| bit_depth.decode(point))
+        .collect::<Vec<_>>();
+
+    // Drop any trailing partial frame
+    let frames = data.len() / header.channel_count as usize;
+    data.truncate(frames * header.channel_count as usize);
+
+    Ok(WaveFile {
+        header,
+        bit_depth,
+        channel_mask,
+        data,
+    })
+}
+
+/// Encode normalized float audio into a wave file
+///
+/// `data` is the audio signal with channels packed after another
+/// `layout` is the speakers of the channels, stored as the channel mask of extensible files
+/// `bit_depth` is the format to store the samples in. Floats are clipped to -1.0..1.0 for integer formats
+/// `dither` if `true`, triangular (TPDF) dither of +-1 LSB is applied before quantizing to integer formats
+///
+/// Files with more than 2 channels or 16 bits are written as WAVE_FORMAT_EXTENSIBLE
+pub fn write<W: Write>(
+    writer: &mut W,
+    sample_rate: u32,
+    channel_count: u16,
+    layout: Option<Layout>,
+    bit_depth: BitDepth,
+    data: &[f32],
+    dither: bool,
+) -> io::Result<()> {
+    let header = wav::Header::new(
+        bit_depth.audio_format(),
+        channel_count,
+        sample_rate,
+        bit_depth.bits(),
+    );
+
+    let extensible = channel_count > 2 || bit_depth.bits() > 16;
+    let format_len = if extensible { 40 } else { 16 };
+
+    let data_len = data.len() * (bit_depth.bits() as usize / 8);
+    let mut bytes = Vec::with_capacity(28 + format_len + data_len + 1);
+
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&((20 + format_len + data_len + (data_len & 1)) as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&(format_len as u32).to_le_bytes());
+    if extensible {
+        // Channels that don't match the layout aren't assigned to speakers
+        let channel_mask = layout
+            .filter(|layout| layout.channel_count() == channel_count)
+            .map_or(0, |layout| layout.channel_mask());
+
+        bytes.extend_from_slice(&<[u8; 16]>::from(wav::Header {
+            audio_format: WAV_FORMAT_EXTENSIBLE,
+            ..header
+        }));
+        // The size of the extension, the valid bits, the channel mask and the sub format
+        bytes.extend_from_slice(&22u16.to_le_bytes());
+        bytes.extend_from_slice(&bit_depth.bits().to_le_bytes());
+        bytes.extend_from_slice(&channel_mask.to_le_bytes());
+        bytes.extend_from_slice(&bit_depth.audio_format().to_le_bytes());
+        bytes.extend_from_slice(&SUB_FORMAT_GUID);
+    } else {
+        bytes.extend_from_slice(&<[u8; 16]>::from(header));
+    }
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(data_len as u32).to_le_bytes());
+
+    for point in data {
+        bit_depth.encode(*point, dither, &mut bytes);
+    }
+
+    if data_len & 1 == 1 {
+        bytes.push(0);
+    }
+
+    writer.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Write `data` and read it back
+    fn round_trip(
+        channels: u16,
+        layout: Option<Layout>,
+        bit_depth: BitDepth,
+        data: &[f32],
+    ) -> (Vec<u8>, WaveFile) {
+        let mut bytes = Vec::new();
+        write(&mut bytes, 48000, channels, layout, bit_depth, data, false).unwrap();
+        let file = read(&mut Cursor::new(&bytes)).unwrap();
+
+        (bytes, file)
+    }
+
+    #[test]
+    fn write_extensible() {
+        let data: Vec<f32> = (0..600).map(|i| (i as f32 / 600.0) - 0.5).collect();
+
+        for bit_depth in BitDepth::ALL {
+            for layout in [
+                Layout::Mono,
+                Layout::Stereo,
+                Layout::Surround51,
+                Layout::Surround714,
+            ] {
+                let channels = layout.channel_count();
+                let (bytes, file) = round_trip(channels, Some(layout), bit_depth, &data);
+
+                let extensible = channels > 2 || bit_depth.bits() > 16;
+                assert_eq!(u16_at(&bytes, 20) == WAV_FORMAT_EXTENSIBLE, extensible);
+                assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+
+                assert_eq!(file.bit_depth, bit_depth);
+                assert_eq!(file.header.audio_format, bit_depth.audio_format());
+                assert_eq!(file.header.channel_count, channels);
+                assert_eq!(
+                    file.channel_mask.and_then(Layout::from_channel_mask),
+                    extensible.then_some(layout)
+                );
+
+                let lsb = 2.0 / (1u64 << bit_depth.bits().min(32)) as f32;
+                for (read, written) in file.data.iter().zip(&data) {
+                    assert!((read - written).abs() <= lsb, "{read} != {written}");
+                }
+            }
+        }
+
+        // Channels without a layout aren't given speakers
+        let (_, file) = round_trip(5, None, BitDepth::Sixteen, &data);
+        assert_eq!(file.channel_mask, Some(0));
+    }
+}